@@ -1,7 +1,10 @@
+use crate::difficulty::{self, DEFAULT_POW_LIMIT_BITS, U256};
+use crate::encoding;
 use crate::error::BlocktreeError;
 use crate::transaction::Transaction;
+use crate::wallet;
 use chrono::Utc;
-use rs_merkle::{Hasher, MerkleTree};
+use rs_merkle::{Hasher, MerkleProof, MerkleTree};
 use serde::{Deserialize, Serialize};
 use sha3::{Digest, Sha3_256};
 
@@ -19,16 +22,50 @@ impl Hasher for Sha3_256Hasher {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct Block {
+/// Block format version written by this node.
+pub const BLOCK_VERSION: u32 = 1;
+
+/// The hashed part of a block. Transactions are committed through
+/// `merkle_root`, so headers can be mined and synced without their bodies.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BlockHeader {
+    pub version: u32,
     pub index: u64,
     pub timestamp: i64,
-    pub transactions: Vec<Transaction>,
     pub previous_hash: String,
     pub branch_id: String,
-    pub nonce: u64,
     pub merkle_root: String,
-    pub hash: String,
+    pub bits: u32, // Compact PoW target
+    pub nonce: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Block {
+    pub header: BlockHeader,
+    pub transactions: Vec<Transaction>,
+    pub hash: String, // Hash of `header`
+}
+
+/// Merkle proof that a transaction id is a leaf of a block's Merkle root.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TransactionProof {
+    pub leaf_index: usize,
+    pub leaf_count: usize,
+    pub hashes: Vec<String>, // Hex-encoded sibling hashes, leaf to root
+}
+
+impl BlockHeader {
+    /// Hashes the canonical encoding of the header.
+    pub fn calculate_hash(&self) -> Result<String, BlocktreeError> {
+        let mut hasher = Sha3_256::new();
+        hasher.update(encoding::to_bytes(self));
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    /// Expected hashes spent to meet this header's target.
+    pub fn work(&self) -> Result<U256, BlocktreeError> {
+        difficulty::bits_to_work(self.bits)
+    }
 }
 
 impl Block {
@@ -39,15 +76,29 @@ impl Block {
         branch_id: String,
     ) -> Result<Self, BlocktreeError> {
         let timestamp = Utc::now().timestamp_millis();
+        Self::with_timestamp(index, transactions, previous_hash, branch_id, timestamp)
+    }
+
+    pub fn with_timestamp(
+        index: u64,
+        transactions: Vec<Transaction>,
+        previous_hash: String,
+        branch_id: String,
+        timestamp: i64,
+    ) -> Result<Self, BlocktreeError> {
         let merkle_root = Self::calculate_merkle_root(&transactions)?;
         let mut block = Block {
-            index,
-            timestamp,
+            header: BlockHeader {
+                version: BLOCK_VERSION,
+                index,
+                timestamp,
+                previous_hash,
+                branch_id,
+                merkle_root,
+                bits: DEFAULT_POW_LIMIT_BITS,
+                nonce: 0,
+            },
             transactions,
-            previous_hash,
-            branch_id,
-            nonce: 0,
-            merkle_root,
             hash: String::new(),
         };
         block.hash = block.calculate_hash()?;
@@ -58,28 +109,75 @@ impl Block {
         if transactions.is_empty() {
             return Ok("0".to_string());
         }
+        let merkle_tree = Self::merkle_tree(transactions);
+        Ok(hex::encode(merkle_tree.root().unwrap_or([0; 32])))
+    }
+
+    // Leaves are the SHA3 of each tx id
+    fn merkle_tree(transactions: &[Transaction]) -> MerkleTree<Sha3_256Hasher> {
         let leaves: Vec<[u8; 32]> = transactions
             .iter()
-            .map(|tx| {
-                let mut hasher = Sha3_256::new();
-                hasher.update(&tx.tx_id);
-                hasher.finalize().into()
-            })
+            .map(|tx| Sha3_256Hasher::hash(tx.tx_id.as_bytes()))
             .collect();
-        let merkle_tree = MerkleTree::<Sha3_256Hasher>::from_leaves(&leaves);
-        Ok(hex::encode(merkle_tree.root().unwrap_or([0; 32])))
+        MerkleTree::from_leaves(&leaves)
     }
 
+    /// Proves that the transaction `tx_id` is part of this block, for
+    /// checking with `verify_transaction_proof` against the Merkle root alone.
+    pub fn transaction_proof(&self, tx_id: &str) -> Result<TransactionProof, BlocktreeError> {
+        let leaf_index = self
+            .transactions
+            .iter()
+            .position(|tx| tx.tx_id == tx_id)
+            .ok_or_else(|| {
+                BlocktreeError::TransactionError(format!(
+                    "Transaction {} is not in block {}",
+                    tx_id, self.hash
+                ))
+            })?;
+        Ok(TransactionProof {
+            leaf_index,
+            leaf_count: self.transactions.len(),
+            hashes: Self::merkle_tree(&self.transactions)
+                .proof(&[leaf_index])
+                .proof_hashes_hex(),
+        })
+    }
+
+    /// Hashes the header only; the body is covered by its Merkle root.
     pub fn calculate_hash(&self) -> Result<String, BlocktreeError> {
-        let block_json = serde_json::to_string(self)
-            .map_err(|e| BlocktreeError::SerializationError(e.to_string()))?;
-        let mut hasher = Sha3_256::new();
-        hasher.update(block_json);
-        Ok(format!("{:x}", hasher.finalize()))
+        self.header.calculate_hash()
     }
 
     pub fn update_hash(&mut self) -> Result<(), BlocktreeError> {
         self.hash = self.calculate_hash()?;
         Ok(())
     }
+
+    /// Expected hashes spent to meet this block's target.
+    pub fn work(&self) -> Result<U256, BlocktreeError> {
+        self.header.work()
+    }
+}
+
+/// True if `proof` links `tx_id` to the hex-encoded `merkle_root`.
+pub fn verify_transaction_proof(merkle_root: &str, tx_id: &str, proof: &TransactionProof) -> bool {
+    let root: [u8; 32] = match wallet::decode_fixed(merkle_root, "merkle root") {
+        Ok(root) => root,
+        Err(_) => return false,
+    };
+    let hashes: Result<Vec<[u8; 32]>, _> = proof
+        .hashes
+        .iter()
+        .map(|hash| wallet::decode_fixed(hash, "proof hash"))
+        .collect();
+    match hashes {
+        Ok(hashes) => MerkleProof::<Sha3_256Hasher>::new(hashes).verify(
+            root,
+            &[proof.leaf_index],
+            &[Sha3_256Hasher::hash(tx_id.as_bytes())],
+            proof.leaf_count,
+        ),
+        Err(_) => false,
+    }
 }
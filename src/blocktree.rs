@@ -1,50 +1,101 @@
-use crate::block::Block;
-use crate::clustering::SpectralClustering;
+use crate::block::{Block, BlockHeader};
+use crate::clock::{Clock, SystemClock};
+use crate::clustering::{Clustering, SpectralClustering};
 use crate::coin::Coin;
+use crate::config::BlocktreeConfig;
 use crate::consensus::{Consensus, ProofOfWork};
+use crate::crossbranch;
+use crate::difficulty::{self, U256};
 use crate::error::BlocktreeError;
-use crate::network::Network;
+use crate::forkchoice::{BlockDag, BlockStatus};
+use crate::gossip::{self, TxGossip, MAX_INVENTORY};
+use crate::ledger::Ledger;
+use crate::mempool::{Mempool, FEE_ESTIMATE_BLOCKS};
+use crate::miner::{CancelToken, MinedBlock, Miner, MiningStats};
+use crate::network::{BranchSummary, Message, Network, NetworkEvent, PeerId, LOCAL_NODE};
 use crate::storage::{InMemoryStorage, Storage};
+use crate::sync::{self, SyncState, MAX_BLOCKS_IN_FLIGHT};
 use crate::transaction::Transaction;
 use crate::tree::{BlocktreeCore, Tree};
+use crate::validation::{self, ValidationReport};
+use std::collections::HashMap;
+use tokio::sync::mpsc;
 
-pub struct Blocktree {
+pub struct Blocktree<
+    S: Storage = InMemoryStorage,
+    C: Consensus = ProofOfWork,
+    K: Clustering = SpectralClustering,
+> {
+    config: BlocktreeConfig,
     tree: BlocktreeCore,
-    consensus: ProofOfWork,
-    clustering: SpectralClustering,
+    consensus: C,
+    clustering: K,
     coin: Coin,
-    storage: InMemoryStorage,
+    pub(crate) storage: S,
+    ledgers: HashMap<String, Ledger>,
+    mempools: HashMap<String, Mempool>,
+    dags: HashMap<String, BlockDag>, // Every known block per branch, for fork choice
+    mining: HashMap<String, CancelToken>, // Cancelled when the branch's tip moves
+    mining_stats: Option<MiningStats>,
+    sync: SyncState,
+    gossip: TxGossip,
     network: Box<dyn Network>,
+    clock: Box<dyn Clock>,
 }
 
 impl Blocktree {
     pub fn new(network: Box<dyn Network>) -> Self {
-        let mut storage = InMemoryStorage::new();
-        let genesis = Block::new(
-            0,
-            vec![Transaction::new("genesis".to_string(), "genesis".to_string(), 0).unwrap()],
-            "0".to_string(),
-            "root".to_string(),
-        )
-        .unwrap();
-        storage
-            .save_block(genesis, "root")
-            .expect("Failed to save genesis block");
-        Blocktree {
-            tree: BlocktreeCore::new(),
-            consensus: ProofOfWork::new(2, 0.2),
-            clustering: SpectralClustering::new(10),
-            coin: Coin::new(),
-            storage,
-            network,
-        }
+        Self::builder(network)
+            .build()
+            .expect("Failed to initialize blocktree")
+    }
+
+    pub fn builder(network: Box<dyn Network>) -> BlocktreeBuilder {
+        BlocktreeBuilder::new(network)
+    }
+
+    pub fn from_config(
+        config: BlocktreeConfig,
+        network: Box<dyn Network>,
+    ) -> Result<Self, BlocktreeError> {
+        BlocktreeBuilder::from_config(config, network).build()
     }
+}
 
+impl<S: Storage, C: Consensus, K: Clustering> Blocktree<S, C, K> {
+    /// Mines a block of `transactions` on top of `branch_id` and appends it.
     pub fn add_block(
         &mut self,
         transactions: Vec<Transaction>,
         branch_id: &str,
     ) -> Result<(), BlocktreeError> {
+        let (block, cancel) = self.prepare_block(transactions, branch_id)?;
+        let mined = self.consensus.mine_block(block, &cancel)?;
+        self.commit_mined_block(mined, branch_id)?;
+        Ok(())
+    }
+
+    /// Prepares the next block of `branch_id` from the highest-priority
+    /// pending transactions of its mempool, as `prepare_block` does.
+    pub fn prepare_pending(
+        &mut self,
+        branch_id: &str,
+    ) -> Result<(Block, CancelToken), BlocktreeError> {
+        let transactions = self
+            .mempool(branch_id)?
+            .block_template(self.ledger(branch_id)?, self.config.max_block_transactions);
+        self.prepare_block(transactions, branch_id)
+    }
+
+    /// Builds the unmined next block of `branch_id`: its coinbase, the given
+    /// transactions and the target to meet. The returned token is cancelled
+    /// as soon as any block extends the branch, so mining on the stale tip
+    /// can stop.
+    pub fn prepare_block(
+        &mut self,
+        transactions: Vec<Transaction>,
+        branch_id: &str,
+    ) -> Result<(Block, CancelToken), BlocktreeError> {
         let branch = self
             .storage
             .get_branch(branch_id)
@@ -52,26 +103,848 @@ impl Blocktree {
         let last_block = branch
             .last()
             .ok_or_else(|| BlocktreeError::BranchNotFound("Empty branch".to_string()))?;
-        let new_block = Block::new(
-            last_block.index + 1,
-            transactions,
+        let index = last_block.header.index + 1;
+        let timestamp = self.clock.now_ms();
+        let fees = transactions
+            .iter()
+            .try_fold(0u64, |total, tx| total.checked_add(tx.fee))
+            .ok_or_else(|| BlocktreeError::TransactionError("Fees overflow".to_string()))?;
+        let coinbase = Transaction::coinbase(
+            self.config.miner_address.clone(),
+            self.coin.reward_at(index).saturating_add(fees),
+            index,
+            branch_id,
+            timestamp,
+        )?;
+        let mut block = Block::with_timestamp(
+            index,
+            std::iter::once(coinbase).chain(transactions).collect(),
             last_block.hash.clone(),
             branch_id.to_string(),
+            timestamp,
+        )?;
+        block.header.bits = self.consensus.adjust_difficulty(branch);
+        // Reject overspends and replays before spending work on the block
+        self.ledger(branch_id)?.clone().apply_block(&block)?;
+        let cancel = self
+            .mining
+            .entry(branch_id.to_string())
+            .or_default()
+            .clone();
+        Ok((block, cancel))
+    }
+
+    /// Processes a locally mined block like any other block of `branch_id`,
+    /// broadcasting it if it became part of the active chain.
+    pub fn commit_block(
+        &mut self,
+        block: Block,
+        branch_id: &str,
+    ) -> Result<BlockStatus, BlocktreeError> {
+        let status = self.process_block(block.clone(), branch_id)?;
+        if matches!(
+            status,
+            BlockStatus::Extended | BlockStatus::Reorganized { .. }
+        ) {
+            self.network.broadcast_block(block)?;
+        }
+        Ok(status)
+    }
+
+    /// Commits a block mined locally, recording the work it took.
+    pub fn commit_mined_block(
+        &mut self,
+        mined: MinedBlock,
+        branch_id: &str,
+    ) -> Result<BlockStatus, BlocktreeError> {
+        self.mining_stats = Some(mined.stats);
+        self.commit_block(mined.block, branch_id)
+    }
+
+    /// Accepts a block of `branch_id` from any source. A block on the active
+    /// tip extends the chain; one on a competing chain is kept and, once that
+    /// chain has more cumulative work, made active by a reorganization. A
+    /// block whose parent is unknown waits in the orphan buffer until the
+    /// parent is processed.
+    pub fn process_block(
+        &mut self,
+        block: Block,
+        branch_id: &str,
+    ) -> Result<BlockStatus, BlocktreeError> {
+        let hash = block.hash.clone();
+        let status = self.connect_block(block, branch_id)?;
+        if matches!(status, BlockStatus::Orphan | BlockStatus::Duplicate) {
+            return Ok(status);
+        }
+        let mut parents = vec![hash];
+        while let Some(parent) = parents.pop() {
+            let orphans = self.dag_mut(branch_id)?.take_orphans(&parent);
+            for orphan in orphans {
+                let orphan_hash = orphan.hash.clone();
+                match self.connect_block(orphan, branch_id) {
+                    Ok(BlockStatus::Orphan | BlockStatus::Duplicate) => {}
+                    Ok(_) => parents.push(orphan_hash),
+                    Err(e) => println!("Dropping orphan {}: {}", orphan_hash, e),
+                }
+            }
+        }
+        Ok(status)
+    }
+
+    // Processes one block, leaving the orphans waiting on it to the caller
+    fn connect_block(
+        &mut self,
+        block: Block,
+        branch_id: &str,
+    ) -> Result<BlockStatus, BlocktreeError> {
+        let dag = self.dag(branch_id)?;
+        if dag.contains(&block.hash) || dag.is_orphan(&block.hash) {
+            return Ok(BlockStatus::Duplicate);
+        }
+        validation::validate_standalone(&block, branch_id, self.consensus.pow_limit())?;
+        if !dag.contains(&block.header.previous_hash) {
+            // Its retargeting can not be checked yet, so it must be at least as
+            // hard as the branch's next block
+            let chain = self
+                .storage
+                .get_branch(branch_id)
+                .ok_or_else(|| BlocktreeError::BranchNotFound(branch_id.to_string()))?;
+            let floor = difficulty::compact_to_target(self.consensus.adjust_difficulty(chain))?;
+            validation::check_target(&block.header, &block.hash, floor)?;
+            self.dag_mut(branch_id)?.add_orphan(block)?;
+            return Ok(BlockStatus::Orphan);
+        }
+        let tip = self.tip(branch_id)?.hash.clone();
+        if block.header.previous_hash == tip {
+            self.extend_chain(block, branch_id)?;
+            return Ok(BlockStatus::Extended);
+        }
+        let ancestry = dag.chain_to(&block.header.previous_hash);
+        validation::validate_block(
+            &block,
+            branch_id,
+            &ancestry,
+            &self.consensus,
+            &self.coin,
+            self.clock.now_ms(),
         )?;
-        let mined_block = self.consensus.mine_block(new_block)?;
+        let hash = block.hash.clone();
+        let dag = self.dag_mut(branch_id)?;
+        let work = dag.insert(block)?;
+        if Some(work) > dag.chain_work(&tip) {
+            self.reorganize(branch_id, &hash)
+        } else {
+            Ok(BlockStatus::SideChain)
+        }
+    }
+
+    // Appends a block on top of the active tip of `branch_id`
+    fn extend_chain(&mut self, block: Block, branch_id: &str) -> Result<(), BlocktreeError> {
+        self.validate_block(&block, branch_id)?;
+        let mut ledger = self.ledger(branch_id)?.clone();
+        ledger.apply_block(&block)?;
+        self.dag_mut(branch_id)?.insert(block.clone())?;
+        let index = block.header.index;
         self.tree
-            .add_block(mined_block.clone(), branch_id, &mut self.storage)?;
-        self.network.broadcast_block(mined_block)?;
-        self.coin.mine_reward();
-        if self.storage.get_branch(branch_id).unwrap().len() >= self.tree.get_split_interval() {
+            .add_block(block.clone(), branch_id, &mut self.storage)?;
+        self.cancel_mining(branch_id);
+        if let Some(mempool) = self.mempools.get_mut(branch_id) {
+            mempool.remove_included(&block, &ledger);
+        }
+        for tx in &block.transactions {
+            self.gossip.insert(&tx.tx_id);
+        }
+        self.ledgers.insert(branch_id.to_string(), ledger);
+        self.coin.mine_reward(index);
+        self.split_if_due(branch_id)
+    }
+
+    /// Switches `branch_id` to the chain ending at `new_tip`, which has more
+    /// work than the active one. The new blocks are applied to the ledger
+    /// replayed up to the fork point before anything is changed, and one that
+    /// fails is dropped from the DAG along with its descendants. Blocks up to
+    /// a split point are final, since the child branches build on them, and
+    /// so are blocks buried `claim_depth` deep, since locks in them may have
+    /// been claimed.
+    fn reorganize(
+        &mut self,
+        branch_id: &str,
+        new_tip: &str,
+    ) -> Result<BlockStatus, BlocktreeError> {
+        let active = self
+            .storage
+            .get_branch(branch_id)
+            .ok_or_else(|| BlocktreeError::BranchNotFound(branch_id.to_string()))?
+            .clone();
+        let (fork, connected) = self.dag(branch_id)?.fork_from(&active, new_tip)?;
+        if fork < self.final_position(branch_id, &active) {
+            return Ok(BlockStatus::SideChain);
+        }
+        let mut ledger = Ledger::branch_base(&self.storage, branch_id)?;
+        for block in &active[1..=fork] {
+            ledger.apply_block(block)?;
+        }
+        for block in &connected {
+            let result = validation::validate_claims(
+                block,
+                branch_id,
+                &self.storage,
+                self.config.claim_depth,
+            )
+            .and_then(|()| ledger.apply_block(block));
+            if let Err(e) = result {
+                self.dag_mut(branch_id)?
+                    .remove_with_descendants(&block.hash);
+                return Err(e);
+            }
+        }
+
+        let disconnected = self.storage.rewind_branch(branch_id, fork + 1)?;
+        for block in &disconnected {
+            self.coin.revert_reward(block.header.index);
+        }
+        for block in &connected {
             self.tree
-                .split_branch(branch_id, &self.clustering, &mut self.storage)?;
+                .add_block(block.clone(), branch_id, &mut self.storage)?;
+            self.coin.mine_reward(block.header.index);
+        }
+        self.cancel_mining(branch_id);
+        if let Some(mempool) = self.mempools.get_mut(branch_id) {
+            for block in &connected {
+                mempool.remove_included(block, &ledger);
+            }
+        }
+        for tx in connected.iter().flat_map(|block| &block.transactions) {
+            self.gossip.insert(&tx.tx_id);
+        }
+        self.ledgers.insert(branch_id.to_string(), ledger);
+        // Transactions only the old chain included are pending again, unless
+        // the new chain spent their nonce or claimed their lock
+        for tx in disconnected.iter().flat_map(|block| &block.transactions) {
+            if !tx.is_coinbase() {
+                let _ = self.insert_transaction(tx.clone(), branch_id);
+            }
+        }
+        println!(
+            "Branch {} reorganized to {}: {} blocks rolled back, {} applied",
+            branch_id,
+            new_tip,
+            disconnected.len(),
+            connected.len()
+        );
+        self.split_if_due(branch_id)?;
+        Ok(BlockStatus::Reorganized {
+            disconnected: disconnected.len(),
+            connected: connected.len(),
+        })
+    }
+
+    // Position in the active chain of `branch_id` of its last block that can
+    // not be rolled back: the one `claim_depth` below the tip, or the one
+    // copied into its children once it has split if that is later
+    fn final_position(&self, branch_id: &str, active: &[Block]) -> usize {
+        let split = self
+            .storage
+            .get_branch(&format!("{}.1", branch_id))
+            .and_then(|child| child.first())
+            .and_then(|base| active.iter().position(|block| block.hash == base.hash))
+            .unwrap_or(0);
+        let buried = active.len().saturating_sub(1 + self.config.claim_depth);
+        split.max(buried)
+    }
+
+    // A branch splits once; later blocks extend it without re-splitting
+    fn split_if_due(&mut self, branch_id: &str) -> Result<(), BlocktreeError> {
+        let already_split = self
+            .storage
+            .get_branch(&format!("{}.1", branch_id))
+            .is_some();
+        let len = self.storage.get_branch(branch_id).map_or(0, |b| b.len());
+        if already_split || len < self.tree.get_split_interval() {
+            return Ok(());
+        }
+        self.tree
+            .split_branch(branch_id, &self.clustering, &mut self.storage)?;
+        // Both children start from the parent's state and pending transactions
+        // at the split
+        let ledger = self.ledger(branch_id)?.clone();
+        let mempool = self.mempool(branch_id)?.clone();
+        for child in [format!("{}.1", branch_id), format!("{}.2", branch_id)] {
+            let chain = self
+                .storage
+                .get_branch(&child)
+                .ok_or_else(|| BlocktreeError::BranchNotFound(child.clone()))?;
+            self.dags
+                .insert(child.clone(), BlockDag::from_chain(chain)?);
+            self.ledgers.insert(child.clone(), ledger.clone());
+            self.mempools.insert(child, mempool.clone());
+        }
+        self.network.announce_branches(&self.get_branches());
+        Ok(())
+    }
+
+    fn dag(&self, branch_id: &str) -> Result<&BlockDag, BlocktreeError> {
+        self.dags
+            .get(branch_id)
+            .ok_or_else(|| BlocktreeError::BranchNotFound(branch_id.to_string()))
+    }
+
+    fn dag_mut(&mut self, branch_id: &str) -> Result<&mut BlockDag, BlocktreeError> {
+        self.dags
+            .get_mut(branch_id)
+            .ok_or_else(|| BlocktreeError::BranchNotFound(branch_id.to_string()))
+    }
+
+    /// Last block of the active chain of `branch_id`.
+    pub fn tip(&self, branch_id: &str) -> Result<&Block, BlocktreeError> {
+        self.storage
+            .get_branch(branch_id)
+            .and_then(|chain| chain.last())
+            .ok_or_else(|| BlocktreeError::BranchNotFound(branch_id.to_string()))
+    }
+
+    /// Blocks of `branch_id` known off its active chain, and orphans waiting
+    /// for their parent.
+    pub fn side_block_count(&self, branch_id: &str) -> Result<(usize, usize), BlocktreeError> {
+        let dag = self.dag(branch_id)?;
+        let active = self.storage.get_branch(branch_id).map_or(0, |b| b.len());
+        Ok((dag.len() - active, dag.orphan_count()))
+    }
+
+    /// Receiver of the network's events, to pass to `handle_network_event`.
+    /// `None` once taken, or if the network receives nothing.
+    pub fn take_network_events(&mut self) -> Option<mpsc::Receiver<NetworkEvent>> {
+        self.network.take_events()
+    }
+
+    /// Acts on an event from the network: syncs with newly connected peers,
+    /// and validates blocks and transactions from peers into their branch,
+    /// relaying those accepted to the other peers. A peer sending something
+    /// invalid only has it rejected.
+    pub fn handle_network_event(&mut self, event: NetworkEvent) -> Result<(), BlocktreeError> {
+        self.expire_requests()?;
+        match event {
+            NetworkEvent::PeerConnected { peer, .. } => {
+                self.send_to_peer(peer, Message::GetBranches);
+            }
+            NetworkEvent::PeerDisconnected(peer) => {
+                self.sync.remove_peer(peer);
+                self.gossip.remove_peer(peer);
+                self.retry_transaction_requests();
+                self.advance_sync()?;
+            }
+            NetworkEvent::Block { peer, block } => self.receive_block(peer, block),
+            NetworkEvent::Transaction {
+                peer,
+                branch_id,
+                transaction,
+            } => self.receive_transaction(peer, branch_id, transaction),
+            NetworkEvent::Message {
+                peer,
+                message: Message::Inventory { branch_id, tx_ids },
+            } => self.receive_inventory(peer, branch_id, tx_ids),
+            NetworkEvent::Message {
+                peer,
+                message: Message::GetTransactions { branch_id, tx_ids },
+            } => self.send_transactions(peer, branch_id, tx_ids),
+            NetworkEvent::Message { peer, message } => {
+                self.handle_sync_message(peer, message)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Gives up on what peers were asked and did not deliver in time, asking
+    /// other peers instead. Runs on every network event; run it periodically
+    /// too, so a silent peer is passed over even when nothing arrives.
+    pub fn expire_requests(&mut self) -> Result<(), BlocktreeError> {
+        self.retry_transaction_requests();
+        if self.sync.expire(self.clock.now_ms()) > 0 {
+            self.advance_sync()?;
+        }
+        Ok(())
+    }
+
+    // Time `peer` has to answer a request, allowing for the round trip
+    fn request_timeout(&self, peer: PeerId) -> i64 {
+        gossip::request_timeout(self.network.get_latency(LOCAL_NODE, peer))
+    }
+
+    fn receive_block(&mut self, peer: PeerId, block: Block) {
+        let branch_id = block.header.branch_id.clone();
+        if !self.dags.contains_key(&branch_id) {
+            // A branch split off beyond what this node has; sync up to it
+            self.send_to_peer(peer, Message::GetBranches);
+            return;
+        }
+        let hash = block.hash.clone();
+        let previous_hash = block.header.previous_hash.clone();
+        match self.process_block(block.clone(), &branch_id) {
+            Ok(BlockStatus::Extended | BlockStatus::Reorganized { .. }) => {
+                self.relay(peer, Message::Block(block));
+            }
+            // The blocks in between are fetched by syncing with the sender
+            Ok(BlockStatus::Orphan) => self.send_to_peer(peer, Message::GetBranches),
+            Ok(BlockStatus::SideChain | BlockStatus::Duplicate) => {}
+            Err(e) => {
+                println!("Rejected block {} from peer {}: {}", hash, peer, e);
+                // An orphan too easy to buffer may still be on a chain ahead
+                if !self.dags[&branch_id].contains(&previous_hash) {
+                    self.send_to_peer(peer, Message::GetBranches);
+                }
+            }
+        }
+    }
+
+    fn receive_transaction(&mut self, peer: PeerId, branch_id: String, transaction: Transaction) {
+        let tx_id = transaction.tx_id.clone();
+        if !self.gossip.on_transaction(peer, &tx_id) {
+            return; // Not asked for, so a peer can not push transactions
+        }
+        match self.insert_transaction(transaction, &branch_id) {
+            Ok(true) => {
+                self.gossip.insert(&tx_id);
+                self.relay(
+                    peer,
+                    Message::Inventory {
+                        branch_id,
+                        tx_ids: vec![tx_id],
+                    },
+                );
+            }
+            Ok(false) => {}
+            Err(e) => println!("Rejected transaction {} from peer {}: {}", tx_id, peer, e),
+        }
+    }
+
+    // Asks `peer` for the transactions it announced that were not seen yet
+    fn receive_inventory(&mut self, peer: PeerId, branch_id: String, tx_ids: Vec<String>) {
+        let mempool = match self.mempools.get(&branch_id) {
+            Some(mempool) => mempool,
+            None => return,
+        };
+        let now = self.clock.now_ms();
+        let timeout = self.request_timeout(peer);
+        let wanted = self
+            .gossip
+            .on_inventory(peer, &branch_id, tx_ids, now, timeout, |tx_id| {
+                mempool.contains(tx_id)
+            });
+        if !wanted.is_empty() {
+            let message = Message::GetTransactions {
+                branch_id,
+                tx_ids: wanted,
+            };
+            self.send_to_peer(peer, message);
+        }
+    }
+
+    // Asks the next announcer for transactions a peer was asked for and did
+    // not deliver in time
+    fn retry_transaction_requests(&mut self) {
+        let mut retries: HashMap<(PeerId, String), Vec<String>> = HashMap::new();
+        let network = &self.network;
+        let timeout = |peer| gossip::request_timeout(network.get_latency(LOCAL_NODE, peer));
+        for (peer, branch_id, tx_id) in self.gossip.expire(self.clock.now_ms(), timeout) {
+            retries.entry((peer, branch_id)).or_default().push(tx_id);
+        }
+        for ((peer, branch_id), tx_ids) in retries {
+            for chunk in tx_ids.chunks(MAX_INVENTORY) {
+                let message = Message::GetTransactions {
+                    branch_id: branch_id.clone(),
+                    tx_ids: chunk.to_vec(),
+                };
+                self.send_to_peer(peer, message);
+            }
+        }
+    }
+
+    // Sends `peer` the requested transactions still pending
+    fn send_transactions(&mut self, peer: PeerId, branch_id: String, tx_ids: Vec<String>) {
+        let transactions: Vec<Transaction> = match self.mempools.get(&branch_id) {
+            Some(mempool) => tx_ids
+                .iter()
+                .take(MAX_INVENTORY)
+                .filter_map(|tx_id| mempool.get(tx_id).cloned())
+                .collect(),
+            None => Vec::new(),
+        };
+        for transaction in transactions {
+            let message = Message::Transaction {
+                branch_id: branch_id.clone(),
+                transaction,
+            };
+            self.send_to_peer(peer, message);
+        }
+    }
+
+    // Forwards `message` to every peer but the one it came from
+    fn relay(&mut self, from: PeerId, message: Message) {
+        for peer in self.network.peer_ids() {
+            if peer != from {
+                self.send_to_peer(peer, message.clone());
+            }
+        }
+    }
+
+    /// Asks every peer for its branches, to catch up with what they hold.
+    pub fn start_sync(&mut self) {
+        for peer in self.network.peer_ids() {
+            self.send_to_peer(peer, Message::GetBranches);
+        }
+    }
+
+    /// Serves a peer's sync request, or advances this node's sync with a
+    /// peer's reply. Other messages are ignored.
+    pub fn handle_sync_message(
+        &mut self,
+        peer: PeerId,
+        message: Message,
+    ) -> Result<(), BlocktreeError> {
+        match message {
+            Message::GetBranches => {
+                let summaries = self.branch_summaries();
+                self.send_to_peer(peer, Message::Branches(summaries));
+            }
+            Message::GetHeaders { branch_id, locator } => {
+                let headers = self
+                    .storage
+                    .get_branch(&branch_id)
+                    .map(|chain| sync::headers_after(chain, &locator))
+                    .unwrap_or_default();
+                self.send_to_peer(peer, Message::Headers { branch_id, headers });
+            }
+            Message::GetBlocks { branch_id, hashes } => {
+                let blocks = match self.dags.get(&branch_id) {
+                    Some(dag) => hashes
+                        .iter()
+                        .take(MAX_BLOCKS_IN_FLIGHT)
+                        .filter_map(|hash| dag.get(hash).cloned())
+                        .collect(),
+                    None => Vec::new(),
+                };
+                self.send_to_peer(peer, Message::Blocks { branch_id, blocks });
+            }
+            Message::Branches(branches) => {
+                self.sync.on_branches(peer, branches);
+                self.advance_sync()?;
+            }
+            Message::Headers { branch_id, headers } => {
+                let now = self.clock.now_ms();
+                let consensus = &self.consensus;
+                // Retargeting looks back one window past the parent
+                let window = self.config.retarget_window + 1;
+                let result = match self.dags.get(&branch_id) {
+                    Some(dag) => {
+                        let check = |header: &BlockHeader, base: &str, run: &[Block]| {
+                            let mut chain = dag.headers_to(base, window.saturating_sub(run.len()));
+                            chain.extend_from_slice(&run[run.len().saturating_sub(window)..]);
+                            let hash = header.calculate_hash()?;
+                            validation::validate_header_context(
+                                header, &hash, &chain, consensus, now,
+                            )
+                        };
+                        self.sync
+                            .on_headers(
+                                peer,
+                                &branch_id,
+                                headers,
+                                consensus.pow_limit(),
+                                |hash| dag.contains(hash),
+                                check,
+                            )
+                            .map(|_| ())
+                    }
+                    None => Err(BlocktreeError::BranchNotFound(branch_id)),
+                };
+                if let Err(e) = result {
+                    println!("Ignoring headers from peer {}: {}", peer, e);
+                }
+                self.advance_sync()?;
+            }
+            Message::Blocks { branch_id, blocks } => {
+                if let Err(e) = self.sync.on_blocks(peer, &branch_id, blocks) {
+                    println!("Ignoring blocks from peer {}: {}", peer, e);
+                }
+                self.advance_sync()?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Nothing is left to download or commit from what peers advertised.
+    pub fn is_synced(&self) -> bool {
+        self.sync.is_synced()
+    }
+
+    // Active chain of every branch, parents before children
+    fn branch_summaries(&self) -> Vec<BranchSummary> {
+        let mut branch_ids = self.get_branches();
+        branch_ids.sort();
+        branch_ids
+            .into_iter()
+            .filter_map(|branch_id| {
+                let chain = self.storage.get_branch(&branch_id)?;
+                Some(BranchSummary {
+                    length: chain.len() as u64,
+                    tip_hash: chain.last()?.hash.clone(),
+                    branch_id,
+                })
+            })
+            .collect()
+    }
+
+    // Commits downloaded blocks, then requests the headers and bodies still
+    // missing, including those of branches the commits split off
+    fn advance_sync(&mut self) -> Result<(), BlocktreeError> {
+        self.commit_synced_blocks();
+        for branch_id in self.get_branches() {
+            let dag = self.dag(&branch_id)?;
+            let peer = match self
+                .sync
+                .headers_wanted(&branch_id, |hash| dag.contains(hash))
+            {
+                Some(peer) => peer,
+                None => continue,
+            };
+            let chain = self
+                .storage
+                .get_branch(&branch_id)
+                .ok_or_else(|| BlocktreeError::BranchNotFound(branch_id.clone()))?;
+            let locator = sync::locator(chain, self.sync.pending_tip(&branch_id));
+            let timeout = self.request_timeout(peer);
+            self.sync
+                .request_headers(&branch_id, peer, self.clock.now_ms(), timeout);
+            self.send_to_peer(peer, Message::GetHeaders { branch_id, locator });
+        }
+        let network = &self.network;
+        let timeout = |peer| gossip::request_timeout(network.get_latency(LOCAL_NODE, peer));
+        for (peer, branch_id, hashes) in self.sync.body_requests(self.clock.now_ms(), timeout) {
+            self.send_to_peer(peer, Message::GetBlocks { branch_id, hashes });
+        }
+        // A block still failing once no reply is awaited can never connect
+        if self.sync.is_idle() {
+            for branch_id in self.sync.ready_branches() {
+                println!(
+                    "Dropping synced blocks of {} that fail validation",
+                    branch_id
+                );
+                self.sync.drop_branch(&branch_id);
+            }
+        }
+        Ok(())
+    }
+
+    // Applies downloaded blocks in chain order. A block that fails, e.g. a
+    // claim of a lock on a branch not synced that far yet, is retried after
+    // other branches made progress.
+    fn commit_synced_blocks(&mut self) {
+        loop {
+            let mut progress = false;
+            for branch_id in self.sync.ready_branches() {
+                while let Some(block) = self.sync.ready(&branch_id).cloned() {
+                    if self.process_block(block, &branch_id).is_err() {
+                        break;
+                    }
+                    self.sync.pop_ready(&branch_id);
+                    progress = true;
+                }
+            }
+            if !progress {
+                return;
+            }
+        }
+    }
+
+    // A peer that can not be reached is dropped from sync and gossip
+    fn send_to_peer(&mut self, peer: PeerId, message: Message) {
+        if let Err(e) = self.network.send_to(peer, message) {
+            println!("Dropping peer {}: {}", peer, e);
+            self.sync.remove_peer(peer);
+            self.gossip.remove_peer(peer);
+        }
+    }
+
+    /// Cancels every mining job working on the current tip of `branch_id`.
+    pub fn cancel_mining(&mut self, branch_id: &str) {
+        if let Some(cancel) = self.mining.remove(branch_id) {
+            cancel.cancel();
+        }
+    }
+
+    /// Queues a transaction in the mempool of `branch_id` and announces it to
+    /// peers. Returns `false` if it was already pending there.
+    pub fn submit_transaction(
+        &mut self,
+        tx: Transaction,
+        branch_id: &str,
+    ) -> Result<bool, BlocktreeError> {
+        let tx_id = tx.tx_id.clone();
+        if !self.insert_transaction(tx, branch_id)? {
+            return Ok(false);
         }
+        self.gossip.insert(&tx_id);
+        self.network.broadcast_transaction(branch_id, &tx_id)?;
+        Ok(true)
+    }
+
+    fn insert_transaction(
+        &mut self,
+        tx: Transaction,
+        branch_id: &str,
+    ) -> Result<bool, BlocktreeError> {
+        if tx.is_claim() {
+            let claim_depth = self.config.claim_depth;
+            crossbranch::verify_claim(&tx, branch_id, &self.storage, claim_depth).map_err(
+                |reason| {
+                    BlocktreeError::TransactionError(format!(
+                        "Claim {} rejected: {}",
+                        tx.tx_id, reason
+                    ))
+                },
+            )?;
+        }
+        let ledger = self
+            .ledgers
+            .get(branch_id)
+            .ok_or_else(|| BlocktreeError::BranchNotFound(branch_id.to_string()))?;
+        let mempool = self
+            .mempools
+            .get_mut(branch_id)
+            .ok_or_else(|| BlocktreeError::BranchNotFound(branch_id.to_string()))?;
+        mempool.insert(tx, branch_id, ledger)
+    }
+
+    /// Mines the next block of `branch_id` from the highest-priority pending
+    /// transactions of its mempool.
+    pub fn mine_pending(&mut self, branch_id: &str) -> Result<(), BlocktreeError> {
+        let (block, cancel) = self.prepare_pending(branch_id)?;
+        let mined = self.consensus.mine_block(block, &cancel)?;
+        self.commit_mined_block(mined, branch_id)?;
         Ok(())
     }
 
+    pub fn mempool(&self, branch_id: &str) -> Result<&Mempool, BlocktreeError> {
+        self.mempools
+            .get(branch_id)
+            .ok_or_else(|| BlocktreeError::BranchNotFound(branch_id.to_string()))
+    }
+
+    /// Builds the claim, on the lock's destination branch, of the lock
+    /// `lock_tx_id` included in `source_branch`.
+    pub fn claim_transfer(
+        &self,
+        source_branch: &str,
+        lock_tx_id: &str,
+    ) -> Result<Transaction, BlocktreeError> {
+        let chain = self
+            .storage
+            .get_branch(source_branch)
+            .ok_or_else(|| BlocktreeError::BranchNotFound(source_branch.to_string()))?;
+        let block = chain
+            .iter()
+            .rev()
+            .find(|block| block.transactions.iter().any(|tx| tx.tx_id == lock_tx_id))
+            .ok_or_else(|| {
+                BlocktreeError::TransactionError(format!(
+                    "Lock {} is not on {}",
+                    lock_tx_id, source_branch
+                ))
+            })?;
+        crossbranch::claim(source_branch, block, lock_tx_id)
+    }
+
+    /// Suggested fee rate (fee per 1000 serialized bytes) for a transaction on
+    /// `branch_id`: the median rate paid in recent blocks, raised to outbid
+    /// the mempool when more is pending than fits in the next block.
+    pub fn estimate_fee_rate(&self, branch_id: &str) -> Result<u64, BlocktreeError> {
+        let chain = self
+            .storage
+            .get_branch(branch_id)
+            .ok_or_else(|| BlocktreeError::BranchNotFound(branch_id.to_string()))?;
+        let mut rates = chain
+            .iter()
+            .rev()
+            .filter(|block| block.header.index > 0)
+            .take(FEE_ESTIMATE_BLOCKS)
+            .flat_map(|block| block.transactions.iter().filter(|tx| !tx.is_coinbase()))
+            .map(|tx| tx.fee_rate())
+            .collect::<Result<Vec<u64>, BlocktreeError>>()?;
+        rates.sort_unstable();
+        let recent = rates.get(rates.len() / 2).copied().unwrap_or(0);
+        let congestion = self
+            .mempool(branch_id)?
+            .congestion_fee_rate(self.config.max_block_transactions)
+            .unwrap_or(0);
+        Ok(recent.max(congestion))
+    }
+
+    /// Validates a block (mined locally or received from a peer) as the next
+    /// block of `branch_id`.
+    pub fn validate_block(&self, block: &Block, branch_id: &str) -> Result<(), BlocktreeError> {
+        let chain = self
+            .storage
+            .get_branch(branch_id)
+            .ok_or_else(|| BlocktreeError::BranchNotFound(branch_id.to_string()))?;
+        validation::validate_block(
+            block,
+            branch_id,
+            chain,
+            &self.consensus,
+            &self.coin,
+            self.clock.now_ms(),
+        )?;
+        validation::validate_claims(block, branch_id, &self.storage, self.config.claim_depth)?;
+        self.ledger(branch_id)?.clone().apply_block(block)
+    }
+
+    fn ledger(&self, branch_id: &str) -> Result<&Ledger, BlocktreeError> {
+        self.ledgers
+            .get(branch_id)
+            .ok_or_else(|| BlocktreeError::BranchNotFound(branch_id.to_string()))
+    }
+
+    /// Balance of `address` at the tip of `branch_id`.
+    pub fn balance_of(&self, address: &str, branch_id: &str) -> Result<u64, BlocktreeError> {
+        Ok(self.ledger(branch_id)?.balance_of(address))
+    }
+
+    /// Nonce the next transaction from `address` on `branch_id` must carry.
+    pub fn nonce_of(&self, address: &str, branch_id: &str) -> Result<u64, BlocktreeError> {
+        Ok(self.ledger(branch_id)?.nonce_of(address))
+    }
+
     pub fn is_branch_valid(&self, branch_id: &str) -> Result<bool, BlocktreeError> {
-        self.tree.is_branch_valid(branch_id, &self.storage)
+        self.tree.is_branch_valid(
+            branch_id,
+            &self.consensus,
+            &self.coin,
+            &self.storage,
+            self.clock.now_ms(),
+        )
+    }
+
+    /// Accumulated proof-of-work of a branch, used as its weight.
+    pub fn branch_work(&self, branch_id: &str) -> Result<U256, BlocktreeError> {
+        let branch = self
+            .storage
+            .get_branch(branch_id)
+            .ok_or_else(|| BlocktreeError::BranchNotFound(branch_id.to_string()))?;
+        branch.iter().try_fold(U256::ZERO, |total, block| {
+            Ok(total.saturating_add(block.work()?))
+        })
+    }
+
+    /// Validates every block of a branch and reports each failure.
+    pub fn validate_branch(&self, branch_id: &str) -> Result<ValidationReport, BlocktreeError> {
+        self.tree.validate_branch(
+            branch_id,
+            &self.consensus,
+            &self.coin,
+            &self.storage,
+            self.clock.now_ms(),
+        )
     }
 
     pub fn get_bkt_supply(&self) -> u64 {
@@ -81,4 +954,154 @@ impl Blocktree {
     pub fn get_branches(&self) -> Vec<String> {
         self.storage.get_branch_keys()
     }
+
+    /// Work done mining the last locally mined block, including its hashrate.
+    pub fn mining_stats(&self) -> Option<MiningStats> {
+        self.mining_stats
+    }
+
+    pub fn consensus(&self) -> &C {
+        &self.consensus
+    }
+
+    pub fn config(&self) -> &BlocktreeConfig {
+        &self.config
+    }
+}
+
+/// Assembles a `Blocktree` from pluggable storage, consensus and clustering
+/// components, defaulting to the in-memory/PoW/spectral implementations.
+pub struct BlocktreeBuilder<
+    S: Storage = InMemoryStorage,
+    C: Consensus = ProofOfWork,
+    K: Clustering = SpectralClustering,
+> {
+    config: BlocktreeConfig,
+    storage: S,
+    consensus: C,
+    clustering: K,
+    network: Box<dyn Network>,
+    clock: Box<dyn Clock>,
+}
+
+impl BlocktreeBuilder {
+    pub fn new(network: Box<dyn Network>) -> Self {
+        Self::from_config(BlocktreeConfig::default(), network)
+    }
+
+    /// Starts a builder whose default components use the config's parameters.
+    pub fn from_config(config: BlocktreeConfig, network: Box<dyn Network>) -> Self {
+        let mut consensus = ProofOfWork::new(config.difficulty_bits, config.target_block_time)
+            .with_retarget_window(config.retarget_window)
+            .with_miner(Miner::new(config.miner_threads));
+        // An invalid limit is reported by `build`, which validates the config
+        if let Ok(pow_limit) = difficulty::compact_to_target(config.pow_limit_bits) {
+            consensus = consensus.with_pow_limit(pow_limit);
+        }
+        BlocktreeBuilder {
+            storage: InMemoryStorage::new(),
+            consensus,
+            clustering: SpectralClustering::new(config.node_count),
+            config,
+            network,
+            clock: Box::new(SystemClock),
+        }
+    }
+}
+
+impl<S: Storage, C: Consensus, K: Clustering> BlocktreeBuilder<S, C, K> {
+    pub fn storage<S2: Storage>(self, storage: S2) -> BlocktreeBuilder<S2, C, K> {
+        BlocktreeBuilder {
+            config: self.config,
+            storage,
+            consensus: self.consensus,
+            clustering: self.clustering,
+            network: self.network,
+            clock: self.clock,
+        }
+    }
+
+    pub fn consensus<C2: Consensus>(self, consensus: C2) -> BlocktreeBuilder<S, C2, K> {
+        BlocktreeBuilder {
+            config: self.config,
+            storage: self.storage,
+            consensus,
+            clustering: self.clustering,
+            network: self.network,
+            clock: self.clock,
+        }
+    }
+
+    pub fn clustering<K2: Clustering>(self, clustering: K2) -> BlocktreeBuilder<S, C, K2> {
+        BlocktreeBuilder {
+            config: self.config,
+            storage: self.storage,
+            consensus: self.consensus,
+            clustering,
+            network: self.network,
+            clock: self.clock,
+        }
+    }
+
+    /// Reads the time from `clock` instead of the wall clock.
+    pub fn clock(mut self, clock: Box<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Builds the blocktree, writing the configured genesis block unless the
+    /// storage already holds a `root` branch (e.g. a reopened `FileStorage`),
+    /// in which case the stored genesis must match the config.
+    pub fn build(self) -> Result<Blocktree<S, C, K>, BlocktreeError> {
+        let config = self.config;
+        config.validate()?;
+        let genesis = config.genesis_block()?;
+        let mut storage = self.storage;
+        match storage.get_branch("root").and_then(|root| root.first()) {
+            Some(stored) if stored.hash != genesis.hash => {
+                return Err(BlocktreeError::ConfigError(format!(
+                    "Stored genesis {} does not match configured genesis {}",
+                    stored.hash, genesis.hash
+                )));
+            }
+            Some(_) => {}
+            None => storage.save_block(genesis, "root")?,
+        }
+        let mut ledgers = HashMap::new();
+        let mut mempools = HashMap::new();
+        let mut dags = HashMap::new();
+        let mut coin = Coin::with_schedule(config.base_reward, config.decay_factor);
+        for branch_id in storage.get_branch_keys() {
+            if let Some(chain) = storage.get_branch(&branch_id) {
+                dags.insert(branch_id.clone(), BlockDag::from_chain(chain)?);
+                // The first block is genesis or a copy of the parent's tip,
+                // rewarded on the parent
+                for block in chain.iter().skip(1) {
+                    coin.mine_reward(block.header.index);
+                }
+            }
+            let ledger = Ledger::replay_branch(&storage, &branch_id)?;
+            ledgers.insert(branch_id.clone(), ledger);
+            mempools.insert(branch_id, Mempool::new(config.mempool_max_bytes));
+        }
+        self.network.announce_branches(&storage.get_branch_keys());
+        Ok(Blocktree {
+            tree: BlocktreeCore::with_split_interval(config.split_interval)
+                .with_claim_depth(config.claim_depth),
+            consensus: self.consensus,
+            clustering: self.clustering,
+            coin,
+            storage,
+            ledgers,
+            mempools,
+            dags,
+            mining: HashMap::new(),
+            mining_stats: None,
+            sync: SyncState::new(),
+            gossip: TxGossip::new(),
+            network: self.network,
+            clock: self.clock,
+            config,
+        })
+    }
 }
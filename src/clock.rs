@@ -0,0 +1,22 @@
+//! Where a blocktree reads the time from.
+//!
+//! Block timestamps, the future-drift check, retargeting and request
+//! timeouts all read the time through a `Clock`, so a simulation can run
+//! nodes on its virtual clock instead of the wall clock.
+
+use chrono::Utc;
+
+pub trait Clock: Send + Sync {
+    /// Milliseconds since the Unix epoch.
+    fn now_ms(&self) -> i64;
+}
+
+/// The wall clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_ms(&self) -> i64 {
+        Utc::now().timestamp_millis()
+    }
+}
@@ -1,25 +1,61 @@
+/// Denominator of the per-block decay factor, which is kept as a fraction so
+/// rewards come out the same on every platform.
+pub const DECAY_DENOMINATOR: u64 = 1_000_000;
+// Fixed-point scale the decay factor is raised to a power in: a multiple of
+// DECAY_DENOMINATOR, so the factor is exact, and of a large power of two, so
+// halvings are too. Its square still fits in a u128.
+const DECAY_SCALE: u128 = (DECAY_DENOMINATOR as u128) << 43;
+
 pub struct Coin {
     pub supply: u64,
     base_reward: u64,
-    decay_factor: f64, // For exponential decay
-    blocks_mined: u64,
+    decay_numerator: u64, // Over DECAY_DENOMINATOR, for exponential decay
 }
 
 impl Coin {
     pub fn new() -> Self {
+        Self::with_schedule(50, 0.999)
+    }
+
+    /// Schedule starting at `base_reward` and decaying by `decay_factor` per
+    /// block, rounded to a multiple of `1 / DECAY_DENOMINATOR` in (0, 1].
+    pub fn with_schedule(base_reward: u64, decay_factor: f64) -> Self {
+        let decay_numerator = (decay_factor * DECAY_DENOMINATOR as f64).round() as u64;
         Coin {
             supply: 0,
-            base_reward: 50,     // Starting reward
-            decay_factor: 0.999, // Decay per block
-            blocks_mined: 0,
+            base_reward,
+            decay_numerator: decay_numerator.clamp(1, DECAY_DENOMINATOR),
         }
     }
 
-    pub fn mine_reward(&mut self) -> u64 {
-        let reward =
-            (self.base_reward as f64 * self.decay_factor.powi(self.blocks_mined as i32)) as u64;
+    /// Scheduled reward for the block at `height` (its index), decaying
+    /// exponentially from the base reward. Computed in integers, squaring
+    /// and multiplying in fixed point and rounding down at each step.
+    pub fn reward_at(&self, height: u64) -> u64 {
+        let multiply = |a: u128, b: u128| a * b / DECAY_SCALE;
+        let mut factor = self.decay_numerator as u128 * (DECAY_SCALE / DECAY_DENOMINATOR as u128);
+        let mut decay = DECAY_SCALE;
+        let mut exponent = height;
+        while exponent > 0 && decay > 0 {
+            if exponent & 1 == 1 {
+                decay = multiply(decay, factor);
+            }
+            factor = multiply(factor, factor);
+            exponent >>= 1;
+        }
+        (self.base_reward as u128 * decay / DECAY_SCALE) as u64
+    }
+
+    pub fn mine_reward(&mut self, height: u64) -> u64 {
+        let reward = self.reward_at(height);
         self.supply += reward;
-        self.blocks_mined += 1;
+        reward
+    }
+
+    /// Takes back the reward of a block at `height` rolled back by a reorg.
+    pub fn revert_reward(&mut self, height: u64) -> u64 {
+        let reward = self.reward_at(height);
+        self.supply = self.supply.saturating_sub(reward);
         reward
     }
 
@@ -0,0 +1,205 @@
+use crate::block::Block;
+use crate::consensus::DEFAULT_RETARGET_WINDOW;
+use crate::crossbranch::DEFAULT_CLAIM_DEPTH;
+use crate::difficulty::{self, DEFAULT_POW_LIMIT_BITS};
+use crate::error::BlocktreeError;
+use crate::mempool::DEFAULT_MEMPOOL_MAX_BYTES;
+use crate::transaction::Transaction;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// Chain parameters for a Blocktree network, loadable from a JSON or TOML
+/// genesis file so devnets, testnets and mainnet stay reproducible. Unknown
+/// fields are rejected, so a misspelled parameter is never silently ignored.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BlocktreeConfig {
+    pub network_name: String,
+    pub difficulty_bits: u32,   // Compact target for new blocks
+    pub pow_limit_bits: u32,    // Easiest target retargeting may reach
+    pub target_block_time: f64, // Seconds
+    pub retarget_window: usize, // Blocks per difficulty adjustment window
+    pub node_count: u32,
+    pub split_interval: usize,
+    pub base_reward: u64,
+    pub decay_factor: f64,     // Per-block reward decay, used to the millionth
+    pub miner_address: String, // Receives coinbase rewards of mined blocks
+    pub mempool_max_bytes: usize, // Per-branch budget for pending transactions
+    pub max_block_transactions: usize, // Pending transactions per mined block
+    pub miner_threads: usize,  // Mining worker threads; 0 uses every available core
+    pub claim_depth: usize,    // Blocks burying a lock before it can be claimed
+    pub genesis: GenesisConfig,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct GenesisConfig {
+    pub timestamp: i64, // Milliseconds since the Unix epoch
+    pub transactions: Vec<GenesisTransaction>,
+    pub allocations: Vec<Allocation>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct GenesisTransaction {
+    pub sender: String,
+    pub receiver: String,
+    pub amount: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Allocation {
+    pub address: String,
+    pub amount: u64,
+}
+
+impl Default for BlocktreeConfig {
+    fn default() -> Self {
+        BlocktreeConfig {
+            network_name: "devnet".to_string(),
+            difficulty_bits: DEFAULT_POW_LIMIT_BITS,
+            pow_limit_bits: DEFAULT_POW_LIMIT_BITS,
+            target_block_time: 0.2,
+            retarget_window: DEFAULT_RETARGET_WINDOW,
+            node_count: 10,
+            split_interval: 5,
+            base_reward: 50,
+            decay_factor: 0.999,
+            miner_address: "miner".to_string(),
+            mempool_max_bytes: DEFAULT_MEMPOOL_MAX_BYTES,
+            max_block_transactions: 1000,
+            miner_threads: 0,
+            claim_depth: DEFAULT_CLAIM_DEPTH,
+            genesis: GenesisConfig::default(),
+        }
+    }
+}
+
+impl BlocktreeConfig {
+    /// Loads a genesis file, parsed as TOML for `.toml` paths and JSON otherwise.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, BlocktreeError> {
+        let path = path.as_ref();
+        let contents =
+            fs::read_to_string(path).map_err(|e| BlocktreeError::ConfigError(e.to_string()))?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::from_toml_str(&contents),
+            _ => Self::from_json_str(&contents),
+        }
+    }
+
+    pub fn from_json_str(contents: &str) -> Result<Self, BlocktreeError> {
+        let config: Self = serde_json::from_str(contents)
+            .map_err(|e| BlocktreeError::ConfigError(e.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_toml_str(contents: &str) -> Result<Self, BlocktreeError> {
+        let config: Self =
+            toml::from_str(contents).map_err(|e| BlocktreeError::ConfigError(e.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn genesis_block(&self) -> Result<Block, BlocktreeError> {
+        self.genesis.build_block(self.difficulty_bits)
+    }
+
+    pub fn validate(&self) -> Result<(), BlocktreeError> {
+        let target = difficulty::compact_to_target(self.difficulty_bits)
+            .map_err(|e| BlocktreeError::ConfigError(e.to_string()))?;
+        let pow_limit = difficulty::compact_to_target(self.pow_limit_bits)
+            .map_err(|e| BlocktreeError::ConfigError(e.to_string()))?;
+        if target > pow_limit {
+            return Err(BlocktreeError::ConfigError(
+                "difficulty_bits must not be easier than pow_limit_bits".to_string(),
+            ));
+        }
+        if !self.target_block_time.is_finite() || self.target_block_time <= 0.0 {
+            return Err(BlocktreeError::ConfigError(
+                "target_block_time must be positive".to_string(),
+            ));
+        }
+        if self.retarget_window == 0 {
+            return Err(BlocktreeError::ConfigError(
+                "retarget_window must be at least 1".to_string(),
+            ));
+        }
+        if self.node_count < 2 {
+            return Err(BlocktreeError::ConfigError(
+                "node_count must be at least 2 to split branches".to_string(),
+            ));
+        }
+        if self.split_interval < 2 {
+            return Err(BlocktreeError::ConfigError(
+                "split_interval must be at least 2".to_string(),
+            ));
+        }
+        if self.miner_address.is_empty() {
+            return Err(BlocktreeError::ConfigError(
+                "miner_address must not be empty".to_string(),
+            ));
+        }
+        if self.mempool_max_bytes == 0 {
+            return Err(BlocktreeError::ConfigError(
+                "mempool_max_bytes must be positive".to_string(),
+            ));
+        }
+        if self.claim_depth == 0 {
+            return Err(BlocktreeError::ConfigError(
+                "claim_depth must be at least 1".to_string(),
+            ));
+        }
+        if self.decay_factor.is_nan() || self.decay_factor <= 0.0 || self.decay_factor > 1.0 {
+            return Err(BlocktreeError::ConfigError(
+                "decay_factor must be in (0, 1]".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl GenesisConfig {
+    /// Builds the deterministic genesis block of the `root` branch, mined at
+    /// `bits`. Allocations are recorded as transfers from `genesis` after the
+    /// listed transactions.
+    pub fn build_block(&self, bits: u32) -> Result<Block, BlocktreeError> {
+        let mut transactions = Vec::new();
+        for tx in &self.transactions {
+            transactions.push(Transaction::with_timestamp(
+                tx.sender.clone(),
+                tx.receiver.clone(),
+                tx.amount,
+                self.timestamp,
+            )?);
+        }
+        for allocation in &self.allocations {
+            transactions.push(Transaction::with_timestamp(
+                "genesis".to_string(),
+                allocation.address.clone(),
+                allocation.amount,
+                self.timestamp,
+            )?);
+        }
+        if transactions.is_empty() {
+            transactions.push(Transaction::with_timestamp(
+                "genesis".to_string(),
+                "genesis".to_string(),
+                0,
+                self.timestamp,
+            )?);
+        }
+        let mut block = Block::with_timestamp(
+            0,
+            transactions,
+            "0".to_string(),
+            "root".to_string(),
+            self.timestamp,
+        )?;
+        block.header.bits = bits;
+        block.update_hash()?;
+        Ok(block)
+    }
+}
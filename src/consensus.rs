@@ -1,56 +1,108 @@
 use crate::block::Block;
+use crate::difficulty::{self, DEFAULT_POW_LIMIT_BITS, U256};
 use crate::error::BlocktreeError;
-use chrono::Utc;
+use crate::miner::{CancelToken, MinedBlock, Miner};
+
+/// Number of recent blocks a branch's difficulty is retargeted from.
+pub const DEFAULT_RETARGET_WINDOW: usize = 10;
 
 pub trait Consensus {
-    fn mine_block(&self, block: Block) -> Result<Block, BlocktreeError>;
-    fn adjust_difficulty(&self, block: &Block, previous_block: &Block) -> usize;
+    /// Mines `block` until its hash meets its target or `cancel` fires.
+    fn mine_block(&self, block: Block, cancel: &CancelToken) -> Result<MinedBlock, BlocktreeError>;
+    /// Compact target required for the block following `chain`, the blocks
+    /// of a single branch in order.
+    fn adjust_difficulty(&self, chain: &[Block]) -> u32;
+    /// Easiest target any block may have.
+    fn pow_limit(&self) -> U256;
 }
 
+#[derive(Clone)]
 pub struct ProofOfWork {
-    base_difficulty: usize,
+    bits: u32, // Target of a branch with no blocks yet
+    pow_limit: U256,
     target_block_time: f64, // Seconds
+    retarget_window: usize,
+    miner: Miner,
 }
 
 impl ProofOfWork {
-    pub fn new(base_difficulty: usize, target_block_time: f64) -> Self {
+    pub fn new(bits: u32, target_block_time: f64) -> Self {
         ProofOfWork {
-            base_difficulty,
+            bits,
+            pow_limit: difficulty::compact_to_target(DEFAULT_POW_LIMIT_BITS)
+                .expect("Default PoW limit is a valid target"),
             target_block_time,
+            retarget_window: DEFAULT_RETARGET_WINDOW,
+            miner: Miner::default(),
         }
     }
+
+    pub fn with_retarget_window(mut self, retarget_window: usize) -> Self {
+        self.retarget_window = retarget_window.max(1);
+        self
+    }
+
+    pub fn with_miner(mut self, miner: Miner) -> Self {
+        self.miner = miner;
+        self
+    }
+
+    /// Sets the easiest target retargeting may reach.
+    pub fn with_pow_limit(mut self, pow_limit: U256) -> Self {
+        self.pow_limit = pow_limit;
+        self
+    }
 }
 
 impl Consensus for ProofOfWork {
-    fn mine_block(&self, mut block: Block) -> Result<Block, BlocktreeError> {
-        let target = "0".repeat(self.base_difficulty);
-        let start_time = Utc::now().timestamp_millis() as f64 / 1000.0;
-        loop {
-            block.update_hash()?;
-            if block.hash.starts_with(&target) {
-                println!("Block mined on branch {}: {}", block.branch_id, block.hash);
-                return Ok(block);
-            }
-            block.nonce += 1;
-            let elapsed = (Utc::now().timestamp_millis() as f64 / 1000.0) - start_time;
-            if elapsed > self.target_block_time {
-                return Err(BlocktreeError::MiningTimeout(format!(
-                    "Failed to mine block within {} seconds",
-                    self.target_block_time
-                )));
+    fn mine_block(&self, block: Block, cancel: &CancelToken) -> Result<MinedBlock, BlocktreeError> {
+        let mined = self.miner.mine(block, cancel)?;
+        println!(
+            "Block mined on branch {}: {} ({} hashes, {:.0} H/s)",
+            mined.block.header.branch_id,
+            mined.block.hash,
+            mined.stats.hashes,
+            mined.stats.hashrate()
+        );
+        Ok(mined)
+    }
+
+    /// Scales the average target of the last `retarget_window` blocks by how
+    /// long they took versus the target block time, clamped to a factor of 4
+    /// either way and never easier than the PoW limit. Until a branch has a
+    /// full window, the previous block's target carries over.
+    fn adjust_difficulty(&self, chain: &[Block]) -> u32 {
+        let last = match chain.last() {
+            Some(last) => last,
+            None => return self.bits,
+        };
+        if chain.len() <= self.retarget_window {
+            return last.header.bits;
+        }
+        let window = &chain[chain.len() - self.retarget_window - 1..];
+        let target_ms = ((self.target_block_time * 1000.0) as u64).max(1);
+        let expected_ms = target_ms * self.retarget_window as u64;
+        let actual_ms = ((last.header.timestamp - window[0].header.timestamp).max(0) as u64)
+            .clamp(expected_ms / 4, expected_ms * 4)
+            .max(1);
+        let mut total = U256::ZERO;
+        for block in &window[1..] {
+            match difficulty::compact_to_target(block.header.bits) {
+                Ok(target) => total = total.saturating_add(target),
+                Err(_) => return difficulty::target_to_compact(self.pow_limit),
             }
         }
+        let average = total.div_u64(self.retarget_window as u64);
+        let adjusted = average
+            .div_u64(expected_ms)
+            .checked_mul_u64(actual_ms)
+            .unwrap_or(U256::MAX)
+            .min(self.pow_limit)
+            .max(U256::ONE);
+        difficulty::target_to_compact(adjusted)
     }
 
-    fn adjust_difficulty(&self, block: &Block, previous_block: &Block) -> usize {
-        let time_taken = (block.timestamp - previous_block.timestamp) as f64 / 1000.0;
-        let expected_time = self.target_block_time;
-        if time_taken < expected_time / 2.0 {
-            self.base_difficulty + 1
-        } else if time_taken > expected_time * 2.0 {
-            self.base_difficulty.saturating_sub(1).max(1)
-        } else {
-            self.base_difficulty
-        }
+    fn pow_limit(&self) -> U256 {
+        self.pow_limit
     }
 }
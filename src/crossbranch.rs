@@ -0,0 +1,156 @@
+use crate::block::{self, Block, TransactionProof};
+use crate::error::BlocktreeError;
+use crate::ledger::parent_branch;
+use crate::storage::Storage;
+use crate::transaction::Transaction;
+use crate::wallet::Keypair;
+use serde::{Deserialize, Serialize};
+
+/// Sender of claim transactions, which release funds locked on another branch.
+pub const CLAIM_SENDER: &str = "crossbranch";
+
+/// Blocks that must follow a lock on its source branch before it can be
+/// claimed, unless configured otherwise.
+pub const DEFAULT_CLAIM_DEPTH: usize = 6;
+
+/// Cross-branch role of a transaction. A lock burns `amount` on the source
+/// branch for `receiver` on `destination_branch`; a claim on the destination
+/// branch mints it again, backed by a Merkle proof of the lock.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CrossBranch {
+    Lock { destination_branch: String },
+    Claim(Claim),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Claim {
+    pub source_branch: String,
+    pub block_hash: String, // Source block holding the lock
+    pub lock: Box<Transaction>,
+    pub proof: TransactionProof,
+}
+
+/// Creates a signed lock of `amount` on `source_branch` from `keypair`'s
+/// address, claimable by `receiver` on `destination_branch`.
+pub fn lock(
+    keypair: &Keypair,
+    destination_branch: &str,
+    receiver: String,
+    amount: u64,
+    fee: u64,
+    nonce: u64,
+    source_branch: &str,
+) -> Result<Transaction, BlocktreeError> {
+    let mut tx = Transaction::new(keypair.address(), receiver, amount)?;
+    tx.fee = fee;
+    tx.nonce = nonce;
+    tx.branch_id = source_branch.to_string();
+    tx.cross_branch = Some(CrossBranch::Lock {
+        destination_branch: destination_branch.to_string(),
+    });
+    tx.sign(keypair)?;
+    Ok(tx)
+}
+
+/// Creates the claim of the lock `lock_tx_id`, included in `block` of
+/// `source_branch`. Anyone may submit it; it always pays the lock's receiver.
+pub fn claim(
+    source_branch: &str,
+    block: &Block,
+    lock_tx_id: &str,
+) -> Result<Transaction, BlocktreeError> {
+    let proof = block.transaction_proof(lock_tx_id)?;
+    let lock = block
+        .transactions
+        .iter()
+        .find(|tx| tx.tx_id == lock_tx_id)
+        .cloned()
+        .ok_or_else(|| BlocktreeError::TransactionError(format!("Unknown lock {}", lock_tx_id)))?;
+    let destination_branch = match &lock.cross_branch {
+        Some(CrossBranch::Lock { destination_branch }) => destination_branch.clone(),
+        _ => {
+            return Err(BlocktreeError::TransactionError(format!(
+                "{} is not a lock",
+                lock_tx_id
+            )))
+        }
+    };
+    let mut tx = Transaction::new(CLAIM_SENDER.to_string(), lock.receiver.clone(), lock.amount)?;
+    tx.branch_id = destination_branch;
+    tx.cross_branch = Some(CrossBranch::Claim(Claim {
+        source_branch: source_branch.to_string(),
+        block_hash: block.hash.clone(),
+        lock: Box::new(lock),
+        proof,
+    }));
+    tx.tx_id = tx.calculate_hash()?;
+    Ok(tx)
+}
+
+/// Transfers are allowed between siblings and between a branch and any of
+/// its descendants.
+pub fn branches_related(a: &str, b: &str) -> bool {
+    let siblings = parent_branch(a).is_some() && parent_branch(a) == parent_branch(b);
+    let descends = |child: &str, ancestor: &str| child.starts_with(&format!("{}.", ancestor));
+    a != b && (siblings || descends(a, b) || descends(b, a))
+}
+
+/// Checks a claim on `branch_id` against the stored source branch: the lock
+/// must target this branch, be signed, and be proven part of a source block
+/// buried under at least `claim_depth` blocks. Returns why it is not
+/// acceptable, if it is not.
+pub fn verify_claim<S: Storage>(
+    tx: &Transaction,
+    branch_id: &str,
+    storage: &S,
+    claim_depth: usize,
+) -> Result<(), String> {
+    let claim = match &tx.cross_branch {
+        Some(CrossBranch::Claim(claim)) => claim,
+        _ => return Err("not a claim".to_string()),
+    };
+    match &claim.lock.cross_branch {
+        Some(CrossBranch::Lock { destination_branch }) if destination_branch == branch_id => {}
+        _ => return Err(format!("lock does not target {}", branch_id)),
+    }
+    if !branches_related(&claim.source_branch, branch_id) {
+        return Err(format!(
+            "{} and {} are neither siblings nor related by descent",
+            claim.source_branch, branch_id
+        ));
+    }
+    let lock = &claim.lock;
+    if lock.calculate_hash().ok().as_deref() != Some(lock.tx_id.as_str()) || !lock.is_valid() {
+        return Err("lock is malformed or badly signed".to_string());
+    }
+    if lock.branch_id != claim.source_branch {
+        return Err(format!("lock was not made on {}", claim.source_branch));
+    }
+    let chain = storage
+        .get_branch(&claim.source_branch)
+        .ok_or_else(|| format!("unknown source branch {}", claim.source_branch))?;
+    let position = chain
+        .iter()
+        .position(|block| block.hash == claim.block_hash)
+        .ok_or_else(|| {
+            format!(
+                "block {} is not on {}",
+                claim.block_hash, claim.source_branch
+            )
+        })?;
+    if chain.len() - 1 - position < claim_depth {
+        return Err(format!(
+            "block {} is not buried deep enough",
+            claim.block_hash
+        ));
+    }
+    if !block::verify_transaction_proof(
+        &chain[position].header.merkle_root,
+        &lock.tx_id,
+        &claim.proof,
+    ) {
+        return Err(format!("lock {} is not proven in its block", lock.tx_id));
+    }
+    Ok(())
+}
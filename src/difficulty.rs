@@ -0,0 +1,311 @@
+use crate::error::BlocktreeError;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Div, Not, Shl, Shr, Sub};
+
+/// Compact encoding of the easiest target allowed, roughly a `00` hex prefix.
+pub const DEFAULT_POW_LIMIT_BITS: u32 = 0x2000ffff;
+
+/// Unsigned 256-bit integer used for PoW targets and accumulated work.
+/// Limbs are stored least significant first.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct U256([u64; 4]);
+
+impl U256 {
+    pub const ZERO: U256 = U256([0; 4]);
+    pub const ONE: U256 = U256([1, 0, 0, 0]);
+    pub const MAX: U256 = U256([u64::MAX; 4]);
+
+    pub fn from_u64(value: u64) -> Self {
+        U256([value, 0, 0, 0])
+    }
+
+    pub fn from_be_bytes(bytes: [u8; 32]) -> Self {
+        let mut limbs = [0u64; 4];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let start = 32 - (i + 1) * 8;
+            *limb = u64::from_be_bytes(bytes[start..start + 8].try_into().unwrap());
+        }
+        U256(limbs)
+    }
+
+    pub fn to_be_bytes(self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for (i, limb) in self.0.iter().enumerate() {
+            let start = 32 - (i + 1) * 8;
+            bytes[start..start + 8].copy_from_slice(&limb.to_be_bytes());
+        }
+        bytes
+    }
+
+    /// Parses a 64-character hex string such as a block hash.
+    pub fn from_hex(hex_str: &str) -> Result<Self, BlocktreeError> {
+        let bytes = hex::decode(hex_str).map_err(|e| BlocktreeError::InvalidHash(e.to_string()))?;
+        let bytes: [u8; 32] = bytes.try_into().map_err(|_| {
+            BlocktreeError::InvalidHash(format!("Expected 32-byte hash, got {}", hex_str))
+        })?;
+        Ok(Self::from_be_bytes(bytes))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0; 4]
+    }
+
+    pub fn low_u64(&self) -> u64 {
+        self.0[0]
+    }
+
+    /// Number of significant bits.
+    pub fn bits(&self) -> u32 {
+        for i in (0..4).rev() {
+            if self.0[i] != 0 {
+                return 64 * i as u32 + (64 - self.0[i].leading_zeros());
+            }
+        }
+        0
+    }
+
+    pub fn checked_add(self, other: U256) -> Option<U256> {
+        let mut result = [0u64; 4];
+        let mut carry = false;
+        for (i, limb) in result.iter_mut().enumerate() {
+            let (sum, c1) = self.0[i].overflowing_add(other.0[i]);
+            let (sum, c2) = sum.overflowing_add(carry as u64);
+            *limb = sum;
+            carry = c1 || c2;
+        }
+        if carry {
+            None
+        } else {
+            Some(U256(result))
+        }
+    }
+
+    pub fn saturating_add(self, other: U256) -> U256 {
+        self.checked_add(other).unwrap_or(U256::MAX)
+    }
+
+    pub fn checked_sub(self, other: U256) -> Option<U256> {
+        let mut result = [0u64; 4];
+        let mut borrow = false;
+        for (i, limb) in result.iter_mut().enumerate() {
+            let (diff, b1) = self.0[i].overflowing_sub(other.0[i]);
+            let (diff, b2) = diff.overflowing_sub(borrow as u64);
+            *limb = diff;
+            borrow = b1 || b2;
+        }
+        if borrow {
+            None
+        } else {
+            Some(U256(result))
+        }
+    }
+
+    pub fn checked_mul_u64(self, other: u64) -> Option<U256> {
+        let mut result = [0u64; 4];
+        let mut carry = 0u128;
+        for (i, limb) in result.iter_mut().enumerate() {
+            let product = self.0[i] as u128 * other as u128 + carry;
+            *limb = product as u64;
+            carry = product >> 64;
+        }
+        if carry != 0 {
+            None
+        } else {
+            Some(U256(result))
+        }
+    }
+
+    pub fn div_u64(self, divisor: u64) -> U256 {
+        assert!(divisor != 0, "U256 division by zero");
+        let mut result = [0u64; 4];
+        let mut remainder = 0u128;
+        for i in (0..4).rev() {
+            let current = (remainder << 64) | self.0[i] as u128;
+            result[i] = (current / divisor as u128) as u64;
+            remainder = current % divisor as u128;
+        }
+        U256(result)
+    }
+}
+
+impl Ord for U256 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        for i in (0..4).rev() {
+            match self.0[i].cmp(&other.0[i]) {
+                Ordering::Equal => continue,
+                ordering => return ordering,
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+impl PartialOrd for U256 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Add for U256 {
+    type Output = U256;
+
+    fn add(self, other: U256) -> U256 {
+        self.checked_add(other).expect("U256 addition overflow")
+    }
+}
+
+impl Sub for U256 {
+    type Output = U256;
+
+    fn sub(self, other: U256) -> U256 {
+        self.checked_sub(other).expect("U256 subtraction underflow")
+    }
+}
+
+impl Div for U256 {
+    type Output = U256;
+
+    /// Long division, one bit at a time.
+    fn div(self, divisor: U256) -> U256 {
+        assert!(!divisor.is_zero(), "U256 division by zero");
+        if self < divisor {
+            return U256::ZERO;
+        }
+        let shift = self.bits() - divisor.bits();
+        let mut divisor = divisor << shift;
+        let mut remainder = self;
+        let mut quotient = U256::ZERO;
+        for i in (0..=shift).rev() {
+            if remainder >= divisor {
+                remainder = remainder - divisor;
+                quotient.0[(i / 64) as usize] |= 1 << (i % 64);
+            }
+            divisor = divisor >> 1;
+        }
+        quotient
+    }
+}
+
+impl Not for U256 {
+    type Output = U256;
+
+    fn not(self) -> U256 {
+        U256(self.0.map(|limb| !limb))
+    }
+}
+
+impl Shl<u32> for U256 {
+    type Output = U256;
+
+    fn shl(self, shift: u32) -> U256 {
+        let mut result = [0u64; 4];
+        let limb_shift = (shift / 64) as usize;
+        let bit_shift = shift % 64;
+        for (i, limb) in result.iter_mut().enumerate().skip(limb_shift) {
+            *limb = self.0[i - limb_shift] << bit_shift;
+            if bit_shift > 0 && i > limb_shift {
+                *limb |= self.0[i - limb_shift - 1] >> (64 - bit_shift);
+            }
+        }
+        U256(result)
+    }
+}
+
+impl Shr<u32> for U256 {
+    type Output = U256;
+
+    fn shr(self, shift: u32) -> U256 {
+        let mut result = [0u64; 4];
+        let limb_shift = (shift / 64) as usize;
+        let bit_shift = shift % 64;
+        for (i, limb) in result
+            .iter_mut()
+            .take(4usize.saturating_sub(limb_shift))
+            .enumerate()
+        {
+            *limb = self.0[i + limb_shift] >> bit_shift;
+            if bit_shift > 0 && i + limb_shift + 1 < 4 {
+                *limb |= self.0[i + limb_shift + 1] << (64 - bit_shift);
+            }
+        }
+        U256(result)
+    }
+}
+
+impl fmt::Display for U256 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.to_be_bytes()))
+    }
+}
+
+impl fmt::Debug for U256 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "U256(0x{})", self)
+    }
+}
+
+/// Decodes a compact "bits" value (8-bit exponent, 23-bit mantissa) into a
+/// 256-bit target. Negative, overflowing and zero targets are rejected.
+pub fn compact_to_target(bits: u32) -> Result<U256, BlocktreeError> {
+    let size = bits >> 24;
+    let word = bits & 0x007f_ffff;
+    if bits & 0x0080_0000 != 0 && word != 0 {
+        return Err(BlocktreeError::InvalidDifficulty(format!(
+            "Negative target in bits {:#010x}",
+            bits
+        )));
+    }
+    if word != 0 && (size > 34 || (word > 0xff && size > 33) || (word > 0xffff && size > 32)) {
+        return Err(BlocktreeError::InvalidDifficulty(format!(
+            "Target overflow in bits {:#010x}",
+            bits
+        )));
+    }
+    let target = if size <= 3 {
+        U256::from_u64((word >> (8 * (3 - size))) as u64)
+    } else {
+        U256::from_u64(word as u64) << (8 * (size - 3))
+    };
+    if target.is_zero() {
+        return Err(BlocktreeError::InvalidDifficulty(format!(
+            "Zero target in bits {:#010x}",
+            bits
+        )));
+    }
+    Ok(target)
+}
+
+/// Encodes a target in compact form, losing precision below the top 23 bits.
+pub fn target_to_compact(target: U256) -> u32 {
+    let mut size = target.bits().div_ceil(8);
+    let mut compact = if size <= 3 {
+        (target.low_u64() << (8 * (3 - size))) as u32
+    } else {
+        (target >> (8 * (size - 3))).low_u64() as u32
+    };
+    // The mantissa's high bit is a sign flag, so shift it into the exponent
+    if compact & 0x0080_0000 != 0 {
+        compact >>= 8;
+        size += 1;
+    }
+    compact | (size << 24)
+}
+
+/// Expected number of hashes needed to meet the target: 2^256 / (target + 1).
+pub fn target_work(target: U256) -> U256 {
+    match target.checked_add(U256::ONE) {
+        // (2^256 - target - 1) / (target + 1) + 1 avoids representing 2^256
+        Some(divisor) => (!target / divisor) + U256::ONE,
+        None => U256::ONE,
+    }
+}
+
+pub fn bits_to_work(bits: u32) -> Result<U256, BlocktreeError> {
+    Ok(target_work(compact_to_target(bits)?))
+}
+
+pub fn hash_meets_target(hash: &str, bits: u32) -> Result<bool, BlocktreeError> {
+    Ok(U256::from_hex(hash)? <= compact_to_target(bits)?)
+}
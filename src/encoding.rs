@@ -0,0 +1,415 @@
+//! Canonical binary encoding of blocks, headers and transactions, used for
+//! hashing, storage and the wire.
+//!
+//! Every top-level value starts with the one-byte `ENCODING_VERSION`, followed
+//! by its fields in declaration order:
+//!
+//! - integers are little-endian: `u8`, `u32`, `u64` and `i64` as two's
+//!   complement; `usize` values are written as `u64`
+//! - strings are a `u32` byte length followed by UTF-8 bytes
+//! - sequences are a `u32` element count followed by the elements
+//!
+//! `BlockHeader`: version u32, index u64, timestamp i64, previous_hash str,
+//! branch_id str, merkle_root str, bits u32, nonce u64.
+//!
+//! `Block`: header, transactions as a sequence, hash str.
+//!
+//! `Transaction`: sender str, receiver str, amount u64, fee u64, nonce u64,
+//! branch_id str, timestamp i64, public_key str, signature str, cross_branch, tx_id str.
+//! `cross_branch` is a `u8` tag: `0` none, `1` lock followed by
+//! destination_branch str, or `2` claim followed by source_branch str,
+//! block_hash str, the lock transaction (never itself a claim), and the proof
+//! as leaf_index u64, leaf_count u64 and a sequence of hash strings.
+//!
+//! Hash preimages are encodings too: a header hashes its full encoding, a
+//! transaction its encoding without the trailing tx_id, and the signature
+//! covers the transaction fields up to public_key plus cross_branch.
+//!
+//! Version 1 lacked the transaction's branch_id. Stored values of that
+//! version can still be read, for migration; the wire takes the current
+//! version only.
+
+use crate::block::{Block, BlockHeader, TransactionProof};
+use crate::crossbranch::{Claim, CrossBranch};
+use crate::error::BlocktreeError;
+use crate::transaction::Transaction;
+
+/// Version byte prefixed to every encoded value. Version 2 added the
+/// transaction's branch.
+pub const ENCODING_VERSION: u8 = 2;
+/// Oldest version `from_versioned_bytes` still reads.
+pub const MIN_ENCODING_VERSION: u8 = 1;
+
+pub trait Encode {
+    fn encode(&self, out: &mut Vec<u8>);
+}
+
+pub trait Decode: Sized {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, BlocktreeError>;
+}
+
+/// Encodes `value` with the version prefix.
+pub fn to_bytes<T: Encode>(value: &T) -> Vec<u8> {
+    let mut out = vec![ENCODING_VERSION];
+    value.encode(&mut out);
+    out
+}
+
+/// Decodes a version-prefixed value, rejecting other versions and trailing
+/// bytes.
+pub fn from_bytes<T: Decode>(bytes: &[u8]) -> Result<T, BlocktreeError> {
+    decode_versioned(bytes, ENCODING_VERSION).map(|(value, _)| value)
+}
+
+/// Decodes a value written by any version since `MIN_ENCODING_VERSION`,
+/// returning it with its version. Fields the version lacked are left empty.
+pub fn from_versioned_bytes<T: Decode>(bytes: &[u8]) -> Result<(T, u8), BlocktreeError> {
+    decode_versioned(bytes, MIN_ENCODING_VERSION)
+}
+
+fn decode_versioned<T: Decode>(bytes: &[u8], oldest: u8) -> Result<(T, u8), BlocktreeError> {
+    let mut reader = Reader::new(bytes);
+    let version = u8::decode(&mut reader)?;
+    if !(oldest..=ENCODING_VERSION).contains(&version) {
+        return Err(decode_error(format!(
+            "unsupported encoding version {}",
+            version
+        )));
+    }
+    reader.version = version;
+    let value = T::decode(&mut reader)?;
+    if reader.remaining() != 0 {
+        return Err(decode_error(format!(
+            "{} trailing bytes",
+            reader.remaining()
+        )));
+    }
+    Ok((value, version))
+}
+
+/// Cursor over encoded bytes of one encoding version.
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    version: u8,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Reader {
+            bytes,
+            position: 0,
+            version: ENCODING_VERSION,
+        }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.bytes.len() - self.position
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], BlocktreeError> {
+        if self.remaining() < len {
+            return Err(decode_error(format!(
+                "needed {} bytes at offset {}, {} left",
+                len,
+                self.position,
+                self.remaining()
+            )));
+        }
+        let bytes = &self.bytes[self.position..self.position + len];
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], BlocktreeError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+}
+
+fn decode_error(message: String) -> BlocktreeError {
+    BlocktreeError::SerializationError(format!("Invalid encoding: {}", message))
+}
+
+impl Encode for u8 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self);
+    }
+}
+
+impl Decode for u8 {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, BlocktreeError> {
+        Ok(reader.take_array::<1>()?[0])
+    }
+}
+
+impl Encode for u32 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Decode for u32 {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, BlocktreeError> {
+        Ok(u32::from_le_bytes(reader.take_array()?))
+    }
+}
+
+impl Encode for u64 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Decode for u64 {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, BlocktreeError> {
+        Ok(u64::from_le_bytes(reader.take_array()?))
+    }
+}
+
+impl Encode for i64 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Decode for i64 {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, BlocktreeError> {
+        Ok(i64::from_le_bytes(reader.take_array()?))
+    }
+}
+
+impl Encode for usize {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (*self as u64).encode(out);
+    }
+}
+
+impl Decode for usize {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, BlocktreeError> {
+        usize::try_from(u64::decode(reader)?)
+            .map_err(|_| decode_error("length does not fit in usize".to_string()))
+    }
+}
+
+impl Encode for str {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (self.len() as u32).encode(out);
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_str().encode(out);
+    }
+}
+
+impl Decode for String {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, BlocktreeError> {
+        let len = u32::decode(reader)? as usize;
+        let bytes = reader.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|e| decode_error(e.to_string()))
+    }
+}
+
+impl<T: Encode> Encode for [T] {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (self.len() as u32).encode(out);
+        for item in self {
+            item.encode(out);
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_slice().encode(out);
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, BlocktreeError> {
+        let count = u32::decode(reader)? as usize;
+        // Every element takes at least one byte, which bounds the allocation
+        let mut items = Vec::with_capacity(count.min(reader.remaining()));
+        for _ in 0..count {
+            items.push(T::decode(reader)?);
+        }
+        Ok(items)
+    }
+}
+
+impl Encode for BlockHeader {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.version.encode(out);
+        self.index.encode(out);
+        self.timestamp.encode(out);
+        self.previous_hash.encode(out);
+        self.branch_id.encode(out);
+        self.merkle_root.encode(out);
+        self.bits.encode(out);
+        self.nonce.encode(out);
+    }
+}
+
+impl Decode for BlockHeader {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, BlocktreeError> {
+        Ok(BlockHeader {
+            version: Decode::decode(reader)?,
+            index: Decode::decode(reader)?,
+            timestamp: Decode::decode(reader)?,
+            previous_hash: Decode::decode(reader)?,
+            branch_id: Decode::decode(reader)?,
+            merkle_root: Decode::decode(reader)?,
+            bits: Decode::decode(reader)?,
+            nonce: Decode::decode(reader)?,
+        })
+    }
+}
+
+impl Encode for Block {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.header.encode(out);
+        self.transactions.encode(out);
+        self.hash.encode(out);
+    }
+}
+
+impl Decode for Block {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, BlocktreeError> {
+        Ok(Block {
+            header: Decode::decode(reader)?,
+            transactions: Decode::decode(reader)?,
+            hash: Decode::decode(reader)?,
+        })
+    }
+}
+
+// Fields up to and including `public_key`, shared by every preimage
+fn encode_signed_fields(tx: &Transaction, out: &mut Vec<u8>) {
+    tx.sender.encode(out);
+    tx.receiver.encode(out);
+    tx.amount.encode(out);
+    tx.fee.encode(out);
+    tx.nonce.encode(out);
+    tx.branch_id.encode(out);
+    tx.timestamp.encode(out);
+    tx.public_key.encode(out);
+}
+
+/// Bytes the sender signs: everything but the signature and tx id.
+pub fn signing_bytes(tx: &Transaction) -> Vec<u8> {
+    let mut out = vec![ENCODING_VERSION];
+    encode_signed_fields(tx, &mut out);
+    encode_cross_branch(&tx.cross_branch, &mut out);
+    out
+}
+
+/// Preimage of a transaction's id: its encoding without the tx id.
+pub fn unhashed_transaction_bytes(tx: &Transaction) -> Vec<u8> {
+    let mut out = vec![ENCODING_VERSION];
+    encode_without_id(tx, &mut out);
+    out
+}
+
+fn encode_without_id(tx: &Transaction, out: &mut Vec<u8>) {
+    encode_signed_fields(tx, out);
+    tx.signature.encode(out);
+    encode_cross_branch(&tx.cross_branch, out);
+}
+
+impl Encode for Transaction {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_without_id(self, out);
+        self.tx_id.encode(out);
+    }
+}
+
+impl Decode for Transaction {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, BlocktreeError> {
+        decode_transaction(reader, true)
+    }
+}
+
+// A claimed lock can not itself be a claim, which also bounds the nesting of
+// untrusted input
+fn decode_transaction(
+    reader: &mut Reader<'_>,
+    allow_claim: bool,
+) -> Result<Transaction, BlocktreeError> {
+    Ok(Transaction {
+        sender: Decode::decode(reader)?,
+        receiver: Decode::decode(reader)?,
+        amount: Decode::decode(reader)?,
+        fee: Decode::decode(reader)?,
+        nonce: Decode::decode(reader)?,
+        branch_id: if reader.version >= 2 {
+            Decode::decode(reader)?
+        } else {
+            String::new()
+        },
+        timestamp: Decode::decode(reader)?,
+        public_key: Decode::decode(reader)?,
+        signature: Decode::decode(reader)?,
+        cross_branch: decode_cross_branch(reader, allow_claim)?,
+        tx_id: Decode::decode(reader)?,
+    })
+}
+
+// A single tag covers both the option and the variant
+fn encode_cross_branch(cross_branch: &Option<CrossBranch>, out: &mut Vec<u8>) {
+    match cross_branch {
+        None => 0u8.encode(out),
+        Some(CrossBranch::Lock { destination_branch }) => {
+            1u8.encode(out);
+            destination_branch.encode(out);
+        }
+        Some(CrossBranch::Claim(claim)) => {
+            2u8.encode(out);
+            claim.source_branch.encode(out);
+            claim.block_hash.encode(out);
+            claim.lock.encode(out);
+            claim.proof.encode(out);
+        }
+    }
+}
+
+fn decode_cross_branch(
+    reader: &mut Reader<'_>,
+    allow_claim: bool,
+) -> Result<Option<CrossBranch>, BlocktreeError> {
+    match u8::decode(reader)? {
+        0 => Ok(None),
+        1 => Ok(Some(CrossBranch::Lock {
+            destination_branch: Decode::decode(reader)?,
+        })),
+        2 if !allow_claim => Err(decode_error("claim nested in a claim".to_string())),
+        2 => Ok(Some(CrossBranch::Claim(Claim {
+            source_branch: Decode::decode(reader)?,
+            block_hash: Decode::decode(reader)?,
+            lock: Box::new(decode_transaction(reader, false)?),
+            proof: Decode::decode(reader)?,
+        }))),
+        tag => Err(decode_error(format!("unknown cross-branch tag {}", tag))),
+    }
+}
+
+impl Encode for TransactionProof {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.leaf_index.encode(out);
+        self.leaf_count.encode(out);
+        self.hashes.encode(out);
+    }
+}
+
+impl Decode for TransactionProof {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, BlocktreeError> {
+        Ok(TransactionProof {
+            leaf_index: Decode::decode(reader)?,
+            leaf_count: Decode::decode(reader)?,
+            hashes: Decode::decode(reader)?,
+        })
+    }
+}
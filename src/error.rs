@@ -6,11 +6,15 @@ pub enum BlocktreeError {
     InvalidHash(String),
     InvalidPreviousHash(String),
     SerializationError(String),
-    MiningTimeout(String),
+    MiningCancelled(String),
     ClusteringError(String),
     TransactionError(String),
     NetworkError(String),
     StorageError(String),
+    ConfigError(String),
+    InvalidDifficulty(String),
+    ServiceError(String),
+    Validation(ValidationError),
 }
 
 impl fmt::Display for BlocktreeError {
@@ -20,13 +24,269 @@ impl fmt::Display for BlocktreeError {
             BlocktreeError::InvalidHash(msg) => write!(f, "Invalid hash: {}", msg),
             BlocktreeError::InvalidPreviousHash(msg) => write!(f, "Invalid previous hash: {}", msg),
             BlocktreeError::SerializationError(msg) => write!(f, "Serialization error: {}", msg),
-            BlocktreeError::MiningTimeout(msg) => write!(f, "Mining timeout: {}", msg),
+            BlocktreeError::MiningCancelled(msg) => write!(f, "Mining cancelled: {}", msg),
             BlocktreeError::ClusteringError(msg) => write!(f, "Clustering error: {}", msg),
             BlocktreeError::TransactionError(msg) => write!(f, "Transaction error: {}", msg),
             BlocktreeError::NetworkError(msg) => write!(f, "Network error: {}", msg),
             BlocktreeError::StorageError(msg) => write!(f, "Storage error: {}", msg),
+            BlocktreeError::ConfigError(msg) => write!(f, "Config error: {}", msg),
+            BlocktreeError::InvalidDifficulty(msg) => write!(f, "Invalid difficulty: {}", msg),
+            BlocktreeError::ServiceError(msg) => write!(f, "Service error: {}", msg),
+            BlocktreeError::Validation(err) => write!(f, "Invalid block: {}", err),
         }
     }
 }
 
 impl std::error::Error for BlocktreeError {}
+
+impl From<ValidationError> for BlocktreeError {
+    fn from(err: ValidationError) -> Self {
+        BlocktreeError::Validation(err)
+    }
+}
+
+/// Why a block failed validation. Every variant carries the offending
+/// block's index and hash.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    UnsupportedVersion {
+        index: u64,
+        hash: String,
+        version: u32,
+    },
+    WrongBranch {
+        index: u64,
+        hash: String,
+        expected: String,
+        found: String,
+    },
+    BadIndex {
+        index: u64,
+        hash: String,
+        expected: u64,
+    },
+    BadLink {
+        index: u64,
+        hash: String,
+        expected_previous: String,
+        found_previous: String,
+    },
+    TimestampOutOfRange {
+        index: u64,
+        hash: String,
+        timestamp: i64,
+        min: i64,
+        max: i64,
+    },
+    BadHash {
+        index: u64,
+        hash: String,
+        computed: String,
+    },
+    BadDifficulty {
+        index: u64,
+        hash: String,
+        expected_bits: u32,
+        found_bits: u32,
+    },
+    InsufficientWork {
+        index: u64,
+        hash: String,
+        bits: u32,
+    },
+    TargetTooEasy {
+        index: u64,
+        hash: String,
+        bits: u32,
+        limit_bits: u32,
+    },
+    BadMerkleRoot {
+        index: u64,
+        hash: String,
+        expected: String,
+        found: String,
+    },
+    BadTransaction {
+        index: u64,
+        hash: String,
+        position: usize,
+        tx_id: String,
+    },
+    MissingCoinbase {
+        index: u64,
+        hash: String,
+    },
+    BadCoinbase {
+        index: u64,
+        hash: String,
+        expected_reward: u64,
+        found_reward: u64,
+    },
+    BadClaim {
+        index: u64,
+        hash: String,
+        position: usize,
+        tx_id: String,
+        reason: String,
+    },
+    BadNonce {
+        index: u64,
+        hash: String,
+        position: usize,
+        tx_id: String,
+        expected: u64,
+        found: u64,
+    },
+    Overspend {
+        index: u64,
+        hash: String,
+        position: usize,
+        tx_id: String,
+        balance: u64,
+        required: u64,
+    },
+}
+
+impl ValidationError {
+    pub fn block_index(&self) -> u64 {
+        match self {
+            ValidationError::UnsupportedVersion { index, .. }
+            | ValidationError::WrongBranch { index, .. }
+            | ValidationError::BadIndex { index, .. }
+            | ValidationError::BadLink { index, .. }
+            | ValidationError::TimestampOutOfRange { index, .. }
+            | ValidationError::BadHash { index, .. }
+            | ValidationError::BadDifficulty { index, .. }
+            | ValidationError::InsufficientWork { index, .. }
+            | ValidationError::TargetTooEasy { index, .. }
+            | ValidationError::BadMerkleRoot { index, .. }
+            | ValidationError::BadTransaction { index, .. }
+            | ValidationError::MissingCoinbase { index, .. }
+            | ValidationError::BadCoinbase { index, .. }
+            | ValidationError::BadClaim { index, .. }
+            | ValidationError::BadNonce { index, .. }
+            | ValidationError::Overspend { index, .. } => *index,
+        }
+    }
+
+    pub fn block_hash(&self) -> &str {
+        match self {
+            ValidationError::UnsupportedVersion { hash, .. }
+            | ValidationError::WrongBranch { hash, .. }
+            | ValidationError::BadIndex { hash, .. }
+            | ValidationError::BadLink { hash, .. }
+            | ValidationError::TimestampOutOfRange { hash, .. }
+            | ValidationError::BadHash { hash, .. }
+            | ValidationError::BadDifficulty { hash, .. }
+            | ValidationError::InsufficientWork { hash, .. }
+            | ValidationError::TargetTooEasy { hash, .. }
+            | ValidationError::BadMerkleRoot { hash, .. }
+            | ValidationError::BadTransaction { hash, .. }
+            | ValidationError::MissingCoinbase { hash, .. }
+            | ValidationError::BadCoinbase { hash, .. }
+            | ValidationError::BadClaim { hash, .. }
+            | ValidationError::BadNonce { hash, .. }
+            | ValidationError::Overspend { hash, .. } => hash,
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "block {} ({}): ", self.block_index(), self.block_hash())?;
+        match self {
+            ValidationError::UnsupportedVersion { version, .. } => {
+                write!(f, "has unsupported version {}", version)
+            }
+            ValidationError::WrongBranch {
+                expected, found, ..
+            } => write!(f, "belongs to branch {}, not {}", found, expected),
+            ValidationError::BadIndex { expected, .. } => {
+                write!(f, "expected index {}", expected)
+            }
+            ValidationError::BadLink {
+                expected_previous,
+                found_previous,
+                ..
+            } => write!(
+                f,
+                "links to {}, expected {}",
+                found_previous, expected_previous
+            ),
+            ValidationError::TimestampOutOfRange {
+                timestamp,
+                min,
+                max,
+                ..
+            } => write!(f, "timestamp {} outside [{}, {}]", timestamp, min, max),
+            ValidationError::BadHash { computed, .. } => {
+                write!(f, "hash does not match contents ({})", computed)
+            }
+            ValidationError::BadDifficulty {
+                expected_bits,
+                found_bits,
+                ..
+            } => write!(
+                f,
+                "bits {:#010x}, expected {:#010x}",
+                found_bits, expected_bits
+            ),
+            ValidationError::InsufficientWork { bits, .. } => {
+                write!(f, "hash does not meet target {:#010x}", bits)
+            }
+            ValidationError::TargetTooEasy {
+                bits, limit_bits, ..
+            } => write!(
+                f,
+                "target {:#010x} is easier than {:#010x}",
+                bits, limit_bits
+            ),
+            ValidationError::BadMerkleRoot {
+                expected, found, ..
+            } => write!(f, "merkle root {}, expected {}", found, expected),
+            ValidationError::BadTransaction {
+                position, tx_id, ..
+            } => write!(f, "transaction {} ({}) is invalid", position, tx_id),
+            ValidationError::MissingCoinbase { .. } => {
+                write!(f, "first transaction is not a coinbase")
+            }
+            ValidationError::BadCoinbase {
+                expected_reward,
+                found_reward,
+                ..
+            } => write!(
+                f,
+                "coinbase pays {}, schedule allows {}",
+                found_reward, expected_reward
+            ),
+            ValidationError::BadClaim {
+                position,
+                tx_id,
+                reason,
+                ..
+            } => write!(f, "claim {} ({}) is invalid: {}", position, tx_id, reason),
+            ValidationError::BadNonce {
+                position,
+                tx_id,
+                expected,
+                found,
+                ..
+            } => write!(
+                f,
+                "transaction {} ({}) has nonce {}, expected {}",
+                position, tx_id, found, expected
+            ),
+            ValidationError::Overspend {
+                position,
+                tx_id,
+                balance,
+                required,
+                ..
+            } => write!(
+                f,
+                "transaction {} ({}) spends {} with balance {}",
+                position, tx_id, required, balance
+            ),
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use crate::block::Block;
+use crate::difficulty::U256;
+use crate::error::BlocktreeError;
+use std::collections::{HashMap, VecDeque};
+
+/// Blocks whose parent is unknown that a branch buffers before dropping the
+/// one with the least work.
+pub const MAX_ORPHANS: usize = 64;
+
+/// What processing a block did to its branch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BlockStatus {
+    /// Appended to the active chain.
+    Extended,
+    /// Made a heavier competing chain active, rolling back `disconnected`
+    /// blocks of the old one and applying `connected` blocks of the new one.
+    Reorganized {
+        disconnected: usize,
+        connected: usize,
+    },
+    /// Kept on a competing chain with no more work than the active one.
+    SideChain,
+    /// Buffered until its parent arrives.
+    Orphan,
+    /// Already known.
+    Duplicate,
+}
+
+struct DagEntry {
+    block: Block,
+    chain_work: U256, // Work of the chain from the branch's first block to this one
+}
+
+/// Every known block of one branch, linked by `previous_hash`, so competing
+/// blocks at the same height can coexist and the chain with the most
+/// cumulative work can be chosen. Only the active chain is persisted; side
+/// chains and orphans live in memory.
+#[derive(Default)]
+pub struct BlockDag {
+    entries: HashMap<String, DagEntry>,
+    orphans: VecDeque<Block>,
+}
+
+impl BlockDag {
+    /// DAG of a branch's active chain, its first block being the base.
+    pub fn from_chain(chain: &[Block]) -> Result<Self, BlocktreeError> {
+        let mut dag = BlockDag::default();
+        let base = chain
+            .first()
+            .ok_or_else(|| BlocktreeError::BranchNotFound("Empty branch".to_string()))?;
+        dag.entries.insert(
+            base.hash.clone(),
+            DagEntry {
+                block: base.clone(),
+                chain_work: base.work()?,
+            },
+        );
+        for block in &chain[1..] {
+            dag.insert(block.clone())?;
+        }
+        Ok(dag)
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.entries.contains_key(hash)
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&Block> {
+        self.entries.get(hash).map(|entry| &entry.block)
+    }
+
+    pub fn chain_work(&self, hash: &str) -> Option<U256> {
+        self.entries.get(hash).map(|entry| entry.chain_work)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Adds a block whose parent is known, returning its chain work.
+    pub fn insert(&mut self, block: Block) -> Result<U256, BlocktreeError> {
+        let parent_work = self
+            .chain_work(&block.header.previous_hash)
+            .ok_or_else(|| {
+                BlocktreeError::InvalidPreviousHash(format!(
+                    "Parent {} of block {} is unknown",
+                    block.header.previous_hash, block.hash
+                ))
+            })?;
+        let chain_work = parent_work.saturating_add(block.work()?);
+        self.entries
+            .insert(block.hash.clone(), DagEntry { block, chain_work });
+        Ok(chain_work)
+    }
+
+    /// Removes `hash` and every block built on it, e.g. once it proved
+    /// invalid. Returns how many blocks were removed.
+    pub fn remove_with_descendants(&mut self, hash: &str) -> usize {
+        let mut doomed = vec![hash.to_string()];
+        let mut removed = 0;
+        while let Some(hash) = doomed.pop() {
+            if self.entries.remove(&hash).is_some() {
+                removed += 1;
+                doomed.extend(
+                    self.entries
+                        .values()
+                        .filter(|entry| entry.block.header.previous_hash == hash)
+                        .map(|entry| entry.block.hash.clone()),
+                );
+            }
+        }
+        removed
+    }
+
+    /// Blocks from the branch's base up to and including `hash`.
+    pub fn chain_to(&self, hash: &str) -> Vec<Block> {
+        let mut chain = Vec::new();
+        let mut next = self.entries.get(hash);
+        while let Some(entry) = next {
+            chain.push(entry.block.clone());
+            next = self.entries.get(&entry.block.header.previous_hash);
+        }
+        chain.reverse();
+        chain
+    }
+
+    /// Up to `count` blocks ending at `hash`, oldest first and without their
+    /// transactions: enough to retarget difficulty after them.
+    pub fn headers_to(&self, hash: &str, count: usize) -> Vec<Block> {
+        let mut chain = Vec::new();
+        let mut next = self.entries.get(hash);
+        while let Some(entry) = next.filter(|_| chain.len() < count) {
+            chain.push(Block {
+                header: entry.block.header.clone(),
+                transactions: Vec::new(),
+                hash: entry.block.hash.clone(),
+            });
+            next = self.entries.get(&entry.block.header.previous_hash);
+        }
+        chain.reverse();
+        chain
+    }
+
+    /// Where the chain ending at `tip` leaves `active`: the position in
+    /// `active` of their last common block, and the blocks after it leading
+    /// to `tip`.
+    pub fn fork_from(
+        &self,
+        active: &[Block],
+        tip: &str,
+    ) -> Result<(usize, Vec<Block>), BlocktreeError> {
+        let positions: HashMap<&str, usize> = active
+            .iter()
+            .enumerate()
+            .map(|(position, block)| (block.hash.as_str(), position))
+            .collect();
+        let mut branch = Vec::new();
+        let mut hash = tip.to_string();
+        loop {
+            if let Some(&position) = positions.get(hash.as_str()) {
+                branch.reverse();
+                return Ok((position, branch));
+            }
+            let block = self.get(&hash).ok_or_else(|| {
+                BlocktreeError::InvalidPreviousHash(format!(
+                    "Block {} does not connect to the active chain",
+                    tip
+                ))
+            })?;
+            hash = block.header.previous_hash.clone();
+            branch.push(block.clone());
+        }
+    }
+
+    /// Buffers a block whose parent has not arrived yet. Once `MAX_ORPHANS`
+    /// are waiting, the one with the least work is dropped, the oldest of
+    /// equals first, so flooding the buffer takes more work than is in it.
+    pub fn add_orphan(&mut self, block: Block) -> Result<(), BlocktreeError> {
+        if self.orphans.len() >= MAX_ORPHANS {
+            let mut lightest: Option<(usize, U256)> = None;
+            for (position, orphan) in self.orphans.iter().enumerate() {
+                let work = orphan.work()?;
+                if lightest.is_none_or(|(_, least)| work < least) {
+                    lightest = Some((position, work));
+                }
+            }
+            if let Some((position, least)) = lightest {
+                if block.work()? < least {
+                    return Ok(());
+                }
+                self.orphans.remove(position);
+            }
+        }
+        self.orphans.push_back(block);
+        Ok(())
+    }
+
+    pub fn is_orphan(&self, hash: &str) -> bool {
+        self.orphans.iter().any(|block| block.hash == hash)
+    }
+
+    pub fn orphan_count(&self) -> usize {
+        self.orphans.len()
+    }
+
+    /// Removes and returns the orphans whose parent is `hash`.
+    pub fn take_orphans(&mut self, hash: &str) -> Vec<Block> {
+        let mut children = Vec::new();
+        for block in std::mem::take(&mut self.orphans) {
+            if block.header.previous_hash == hash {
+                children.push(block);
+            } else {
+                self.orphans.push_back(block);
+            }
+        }
+        children
+    }
+}
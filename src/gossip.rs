@@ -0,0 +1,194 @@
+//! Spreading transactions between peers by inventory.
+//!
+//! A node announces the ids of transactions it accepts, and peers ask for
+//! those they have not seen. Every id accepted recently is remembered, so a
+//! transaction is fetched from at most one peer and never announced twice.
+//! A transaction nobody asked for is dropped. A peer that does not deliver
+//! what it announced in time, allowing for the round trip over its link, is
+//! passed over for the next peer announcing it; what it delivers late is
+//! still taken if not seen by then.
+
+use crate::network::PeerId;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+
+/// Most transaction ids announced or asked for in one message.
+pub const MAX_INVENTORY: usize = 1000;
+/// Most transaction ids remembered as seen.
+pub const MAX_KNOWN_TRANSACTIONS: usize = 50_000;
+/// Most transactions asked for and not yet received.
+pub const MAX_REQUESTED_TRANSACTIONS: usize = 10_000;
+/// Most peers remembered as announcing one transaction, to ask next.
+pub const MAX_ANNOUNCERS: usize = 8;
+/// How long a peer has to answer a request, such as for a transaction, on
+/// top of the round trip over its link, in milliseconds.
+pub const REQUEST_TIMEOUT_MS: i64 = 10_000;
+
+/// Time a peer `latency_ms` away has to answer a request, in milliseconds.
+/// An unmeasured link gets `REQUEST_TIMEOUT_MS` alone.
+pub fn request_timeout(latency_ms: f64) -> i64 {
+    if latency_ms.is_finite() && latency_ms > 0.0 {
+        REQUEST_TIMEOUT_MS.saturating_add((2.0 * latency_ms).ceil() as i64)
+    } else {
+        REQUEST_TIMEOUT_MS
+    }
+}
+
+/// Transactions seen so far and those asked of a peer and not yet received.
+#[derive(Default)]
+pub struct TxGossip {
+    known: HashSet<String>,
+    order: VecDeque<String>, // Known ids, oldest first
+    requested: HashMap<String, Request>,
+    deadlines: BinaryHeap<Reverse<(i64, String)>>, // Earliest first
+    late: HashSet<(String, PeerId)>, // Requests that expired, still taken if delivered
+    late_order: VecDeque<(String, PeerId)>, // Oldest first
+}
+
+struct Request {
+    branch_id: String,
+    peer: PeerId,
+    deadline: i64,
+    announcers: VecDeque<PeerId>, // Others announcing it, asked in turn on timeout
+}
+
+impl TxGossip {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ids of `branch_id` announced by `peer` worth asking it for at `now`:
+    /// neither seen nor already asked of another peer. They are recorded as
+    /// asked of `peer`, due within `timeout`; ids already asked elsewhere
+    /// remember `peer` as a fallback.
+    pub fn on_inventory(
+        &mut self,
+        peer: PeerId,
+        branch_id: &str,
+        tx_ids: Vec<String>,
+        now: i64,
+        timeout: i64,
+        have: impl Fn(&str) -> bool,
+    ) -> Vec<String> {
+        let mut wanted = Vec::new();
+        for tx_id in tx_ids.into_iter().take(MAX_INVENTORY) {
+            if self.known.contains(&tx_id) || have(&tx_id) {
+                continue;
+            }
+            if let Some(request) = self.requested.get_mut(&tx_id) {
+                if request.peer != peer
+                    && !request.announcers.contains(&peer)
+                    && request.announcers.len() < MAX_ANNOUNCERS
+                {
+                    request.announcers.push_back(peer);
+                }
+                continue;
+            }
+            if self.requested.len() >= MAX_REQUESTED_TRANSACTIONS {
+                continue;
+            }
+            let deadline = now.saturating_add(timeout);
+            let request = Request {
+                branch_id: branch_id.to_string(),
+                peer,
+                deadline,
+                announcers: VecDeque::new(),
+            };
+            self.requested.insert(tx_id.clone(), request);
+            self.deadlines.push(Reverse((deadline, tx_id.clone())));
+            wanted.push(tx_id);
+        }
+        wanted
+    }
+
+    /// Requests not delivered in time by `now`, each moved on to the next
+    /// peer that announced it, given `timeout(peer)`, as
+    /// `(peer, branch_id, tx_id)`. A request with no announcer left is
+    /// dropped, so the id is asked for when next announced.
+    pub fn expire(
+        &mut self,
+        now: i64,
+        timeout: impl Fn(PeerId) -> i64,
+    ) -> Vec<(PeerId, String, String)> {
+        let mut retries = Vec::new();
+        while let Some(Reverse((deadline, _))) = self.deadlines.peek() {
+            if *deadline > now {
+                break;
+            }
+            let Reverse((deadline, tx_id)) = self.deadlines.pop().expect("peeked");
+            // Settled or asked again since
+            let request = match self.requested.get_mut(&tx_id) {
+                Some(request) if request.deadline == deadline => request,
+                _ => continue,
+            };
+            let expired = (tx_id.clone(), request.peer);
+            match request.announcers.pop_front() {
+                Some(peer) => {
+                    request.peer = peer;
+                    request.deadline = now.saturating_add(timeout(peer));
+                    retries.push((peer, request.branch_id.clone(), tx_id.clone()));
+                    self.deadlines.push(Reverse((request.deadline, tx_id)));
+                }
+                None => {
+                    self.requested.remove(&tx_id);
+                }
+            }
+            self.remember_late(expired);
+        }
+        retries
+    }
+
+    fn remember_late(&mut self, expired: (String, PeerId)) {
+        if self.late.insert(expired.clone()) {
+            self.late_order.push_back(expired);
+        }
+        if self.late_order.len() > MAX_REQUESTED_TRANSACTIONS {
+            if let Some(oldest) = self.late_order.pop_front() {
+                self.late.remove(&oldest);
+            }
+        }
+    }
+
+    /// Whether `tx_id` was asked of `peer`, including a request that expired
+    /// before a slow peer answered, as long as the transaction is not known
+    /// yet. The request is settled either way, so a rejected transaction is
+    /// asked for again when next announced.
+    pub fn on_transaction(&mut self, peer: PeerId, tx_id: &str) -> bool {
+        let key = (tx_id.to_string(), peer);
+        let asked = self.requested.get(tx_id).map(|request| request.peer) == Some(peer);
+        let late = self.late.contains(&key) && !self.known.contains(tx_id);
+        if !asked && !late {
+            return false;
+        }
+        self.late.remove(&key);
+        self.requested.remove(tx_id);
+        true
+    }
+
+    /// Records a transaction as accepted, returning `false` if it already was.
+    pub fn insert(&mut self, tx_id: &str) -> bool {
+        self.requested.remove(tx_id);
+        if !self.known.insert(tx_id.to_string()) {
+            return false;
+        }
+        self.order.push_back(tx_id.to_string());
+        if self.order.len() > MAX_KNOWN_TRANSACTIONS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.known.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    /// Forgets `peer` as an announcer. What was asked of it expires at once,
+    /// so the next `expire` asks another peer announcing those transactions.
+    pub fn remove_peer(&mut self, peer: PeerId) {
+        for (tx_id, request) in self.requested.iter_mut() {
+            request.announcers.retain(|announcer| *announcer != peer);
+            if request.peer == peer {
+                request.deadline = i64::MIN;
+                self.deadlines.push(Reverse((i64::MIN, tx_id.clone())));
+            }
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use crate::block::Block;
+use crate::crossbranch::CrossBranch;
+use crate::error::{BlocktreeError, ValidationError};
+use crate::storage::Storage;
+use crate::transaction::Transaction;
+use std::collections::{HashMap, HashSet};
+
+/// Account state of one branch: balances, the next expected nonce of every
+/// address, and the cross-branch locks already claimed on it.
+#[derive(Clone, Debug, Default)]
+pub struct Ledger {
+    balances: HashMap<String, u64>,
+    nonces: HashMap<String, u64>,
+    claimed: HashSet<String>,
+}
+
+// Why a transaction cannot be applied to a ledger
+enum Violation {
+    BadNonce { expected: u64 },
+    Overspend { balance: u64 },
+    Overflow,
+    AlreadyClaimed,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// State after the genesis block, whose transactions mint their amounts
+    /// to the receivers without debiting anyone.
+    pub fn from_genesis(genesis: &Block) -> Result<Self, BlocktreeError> {
+        let mut ledger = Ledger::new();
+        for tx in &genesis.transactions {
+            ledger.credit(&tx.receiver, tx.amount).map_err(|_| {
+                BlocktreeError::TransactionError(format!(
+                    "Genesis allocation to {} overflows",
+                    tx.receiver
+                ))
+            })?;
+        }
+        Ok(ledger)
+    }
+
+    /// Rebuilds the state at the tip of `branch_id` by replaying its blocks on
+    /// top of its parent branch's state at the split point.
+    pub fn replay_branch<S: Storage>(storage: &S, branch_id: &str) -> Result<Self, BlocktreeError> {
+        Self::replay_until(storage, branch_id, u64::MAX)
+    }
+
+    /// State after the first block of `branch_id`: the genesis state for
+    /// `root`, or the parent branch's state at the split point, since a split
+    /// branch starts with a copy of its parent's tip.
+    pub fn branch_base<S: Storage>(storage: &S, branch_id: &str) -> Result<Self, BlocktreeError> {
+        let first = storage
+            .get_branch(branch_id)
+            .ok_or_else(|| BlocktreeError::BranchNotFound(branch_id.to_string()))?
+            .first()
+            .ok_or_else(|| BlocktreeError::BranchNotFound("Empty branch".to_string()))?;
+        match parent_branch(branch_id) {
+            Some(parent) => Self::replay_until(storage, parent, first.header.index),
+            None => Self::from_genesis(first),
+        }
+    }
+
+    fn replay_until<S: Storage>(
+        storage: &S,
+        branch_id: &str,
+        last_index: u64,
+    ) -> Result<Self, BlocktreeError> {
+        let mut ledger = Self::branch_base(storage, branch_id)?;
+        let chain = storage
+            .get_branch(branch_id)
+            .ok_or_else(|| BlocktreeError::BranchNotFound(branch_id.to_string()))?;
+        for block in chain
+            .iter()
+            .skip(1)
+            .take_while(|b| b.header.index <= last_index)
+        {
+            ledger.apply_block(block)?;
+        }
+        Ok(ledger)
+    }
+
+    pub fn balance_of(&self, address: &str) -> u64 {
+        self.balances.get(address).copied().unwrap_or(0)
+    }
+
+    /// Nonce the next transaction from `address` must carry.
+    pub fn nonce_of(&self, address: &str) -> u64 {
+        self.nonces.get(address).copied().unwrap_or(0)
+    }
+
+    /// True if the lock `lock_tx_id` has been claimed on this branch.
+    pub fn is_claimed(&self, lock_tx_id: &str) -> bool {
+        self.claimed.contains(lock_tx_id)
+    }
+
+    /// Applies a single transaction, rejecting replays and overspends.
+    pub fn apply_transaction(&mut self, tx: &Transaction) -> Result<(), BlocktreeError> {
+        self.try_apply(tx).map_err(|violation| {
+            BlocktreeError::TransactionError(match violation {
+                Violation::BadNonce { expected } => format!(
+                    "Transaction {} has nonce {}, expected {}",
+                    tx.tx_id, tx.nonce, expected
+                ),
+                Violation::Overspend { balance } => format!(
+                    "Transaction {} spends {} but {} holds {}",
+                    tx.tx_id,
+                    tx.total_cost().unwrap_or(u64::MAX),
+                    tx.sender,
+                    balance
+                ),
+                Violation::Overflow => format!("Transaction {} overflows a balance", tx.tx_id),
+                Violation::AlreadyClaimed => {
+                    format!("Transaction {} claims a lock claimed before", tx.tx_id)
+                }
+            })
+        })
+    }
+
+    /// Applies every transaction of a block, leaving the ledger untouched if
+    /// any of them is rejected.
+    pub fn apply_block(&mut self, block: &Block) -> Result<(), BlocktreeError> {
+        let mut next = self.clone();
+        for (position, tx) in block.transactions.iter().enumerate() {
+            next.try_apply(tx).map_err(|violation| {
+                let index = block.header.index;
+                let hash = block.hash.clone();
+                let tx_id = tx.tx_id.clone();
+                match violation {
+                    Violation::BadNonce { expected } => ValidationError::BadNonce {
+                        index,
+                        hash,
+                        position,
+                        tx_id,
+                        expected,
+                        found: tx.nonce,
+                    },
+                    Violation::Overspend { balance } => ValidationError::Overspend {
+                        index,
+                        hash,
+                        position,
+                        tx_id,
+                        balance,
+                        required: tx.total_cost().unwrap_or(u64::MAX),
+                    },
+                    Violation::Overflow => ValidationError::BadTransaction {
+                        index,
+                        hash,
+                        position,
+                        tx_id,
+                    },
+                    Violation::AlreadyClaimed => ValidationError::BadClaim {
+                        index,
+                        hash,
+                        position,
+                        tx_id,
+                        reason: "lock already claimed".to_string(),
+                    },
+                }
+            })?;
+        }
+        *self = next;
+        Ok(())
+    }
+
+    fn try_apply(&mut self, tx: &Transaction) -> Result<(), Violation> {
+        // Block validation has already checked the coinbase against the schedule
+        if tx.is_coinbase() {
+            return self.credit(&tx.receiver, tx.amount);
+        }
+        // Block validation has already checked the proof against the source
+        if let Some(CrossBranch::Claim(claim)) = &tx.cross_branch {
+            if self.claimed.contains(&claim.lock.tx_id) {
+                return Err(Violation::AlreadyClaimed);
+            }
+            self.credit(&tx.receiver, tx.amount)?;
+            self.claimed.insert(claim.lock.tx_id.clone());
+            return Ok(());
+        }
+        let expected = self.nonce_of(&tx.sender);
+        if tx.nonce != expected {
+            return Err(Violation::BadNonce { expected });
+        }
+        // The fee leaves the sender here and reaches the miner via the coinbase
+        let cost = tx.total_cost().ok_or(Violation::Overflow)?;
+        let balance = self.balance_of(&tx.sender);
+        if balance < cost {
+            return Err(Violation::Overspend { balance });
+        }
+        self.balances.insert(tx.sender.clone(), balance - cost);
+        // A lock burns the amount here; the claim mints it on the destination
+        let is_lock = matches!(tx.cross_branch, Some(CrossBranch::Lock { .. }));
+        if !is_lock {
+            if let Err(violation) = self.credit(&tx.receiver, tx.amount) {
+                self.balances.insert(tx.sender.clone(), balance);
+                return Err(violation);
+            }
+        }
+        self.nonces.insert(tx.sender.clone(), expected + 1);
+        Ok(())
+    }
+
+    fn credit(&mut self, address: &str, amount: u64) -> Result<(), Violation> {
+        let balance = self.balances.entry(address.to_string()).or_insert(0);
+        *balance = balance.checked_add(amount).ok_or(Violation::Overflow)?;
+        Ok(())
+    }
+}
+
+/// Branch a split branch was created from: `root.1.2` -> `root.1`.
+pub fn parent_branch(branch_id: &str) -> Option<&str> {
+    branch_id.rsplit_once('.').map(|(parent, _)| parent)
+}
@@ -1,10 +1,29 @@
 pub mod block;
 pub mod blocktree;
+pub mod clock;
 pub mod clustering;
 pub mod coin;
+pub mod config;
 pub mod consensus;
+pub mod crossbranch;
+pub mod difficulty;
+pub mod encoding;
 pub mod error;
+pub mod forkchoice;
+pub mod gossip;
+pub mod ledger;
+pub mod mempool;
+pub mod miner;
 pub mod network;
+pub mod p2p;
+pub mod service;
+pub mod simulation;
 pub mod storage;
+pub mod sync;
 pub mod transaction;
 pub mod tree;
+pub mod validation;
+pub mod wallet;
+
+#[cfg(test)]
+mod tests;
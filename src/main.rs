@@ -1,30 +1,89 @@
 use blocktree::blocktree::Blocktree;
+use blocktree::config::{Allocation, BlocktreeConfig};
 use blocktree::network::MockNetwork;
+use blocktree::service::BlocktreeService;
 use blocktree::transaction::Transaction;
+use blocktree::wallet::Keypair;
 
 #[tokio::main]
 async fn main() {
+    // Demo account, funded at genesis unless a genesis file is given
+    let sender = Keypair::from_seed(&[7; 32]);
+
+    // Optional genesis file (JSON or TOML) as the first argument
+    let mut config = match std::env::args().nth(1) {
+        Some(path) => BlocktreeConfig::from_file(&path).expect("Failed to load genesis file"),
+        None => {
+            let mut config = BlocktreeConfig::default();
+            config.genesis.allocations.push(Allocation {
+                address: sender.address(),
+                amount: 10_000,
+            });
+            config
+        }
+    };
+    config.miner_address = sender.address();
     let network = Box::new(MockNetwork::new());
-    let mut blocktree = Blocktree::new(network);
+    let blocktree =
+        Blocktree::from_config(config, network).expect("Failed to initialize blocktree");
 
-    // Add blocks to root branch
+    // Mine the root branch in the background while submitting transactions
+    let service = BlocktreeService::new(blocktree);
+    let mut blocks = service.subscribe_blocks();
+    service
+        .start_mining("root")
+        .expect("Failed to start mining");
+    let first_nonce = service
+        .with_blocktree(|blocktree| blocktree.nonce_of(&sender.address(), "root"))
+        .unwrap_or(0);
+    let mut pending = Vec::new();
     for i in 1..=6 {
-        println!("\nAdding block {} to root...", i);
-        let tx = Transaction::new(format!("sender{}", i), format!("receiver{}", i), 100)
-            .expect("Failed to create transaction");
-        if let Err(e) = blocktree.add_block(vec![tx], "root") {
-            println!("Error: {}", e);
+        let tx = Transaction::new_signed(
+            &sender,
+            format!("receiver{}", i),
+            100,
+            first_nonce + i - 1,
+            "root",
+        )
+        .expect("Failed to create transaction");
+        match service.submit_transaction(tx.clone(), "root").await {
+            Ok(_) => pending.push(tx.tx_id),
+            Err(e) => println!("Error: {}", e),
         }
     }
+    // Keep mining until the transactions are in and root has split
+    while !pending.is_empty() || service.with_blocktree(|b| b.get_branches().len()) < 3 {
+        match blocks.recv().await {
+            Ok(block) => {
+                println!(
+                    "Block {} on {} with {} transactions",
+                    block.header.index,
+                    block.header.branch_id,
+                    block.transactions.len()
+                );
+                pending.retain(|id| !block.transactions.iter().any(|tx| &tx.tx_id == id));
+            }
+            Err(e) => {
+                println!("Error: {}", e);
+                break;
+            }
+        }
+    }
+    let mut blocktree = service.shutdown().await.expect("Failed to shut down");
 
     // Add blocks to new branches
     let branch_ids = blocktree.get_branches();
     for branch_id in branch_ids {
         println!("\nAdding block to {}...", branch_id);
-        let tx = Transaction::new(
-            format!("sender_{}", branch_id),
+        let nonce = blocktree
+            .nonce_of(&sender.address(), &branch_id)
+            .unwrap_or(0);
+        let tx = Transaction::new_signed(
+            &sender,
             format!("receiver_{}", branch_id),
             100,
+            nonce,
+            &branch_id,
         )
         .expect("Failed to create transaction");
         if let Err(e) = blocktree.add_block(vec![tx], &branch_id) {
@@ -35,12 +94,23 @@ async fn main() {
     // Validate branches
     println!("\nValidating branches:");
     for branch_id in blocktree.get_branches() {
-        match blocktree.is_branch_valid(&branch_id) {
-            Ok(valid) => println!("Branch {} valid? {}", branch_id, valid),
+        match blocktree.validate_branch(&branch_id) {
+            Ok(report) => {
+                println!("Branch {} valid? {}", branch_id, report.is_valid());
+                for error in &report.errors {
+                    println!("  {}", error);
+                }
+            }
             Err(e) => println!("Error validating branch {}: {}", branch_id, e),
         }
     }
 
+    for branch_id in blocktree.get_branches() {
+        if let Ok(balance) = blocktree.balance_of(&sender.address(), &branch_id) {
+            println!("Sender balance on {}: {}", branch_id, balance);
+        }
+    }
+
     // Print BKT supply
     println!("\nTotal BKT supply: {}", blocktree.get_bkt_supply());
 }
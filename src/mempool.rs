@@ -0,0 +1,274 @@
+use crate::block::Block;
+use crate::crossbranch::CrossBranch;
+use crate::error::BlocktreeError;
+use crate::ledger::Ledger;
+use crate::transaction::Transaction;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+/// Default memory budget of a branch's mempool, in serialized bytes.
+pub const DEFAULT_MEMPOOL_MAX_BYTES: usize = 1 << 20;
+
+/// Recent blocks whose fee rates feed the fee estimate.
+pub const FEE_ESTIMATE_BLOCKS: usize = 10;
+
+/// Pending transactions of one branch, keyed by `tx_id` and ordered by fee
+/// rate, then arrival.
+#[derive(Clone, Debug)]
+pub struct Mempool {
+    entries: HashMap<String, Entry>,
+    max_bytes: usize,
+    total_bytes: usize,
+    next_sequence: u64,
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    tx: Transaction,
+    size: usize,
+    fee_rate: u64, // Fee per 1000 serialized bytes
+    sequence: u64, // Arrival order
+}
+
+impl Entry {
+    // Higher fee rate first, earlier arrival breaking ties
+    fn priority(&self) -> (Reverse<u64>, u64) {
+        (Reverse(self.fee_rate), self.sequence)
+    }
+}
+
+fn claimed_lock(tx: &Transaction) -> Option<&str> {
+    match &tx.cross_branch {
+        Some(CrossBranch::Claim(claim)) => Some(&claim.lock.tx_id),
+        _ => None,
+    }
+}
+
+impl Mempool {
+    pub fn new(max_bytes: usize) -> Self {
+        Mempool {
+            entries: HashMap::new(),
+            max_bytes,
+            total_bytes: 0,
+            next_sequence: 0,
+        }
+    }
+
+    /// Validates `tx` against the branch's `ledger` and queues it. Returns
+    /// `false` if the transaction is already pending.
+    ///
+    /// A pending transaction with the same sender and nonce is replaced only
+    /// by one paying a higher fee rate. When the pool is full the lowest
+    /// priority transactions are evicted to make room, unless `tx` itself
+    /// would be the lowest.
+    pub fn insert(
+        &mut self,
+        tx: Transaction,
+        branch_id: &str,
+        ledger: &Ledger,
+    ) -> Result<bool, BlocktreeError> {
+        if self.entries.contains_key(&tx.tx_id) {
+            return Ok(false);
+        }
+        if tx.branch_id != branch_id {
+            return Err(BlocktreeError::TransactionError(format!(
+                "Transaction {} belongs to branch {}, not {}",
+                tx.tx_id, tx.branch_id, branch_id
+            )));
+        }
+        if tx.is_coinbase() {
+            return Err(BlocktreeError::TransactionError(
+                "Coinbase transactions cannot be submitted".to_string(),
+            ));
+        }
+        if tx.tx_id != tx.calculate_hash()? || !tx.is_valid() {
+            return Err(BlocktreeError::TransactionError(format!(
+                "Transaction {} is malformed or badly signed",
+                tx.tx_id
+            )));
+        }
+        if let Some(lock_tx_id) = claimed_lock(&tx) {
+            if ledger.is_claimed(lock_tx_id) {
+                return Err(BlocktreeError::TransactionError(format!(
+                    "Lock {} is already claimed",
+                    lock_tx_id
+                )));
+            }
+        } else {
+            Self::check_funds(&tx, ledger)?;
+        }
+
+        let size = tx.size()?;
+        if size > self.max_bytes {
+            return Err(BlocktreeError::TransactionError(format!(
+                "Transaction {} is larger than the mempool",
+                tx.tx_id
+            )));
+        }
+        let entry = Entry {
+            fee_rate: tx.fee_rate()?,
+            size,
+            sequence: self.next_sequence,
+            tx,
+        };
+
+        // Work out what has to go before touching the pool, so a rejected
+        // transaction leaves it unchanged
+        let mut evicted = Vec::new();
+        let mut freed = 0;
+        if let Some(conflict) = self
+            .entries
+            .values()
+            .filter(|e| !e.tx.is_claim())
+            .find(|e| e.tx.sender == entry.tx.sender && e.tx.nonce == entry.tx.nonce)
+        {
+            if entry.fee_rate <= conflict.fee_rate {
+                return Err(BlocktreeError::TransactionError(format!(
+                    "Transaction {} conflicts with pending {}",
+                    entry.tx.tx_id, conflict.tx.tx_id
+                )));
+            }
+            evicted.push(conflict.tx.tx_id.clone());
+            freed += conflict.size;
+        }
+        let mut candidates: Vec<&Entry> = self
+            .entries
+            .values()
+            .filter(|e| !evicted.contains(&e.tx.tx_id))
+            .collect();
+        candidates.sort_by_key(|e| Reverse(e.priority()));
+        let mut candidates = candidates.into_iter();
+        while self.total_bytes - freed + entry.size > self.max_bytes {
+            let lowest = candidates.next().expect("entry fits in an empty pool");
+            if lowest.priority() < entry.priority() {
+                return Err(BlocktreeError::TransactionError(format!(
+                    "Mempool is full; transaction {} pays too little",
+                    entry.tx.tx_id
+                )));
+            }
+            evicted.push(lowest.tx.tx_id.clone());
+            freed += lowest.size;
+        }
+        for tx_id in evicted {
+            self.remove(&tx_id);
+        }
+
+        self.next_sequence += 1;
+        self.total_bytes += entry.size;
+        self.entries.insert(entry.tx.tx_id.clone(), entry);
+        Ok(true)
+    }
+
+    // Claims are unsigned and checked against the source branch instead
+    fn check_funds(tx: &Transaction, ledger: &Ledger) -> Result<(), BlocktreeError> {
+        let expected = ledger.nonce_of(&tx.sender);
+        if tx.nonce < expected {
+            return Err(BlocktreeError::TransactionError(format!(
+                "Transaction {} has nonce {}, expected at least {}",
+                tx.tx_id, tx.nonce, expected
+            )));
+        }
+        let balance = ledger.balance_of(&tx.sender);
+        let cost = tx.total_cost().unwrap_or(u64::MAX);
+        if cost > balance {
+            return Err(BlocktreeError::TransactionError(format!(
+                "Transaction {} spends {} but {} holds {}",
+                tx.tx_id, cost, tx.sender, balance
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, tx_id: &str) -> Option<Transaction> {
+        let entry = self.entries.remove(tx_id)?;
+        self.total_bytes -= entry.size;
+        Some(entry.tx)
+    }
+
+    /// Drops the transactions included in `block` and any others that the
+    /// branch's new `ledger` state makes stale.
+    pub fn remove_included(&mut self, block: &Block, ledger: &Ledger) {
+        for tx in &block.transactions {
+            self.remove(&tx.tx_id);
+        }
+        let stale: Vec<String> = self
+            .entries
+            .values()
+            .filter(|e| match claimed_lock(&e.tx) {
+                Some(lock_tx_id) => ledger.is_claimed(lock_tx_id),
+                None => e.tx.nonce < ledger.nonce_of(&e.tx.sender),
+            })
+            .map(|e| e.tx.tx_id.clone())
+            .collect();
+        for tx_id in stale {
+            self.remove(&tx_id);
+        }
+    }
+
+    /// Selects up to `max_transactions` pending transactions by priority,
+    /// keeping only those that apply cleanly on top of `ledger`. A
+    /// transaction waiting on a lower nonce from the same sender is picked up
+    /// once that one has been selected.
+    pub fn block_template(&self, ledger: &Ledger, max_transactions: usize) -> Vec<Transaction> {
+        let mut ledger = ledger.clone();
+        let mut pending: Vec<&Entry> = self.entries.values().collect();
+        pending.sort_by_key(|e| e.priority());
+        let mut selected = Vec::new();
+        loop {
+            let before = selected.len();
+            pending.retain(|entry| {
+                if selected.len() >= max_transactions {
+                    return true;
+                }
+                if ledger.apply_transaction(&entry.tx).is_ok() {
+                    selected.push(entry.tx.clone());
+                    return false;
+                }
+                true
+            });
+            if selected.len() == before || selected.len() >= max_transactions {
+                return selected;
+            }
+        }
+    }
+
+    /// Fee rate needed to outbid the last transaction that fits in a block of
+    /// `max_transactions`, or `None` while everything pending fits.
+    pub fn congestion_fee_rate(&self, max_transactions: usize) -> Option<u64> {
+        if self.entries.len() < max_transactions.max(1) {
+            return None;
+        }
+        let mut rates: Vec<u64> = self.entries.values().map(|e| e.fee_rate).collect();
+        rates.sort_unstable_by(|a, b| b.cmp(a));
+        rates
+            .get(max_transactions.saturating_sub(1))
+            .map(|rate| rate.saturating_add(1))
+    }
+
+    pub fn contains(&self, tx_id: &str) -> bool {
+        self.entries.contains_key(tx_id)
+    }
+
+    pub fn get(&self, tx_id: &str) -> Option<&Transaction> {
+        self.entries.get(tx_id).map(|entry| &entry.tx)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Serialized size of all pending transactions.
+    pub fn size_bytes(&self) -> usize {
+        self.total_bytes
+    }
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        Self::new(DEFAULT_MEMPOOL_MAX_BYTES)
+    }
+}
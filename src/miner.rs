@@ -0,0 +1,191 @@
+use crate::block::Block;
+use crate::difficulty::{self, U256};
+use crate::error::BlocktreeError;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Shared flag that stops a mining job, e.g. once a competing block extends
+/// the branch being mined. Clones observe the same flag.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Work done by one mining job.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MiningStats {
+    pub hashes: u64,
+    pub elapsed: Duration,
+}
+
+impl MiningStats {
+    /// Hashes per second over the job.
+    pub fn hashrate(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 {
+            self.hashes as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+}
+
+/// A block whose hash meets its target, with the work it took.
+#[derive(Clone, Debug)]
+pub struct MinedBlock {
+    pub block: Block,
+    pub stats: MiningStats,
+}
+
+/// Multithreaded proof-of-work search. Each worker owns a contiguous slice of
+/// the nonce space; once its slice is exhausted it moves the header timestamp
+/// forward a millisecond and scans the slice again with what is now a
+/// different header. With one thread the search is deterministic: the same
+/// block always mines to the same nonce and timestamp.
+#[derive(Clone, Debug)]
+pub struct Miner {
+    threads: usize,
+    nonce_space: u64, // Nonces tried per timestamp, split across workers
+}
+
+impl Miner {
+    /// Miner with `threads` workers; `0` uses every available core.
+    pub fn new(threads: usize) -> Self {
+        let threads = if threads == 0 {
+            thread::available_parallelism().map_or(1, |n| n.get())
+        } else {
+            threads
+        };
+        Miner {
+            threads,
+            nonce_space: u64::MAX,
+        }
+    }
+
+    /// Limits the nonces tried before timestamps roll over, mainly so tests
+    /// can exercise the rollover.
+    pub fn with_nonce_space(mut self, nonce_space: u64) -> Self {
+        self.nonce_space = nonce_space.max(1);
+        self
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Searches for a nonce (and if need be a later timestamp) giving `block`
+    /// a hash within its target, until one is found or `cancel` fires.
+    pub fn mine(&self, block: Block, cancel: &CancelToken) -> Result<MinedBlock, BlocktreeError> {
+        let target = difficulty::compact_to_target(block.header.bits)?;
+        let workers = (self.threads as u64).clamp(1, self.nonce_space);
+        let slice = self.nonce_space / workers;
+        let start = Instant::now();
+        let hashes = AtomicU64::new(0);
+        let done = AtomicBool::new(false);
+        let found: Mutex<Option<Block>> = Mutex::new(None);
+        let errors: Mutex<Vec<BlocktreeError>> = Mutex::new(Vec::new());
+        thread::scope(|scope| {
+            for worker in 0..workers {
+                let first = worker * slice;
+                // The last worker also takes the remainder of the division
+                let end = if worker + 1 == workers {
+                    self.nonce_space
+                } else {
+                    first + slice
+                };
+                let mut candidate = block.clone();
+                let (hashes, done, found, errors) = (&hashes, &done, &found, &errors);
+                scope.spawn(move || {
+                    let mut count = 0;
+                    let result = search(
+                        &mut candidate,
+                        first..end,
+                        &target,
+                        cancel,
+                        done,
+                        &mut count,
+                    );
+                    hashes.fetch_add(count, Ordering::Relaxed);
+                    match result {
+                        Ok(true) => {
+                            let mut found = found.lock().unwrap();
+                            if found.is_none() {
+                                *found = Some(candidate);
+                            }
+                        }
+                        Ok(false) => {}
+                        Err(e) => errors.lock().unwrap().push(e),
+                    }
+                    done.store(true, Ordering::Relaxed);
+                });
+            }
+        });
+        let stats = MiningStats {
+            hashes: hashes.into_inner(),
+            elapsed: start.elapsed(),
+        };
+        if let Some(block) = found.into_inner().unwrap() {
+            return Ok(MinedBlock { block, stats });
+        }
+        if let Some(e) = errors.into_inner().unwrap().into_iter().next() {
+            return Err(e);
+        }
+        Err(BlocktreeError::MiningCancelled(format!(
+            "Block {} on branch {} after {} hashes",
+            block.header.index, block.header.branch_id, stats.hashes
+        )))
+    }
+}
+
+impl Default for Miner {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+// Scans the nonces of one worker, rolling the timestamp forward a millisecond
+// each time they run out, and counts hashes into `count`. Returns whether `block` now meets
+// `target`.
+fn search(
+    block: &mut Block,
+    nonces: Range<u64>,
+    target: &U256,
+    cancel: &CancelToken,
+    done: &AtomicBool,
+    count: &mut u64,
+) -> Result<bool, BlocktreeError> {
+    loop {
+        let mut solved = false;
+        for nonce in nonces.clone() {
+            if cancel.is_cancelled() || done.load(Ordering::Relaxed) {
+                break;
+            }
+            block.header.nonce = nonce;
+            block.update_hash()?;
+            *count += 1;
+            if U256::from_hex(&block.hash)? <= *target {
+                solved = true;
+                break;
+            }
+        }
+        if solved || cancel.is_cancelled() || done.load(Ordering::Relaxed) {
+            return Ok(solved);
+        }
+        block.header.timestamp += 1;
+    }
+}
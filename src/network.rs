@@ -1,21 +1,290 @@
-use crate::block::Block;
+use crate::block::{Block, BlockHeader};
+use crate::encoding::{self, Decode, Encode, Reader};
 use crate::error::BlocktreeError;
+use crate::transaction::Transaction;
 use rand::Rng;
 use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
 
-pub trait Network {
+/// Version of the wire protocol, checked in the handshake.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Id `get_latency` uses for this node; peers are numbered from 1.
+pub const LOCAL_NODE: PeerId = 0;
+
+pub type PeerId = u32;
+
+pub trait Network: Send {
     fn broadcast_block(&self, block: Block) -> Result<(), BlocktreeError>;
+
+    /// Announces a new transaction of `branch_id` to every peer, which asks
+    /// for it if it has not seen it.
+    fn broadcast_transaction(&self, branch_id: &str, tx_id: &str) -> Result<(), BlocktreeError>;
+
     fn get_latency(&self, node1: u32, node2: u32) -> f64;
+
+    /// Queues `message` for one peer.
+    fn send_to(&self, peer: PeerId, message: Message) -> Result<(), BlocktreeError>;
+
+    /// Peers currently connected.
+    fn peer_ids(&self) -> Vec<PeerId>;
+
+    /// Branches this node holds, advertised to peers it connects to.
+    fn announce_branches(&self, _branches: &[String]) {}
+
+    /// Receiver of what peers send, for `Blocktree::handle_network_event`.
+    /// `None` once taken, or for a network that receives nothing.
+    fn take_events(&mut self) -> Option<mpsc::Receiver<NetworkEvent>> {
+        None
+    }
+}
+
+/// Something that happened on the network, for the blocktree to act on.
+#[derive(Clone, Debug)]
+pub enum NetworkEvent {
+    PeerConnected {
+        peer: PeerId,
+        branches: Vec<String>,
+    },
+    PeerDisconnected(PeerId),
+    Block {
+        peer: PeerId,
+        block: Block,
+    },
+    Transaction {
+        peer: PeerId,
+        branch_id: String,
+        transaction: Transaction,
+    },
+    /// Any other message, e.g. of the sync protocol.
+    Message {
+        peer: PeerId,
+        message: Message,
+    },
+}
+
+impl NetworkEvent {
+    /// Event for `message` received from `peer`.
+    pub fn from_message(peer: PeerId, message: Message) -> Self {
+        match message {
+            Message::Block(block) => NetworkEvent::Block { peer, block },
+            Message::Transaction {
+                branch_id,
+                transaction,
+            } => NetworkEvent::Transaction {
+                peer,
+                branch_id,
+                transaction,
+            },
+            message => NetworkEvent::Message { peer, message },
+        }
+    }
+}
+
+/// What a node tells a peer about itself on connecting.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Handshake {
+    pub version: u32,
+    pub genesis_hash: String,
+    pub branches: Vec<String>,
+}
+
+/// Active chain of a branch as advertised to peers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BranchSummary {
+    pub branch_id: String,
+    pub length: u64,
+    pub tip_hash: String,
+}
+
+/// Messages exchanged between peers, encoded as a `u8` tag followed by the
+/// variant's fields.
+#[derive(Clone, Debug)]
+pub enum Message {
+    Handshake(Handshake),
+    Block(Block),
+    Ping(u64),
+    Pong(u64),
+    GetBranches,
+    Branches(Vec<BranchSummary>),
+    /// Asks for headers of `branch_id` after the first `locator` hash the
+    /// peer has on its active chain.
+    GetHeaders {
+        branch_id: String,
+        locator: Vec<String>,
+    },
+    Headers {
+        branch_id: String,
+        headers: Vec<BlockHeader>,
+    },
+    GetBlocks {
+        branch_id: String,
+        hashes: Vec<String>,
+    },
+    /// The requested blocks the peer has, in request order.
+    Blocks {
+        branch_id: String,
+        blocks: Vec<Block>,
+    },
+    Transaction {
+        branch_id: String,
+        transaction: Transaction,
+    },
+    /// Ids of transactions of `branch_id` the peer has accepted.
+    Inventory {
+        branch_id: String,
+        tx_ids: Vec<String>,
+    },
+    /// Asks for announced transactions, each sent back as a `Transaction`.
+    GetTransactions {
+        branch_id: String,
+        tx_ids: Vec<String>,
+    },
+}
+
+impl Encode for BranchSummary {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.branch_id.encode(out);
+        self.length.encode(out);
+        self.tip_hash.encode(out);
+    }
+}
+
+impl Decode for BranchSummary {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, BlocktreeError> {
+        Ok(BranchSummary {
+            branch_id: Decode::decode(reader)?,
+            length: Decode::decode(reader)?,
+            tip_hash: Decode::decode(reader)?,
+        })
+    }
+}
+
+impl Encode for Message {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Message::Handshake(handshake) => {
+                0u8.encode(out);
+                handshake.version.encode(out);
+                handshake.genesis_hash.encode(out);
+                handshake.branches.encode(out);
+            }
+            Message::Block(block) => {
+                1u8.encode(out);
+                block.encode(out);
+            }
+            Message::Ping(nonce) => {
+                2u8.encode(out);
+                nonce.encode(out);
+            }
+            Message::Pong(nonce) => {
+                3u8.encode(out);
+                nonce.encode(out);
+            }
+            Message::GetBranches => 4u8.encode(out),
+            Message::Branches(branches) => {
+                5u8.encode(out);
+                branches.encode(out);
+            }
+            Message::GetHeaders { branch_id, locator } => {
+                6u8.encode(out);
+                branch_id.encode(out);
+                locator.encode(out);
+            }
+            Message::Headers { branch_id, headers } => {
+                7u8.encode(out);
+                branch_id.encode(out);
+                headers.encode(out);
+            }
+            Message::GetBlocks { branch_id, hashes } => {
+                8u8.encode(out);
+                branch_id.encode(out);
+                hashes.encode(out);
+            }
+            Message::Blocks { branch_id, blocks } => {
+                9u8.encode(out);
+                branch_id.encode(out);
+                blocks.encode(out);
+            }
+            Message::Transaction {
+                branch_id,
+                transaction,
+            } => {
+                10u8.encode(out);
+                branch_id.encode(out);
+                transaction.encode(out);
+            }
+            Message::Inventory { branch_id, tx_ids } => {
+                11u8.encode(out);
+                branch_id.encode(out);
+                tx_ids.encode(out);
+            }
+            Message::GetTransactions { branch_id, tx_ids } => {
+                12u8.encode(out);
+                branch_id.encode(out);
+                tx_ids.encode(out);
+            }
+        }
+    }
+}
+
+impl Decode for Message {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, BlocktreeError> {
+        match u8::decode(reader)? {
+            0 => Ok(Message::Handshake(Handshake {
+                version: Decode::decode(reader)?,
+                genesis_hash: Decode::decode(reader)?,
+                branches: Decode::decode(reader)?,
+            })),
+            1 => Ok(Message::Block(Decode::decode(reader)?)),
+            2 => Ok(Message::Ping(Decode::decode(reader)?)),
+            3 => Ok(Message::Pong(Decode::decode(reader)?)),
+            4 => Ok(Message::GetBranches),
+            5 => Ok(Message::Branches(Decode::decode(reader)?)),
+            6 => Ok(Message::GetHeaders {
+                branch_id: Decode::decode(reader)?,
+                locator: Decode::decode(reader)?,
+            }),
+            7 => Ok(Message::Headers {
+                branch_id: Decode::decode(reader)?,
+                headers: Decode::decode(reader)?,
+            }),
+            8 => Ok(Message::GetBlocks {
+                branch_id: Decode::decode(reader)?,
+                hashes: Decode::decode(reader)?,
+            }),
+            9 => Ok(Message::Blocks {
+                branch_id: Decode::decode(reader)?,
+                blocks: Decode::decode(reader)?,
+            }),
+            10 => Ok(Message::Transaction {
+                branch_id: Decode::decode(reader)?,
+                transaction: Decode::decode(reader)?,
+            }),
+            11 => Ok(Message::Inventory {
+                branch_id: Decode::decode(reader)?,
+                tx_ids: Decode::decode(reader)?,
+            }),
+            12 => Ok(Message::GetTransactions {
+                branch_id: Decode::decode(reader)?,
+                tx_ids: Decode::decode(reader)?,
+            }),
+            tag => Err(BlocktreeError::SerializationError(format!(
+                "Invalid encoding: unknown message tag {}",
+                tag
+            ))),
+        }
+    }
 }
 
 pub struct MockNetwork {
-    sender: mpsc::Sender<Block>,
+    sender: mpsc::Sender<Vec<u8>>, // Blocks in their canonical wire encoding
+    _receiver: mpsc::Receiver<Vec<u8>>, // Kept alive so broadcasts don't fail with a closed channel
 }
 
 impl MockNetwork {
     pub fn new() -> Self {
         let (sender, _receiver) = mpsc::channel(100);
-        MockNetwork { sender }
+        MockNetwork { sender, _receiver }
     }
 }
 
@@ -27,13 +296,29 @@ impl Default for MockNetwork {
 
 impl Network for MockNetwork {
     fn broadcast_block(&self, block: Block) -> Result<(), BlocktreeError> {
-        self.sender
-            .try_send(block)
-            .map_err(|e| BlocktreeError::NetworkError(e.to_string()))?;
+        match self.sender.try_send(encoding::to_bytes(&block)) {
+            // Nobody drains the mock channel, so a full buffer is not an error
+            Ok(()) | Err(TrySendError::Full(_)) => Ok(()),
+            Err(e) => Err(BlocktreeError::NetworkError(e.to_string())),
+        }
+    }
+
+    fn broadcast_transaction(&self, _branch_id: &str, _tx_id: &str) -> Result<(), BlocktreeError> {
         Ok(())
     }
 
     fn get_latency(&self, _node1: u32, _node2: u32) -> f64 {
         rand::thread_rng().gen_range(10.0..100.0) // Mocked latency
     }
+
+    fn send_to(&self, peer: PeerId, _message: Message) -> Result<(), BlocktreeError> {
+        Err(BlocktreeError::NetworkError(format!(
+            "Unknown peer {}",
+            peer
+        )))
+    }
+
+    fn peer_ids(&self) -> Vec<PeerId> {
+        Vec::new()
+    }
 }
@@ -0,0 +1,472 @@
+//! TCP peer-to-peer implementation of `Network`.
+//!
+//! Every message is a frame: a `u32` little-endian payload length followed by
+//! the `Message` in the canonical encoding. On connecting, both sides send a
+//! `Handshake` and drop the connection unless the other speaks the same
+//! protocol version and shares their genesis block. Until then frames are
+//! limited to `MAX_HANDSHAKE_BYTES`, and connections still handshaking count
+//! against the peer limit. A disconnection is always reported, even while
+//! the event queue is full.
+
+use crate::block::Block;
+use crate::encoding;
+use crate::error::BlocktreeError;
+use crate::network::{
+    Handshake, Message, Network, NetworkEvent, PeerId, LOCAL_NODE, PROTOCOL_VERSION,
+};
+use rand::Rng;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+/// Largest frame payload accepted from a peer.
+pub const MAX_FRAME_BYTES: usize = 32 * 1024 * 1024;
+/// Largest frame payload accepted before the handshake completes.
+pub const MAX_HANDSHAKE_BYTES: usize = 8 * 1024;
+pub const DEFAULT_MAX_PEERS: usize = 32;
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+const PEER_QUEUE_CAPACITY: usize = 256; // Outgoing messages buffered per peer
+/// Events buffered before peers' messages wait to be reported.
+pub const EVENT_CAPACITY: usize = 1024;
+
+/// Writes one length-prefixed frame.
+pub async fn write_frame<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    message: &Message,
+) -> Result<(), BlocktreeError> {
+    let payload = encoding::to_bytes(message);
+    if payload.len() > MAX_FRAME_BYTES {
+        return Err(BlocktreeError::NetworkError(format!(
+            "Frame of {} bytes exceeds {} bytes",
+            payload.len(),
+            MAX_FRAME_BYTES
+        )));
+    }
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&payload);
+    writer.write_all(&frame).await.map_err(network_error)?;
+    writer.flush().await.map_err(network_error)
+}
+
+/// Reads one length-prefixed frame of at most `max_bytes`, refusing larger
+/// ones up front. The payload buffer grows only as its bytes arrive.
+pub async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    max_bytes: usize,
+) -> Result<Message, BlocktreeError> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len).await.map_err(network_error)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > max_bytes {
+        return Err(BlocktreeError::NetworkError(format!(
+            "Frame of {} bytes exceeds {} bytes",
+            len, max_bytes
+        )));
+    }
+    let mut payload = Vec::new();
+    reader
+        .take(len as u64)
+        .read_to_end(&mut payload)
+        .await
+        .map_err(network_error)?;
+    if payload.len() < len {
+        return Err(BlocktreeError::NetworkError(format!(
+            "Frame ended after {} of {} bytes",
+            payload.len(),
+            len
+        )));
+    }
+    encoding::from_bytes(&payload)
+}
+
+fn network_error(e: std::io::Error) -> BlocktreeError {
+    BlocktreeError::NetworkError(e.to_string())
+}
+
+/// A connected peer as seen by this node.
+#[derive(Clone, Debug)]
+pub struct PeerInfo {
+    pub id: PeerId,
+    pub addr: SocketAddr,
+    pub outbound: bool, // This node opened the connection
+    pub branches: Vec<String>,
+    pub latency_ms: Option<f64>, // Half the last ping round trip
+}
+
+struct Peer {
+    info: PeerInfo,
+    sender: mpsc::Sender<Message>,
+    ping: Option<(u64, Instant)>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+struct Shared {
+    local: Mutex<Handshake>,
+    peers: Mutex<HashMap<PeerId, Peer>>,
+    next_id: AtomicU32,
+    max_peers: usize,
+    handshaking: AtomicUsize, // Connections not yet in `peers`
+    events: mpsc::Sender<NetworkEvent>,
+    // Disconnections, queued without waiting and forwarded to `events`
+    lifecycle: mpsc::UnboundedSender<NetworkEvent>,
+}
+
+impl Shared {
+    fn remove_peer(&self, id: PeerId) -> bool {
+        let peer = self.peers.lock().unwrap().remove(&id);
+        match peer {
+            Some(peer) => {
+                for task in peer.tasks {
+                    task.abort();
+                }
+                // Only fails once the forwarder is gone, with nobody listening
+                let _ = self.lifecycle.send(NetworkEvent::PeerDisconnected(id));
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+// A connection counted against the peer limit while it handshakes
+struct HandshakeSlot(Arc<Shared>);
+
+impl HandshakeSlot {
+    fn reserve(shared: &Arc<Shared>) -> Result<Self, BlocktreeError> {
+        // Reserved under the peers lock, so slots and peers never exceed the limit
+        let peers = shared.peers.lock().unwrap();
+        if peers.len() + shared.handshaking.load(Ordering::SeqCst) >= shared.max_peers {
+            return Err(BlocktreeError::NetworkError(format!(
+                "Peer limit of {} reached",
+                shared.max_peers
+            )));
+        }
+        shared.handshaking.fetch_add(1, Ordering::SeqCst);
+        Ok(HandshakeSlot(shared.clone()))
+    }
+}
+
+impl Drop for HandshakeSlot {
+    fn drop(&mut self) {
+        self.0.handshaking.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Network of TCP connections to peers. Connections, disconnections and
+/// messages other than handshakes and pings are reported as events.
+pub struct TcpNetwork {
+    shared: Arc<Shared>,
+    local_addr: SocketAddr,
+    accept_task: JoinHandle<()>,
+    events: Option<mpsc::Receiver<NetworkEvent>>,
+}
+
+impl TcpNetwork {
+    /// Listens on `addr` for peers of the chain starting at `genesis_hash`.
+    /// Must be called within a tokio runtime.
+    pub async fn bind<A: ToSocketAddrs>(
+        addr: A,
+        genesis_hash: String,
+        max_peers: usize,
+    ) -> Result<Self, BlocktreeError> {
+        let listener = TcpListener::bind(addr).await.map_err(network_error)?;
+        let local_addr = listener.local_addr().map_err(network_error)?;
+        let (event_sender, events) = mpsc::channel(EVENT_CAPACITY);
+        let (lifecycle, mut lifecycle_events) = mpsc::unbounded_channel();
+        // Ends once the network is dropped and its queue drained, or nobody listens
+        let forwarded = event_sender.clone();
+        tokio::spawn(async move {
+            while let Some(event) = lifecycle_events.recv().await {
+                if forwarded.send(event).await.is_err() {
+                    break;
+                }
+            }
+        });
+        let shared = Arc::new(Shared {
+            local: Mutex::new(Handshake {
+                version: PROTOCOL_VERSION,
+                genesis_hash,
+                branches: vec!["root".to_string()],
+            }),
+            peers: Mutex::new(HashMap::new()),
+            next_id: AtomicU32::new(LOCAL_NODE + 1),
+            max_peers,
+            handshaking: AtomicUsize::new(0),
+            events: event_sender,
+            lifecycle,
+        });
+        let accept_task = tokio::spawn(accept_loop(listener, shared.clone()));
+        Ok(TcpNetwork {
+            shared,
+            local_addr,
+            accept_task,
+            events: Some(events),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Connects and handshakes with the node listening on `addr`.
+    pub async fn connect<A: ToSocketAddrs>(&self, addr: A) -> Result<PeerId, BlocktreeError> {
+        let slot = HandshakeSlot::reserve(&self.shared)?;
+        let stream = TcpStream::connect(addr).await.map_err(network_error)?;
+        add_peer(stream, slot, true).await
+    }
+
+    pub fn peers(&self) -> Vec<PeerInfo> {
+        let mut peers: Vec<PeerInfo> = self
+            .shared
+            .peers
+            .lock()
+            .unwrap()
+            .values()
+            .map(|peer| peer.info.clone())
+            .collect();
+        peers.sort_by_key(|peer| peer.id);
+        peers
+    }
+
+    /// Closes the connection to `id`, returning whether it was connected.
+    pub fn disconnect(&self, id: PeerId) -> bool {
+        self.shared.remove_peer(id)
+    }
+
+    // Queues `message` for every peer, skipping those whose queue is full
+    fn broadcast(&self, message: Message) {
+        for peer in self.shared.peers.lock().unwrap().values() {
+            if let Err(TrySendError::Full(_)) = peer.sender.try_send(message.clone()) {
+                println!("Peer {} is not keeping up, skipping message", peer.info.id);
+            }
+        }
+    }
+}
+
+impl Drop for TcpNetwork {
+    fn drop(&mut self) {
+        self.accept_task.abort();
+        let ids: Vec<PeerId> = self.shared.peers.lock().unwrap().keys().copied().collect();
+        for id in ids {
+            self.shared.remove_peer(id);
+        }
+    }
+}
+
+impl Network for TcpNetwork {
+    fn broadcast_block(&self, block: Block) -> Result<(), BlocktreeError> {
+        self.broadcast(Message::Block(block));
+        Ok(())
+    }
+
+    fn broadcast_transaction(&self, branch_id: &str, tx_id: &str) -> Result<(), BlocktreeError> {
+        self.broadcast(Message::Inventory {
+            branch_id: branch_id.to_string(),
+            tx_ids: vec![tx_id.to_string()],
+        });
+        Ok(())
+    }
+
+    fn get_latency(&self, node1: u32, node2: u32) -> f64 {
+        let peer = match (node1, node2) {
+            (a, b) if a == b => return 0.0,
+            (LOCAL_NODE, peer) | (peer, LOCAL_NODE) => peer,
+            _ => return f64::INFINITY, // Links between other nodes are not measured
+        };
+        self.shared
+            .peers
+            .lock()
+            .unwrap()
+            .get(&peer)
+            .and_then(|peer| peer.info.latency_ms)
+            .unwrap_or(f64::INFINITY)
+    }
+
+    fn send_to(&self, peer: PeerId, message: Message) -> Result<(), BlocktreeError> {
+        let peers = self.shared.peers.lock().unwrap();
+        let connection = peers
+            .get(&peer)
+            .ok_or_else(|| BlocktreeError::NetworkError(format!("Unknown peer {}", peer)))?;
+        connection
+            .sender
+            .try_send(message)
+            .map_err(|e| BlocktreeError::NetworkError(format!("Peer {}: {}", peer, e)))
+    }
+
+    fn peer_ids(&self) -> Vec<PeerId> {
+        self.peers().into_iter().map(|peer| peer.id).collect()
+    }
+
+    fn announce_branches(&self, branches: &[String]) {
+        self.shared.local.lock().unwrap().branches = branches.to_vec();
+    }
+
+    fn take_events(&mut self) -> Option<mpsc::Receiver<NetworkEvent>> {
+        self.events.take()
+    }
+}
+
+async fn accept_loop(listener: TcpListener, shared: Arc<Shared>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                // Refused before spawning anything, dropping the connection
+                let slot = match HandshakeSlot::reserve(&shared) {
+                    Ok(slot) => slot,
+                    Err(e) => {
+                        println!("Rejected peer {}: {}", addr, e);
+                        continue;
+                    }
+                };
+                tokio::spawn(async move {
+                    if let Err(e) = add_peer(stream, slot, false).await {
+                        println!("Rejected peer {}: {}", addr, e);
+                    }
+                });
+            }
+            Err(e) => println!("Failed to accept peer: {}", e),
+        }
+    }
+}
+
+// Handshakes over `stream` and starts the peer's reader and writer tasks,
+// releasing `slot` once the peer is added or refused
+async fn add_peer(
+    stream: TcpStream,
+    slot: HandshakeSlot,
+    outbound: bool,
+) -> Result<PeerId, BlocktreeError> {
+    let shared = slot.0.clone();
+    let addr = stream.peer_addr().map_err(network_error)?;
+    let (mut reader, mut writer) = stream.into_split();
+    let local = shared.local.lock().unwrap().clone();
+    write_frame(&mut writer, &Message::Handshake(local.clone())).await?;
+    let handshake = read_frame(&mut reader, MAX_HANDSHAKE_BYTES);
+    let remote = match timeout(HANDSHAKE_TIMEOUT, handshake).await {
+        Ok(Ok(Message::Handshake(remote))) => remote,
+        Ok(Ok(_)) => {
+            return Err(BlocktreeError::NetworkError(format!(
+                "Peer {} did not start with a handshake",
+                addr
+            )))
+        }
+        Ok(Err(e)) => return Err(e),
+        Err(_) => {
+            return Err(BlocktreeError::NetworkError(format!(
+                "Handshake with {} timed out",
+                addr
+            )))
+        }
+    };
+    if remote.version != PROTOCOL_VERSION {
+        return Err(BlocktreeError::NetworkError(format!(
+            "Peer {} speaks protocol version {}, expected {}",
+            addr, remote.version, PROTOCOL_VERSION
+        )));
+    }
+    if remote.genesis_hash != local.genesis_hash {
+        return Err(BlocktreeError::NetworkError(format!(
+            "Peer {} has genesis {}, expected {}",
+            addr, remote.genesis_hash, local.genesis_hash
+        )));
+    }
+
+    let (sender, mut outgoing) = mpsc::channel(PEER_QUEUE_CAPACITY);
+    let id = {
+        // Room was reserved by the slot
+        let mut peers = shared.peers.lock().unwrap();
+        let id = shared.next_id.fetch_add(1, Ordering::Relaxed);
+        let writer_task = tokio::spawn(async move {
+            while let Some(message) = outgoing.recv().await {
+                if write_frame(&mut writer, &message).await.is_err() {
+                    break;
+                }
+            }
+        });
+        let nonce = rand::thread_rng().gen();
+        // Cannot fail: the queue is new and the writer holds its receiver
+        let _ = sender.try_send(Message::Ping(nonce));
+        peers.insert(
+            id,
+            Peer {
+                info: PeerInfo {
+                    id,
+                    addr,
+                    outbound,
+                    branches: remote.branches.clone(),
+                    latency_ms: None,
+                },
+                sender,
+                ping: Some((nonce, Instant::now())),
+                tasks: vec![writer_task],
+            },
+        );
+        id
+    };
+    // Reported before the peer's first message is read
+    let _ = shared
+        .events
+        .send(NetworkEvent::PeerConnected {
+            peer: id,
+            branches: remote.branches,
+        })
+        .await;
+    let reader_shared = shared.clone();
+    let reader_task = tokio::spawn(async move {
+        while let Ok(message) = read_frame(&mut reader, MAX_FRAME_BYTES).await {
+            if !handle_message(&reader_shared, id, message).await {
+                break;
+            }
+        }
+        reader_shared.remove_peer(id);
+    });
+    match shared.peers.lock().unwrap().get_mut(&id) {
+        Some(peer) => peer.tasks.push(reader_task),
+        None => reader_task.abort(), // Disconnected meanwhile
+    }
+    Ok(id)
+}
+
+// Answers pings and forwards everything else; false drops the peer
+async fn handle_message(shared: &Shared, id: PeerId, message: Message) -> bool {
+    match message {
+        Message::Handshake(_) => false, // Only valid as the first message
+        Message::Ping(nonce) => {
+            let peers = shared.peers.lock().unwrap();
+            if let Some(peer) = peers.get(&id) {
+                let _ = peer.sender.try_send(Message::Pong(nonce));
+            }
+            true
+        }
+        Message::Pong(nonce) => {
+            let mut peers = shared.peers.lock().unwrap();
+            if let Some(peer) = peers.get_mut(&id) {
+                if let Some((sent, at)) = peer.ping {
+                    if sent == nonce {
+                        peer.info.latency_ms = Some(at.elapsed().as_secs_f64() * 1000.0 / 2.0);
+                        peer.ping = None;
+                    }
+                }
+            }
+            true
+        }
+        message => {
+            // Nobody listening is not the peer's fault
+            let _ = shared
+                .events
+                .send(NetworkEvent::from_message(id, message))
+                .await;
+            true
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use crate::block::Block;
+use crate::blocktree::Blocktree;
+use crate::clustering::Clustering;
+use crate::consensus::Consensus;
+use crate::error::BlocktreeError;
+use crate::forkchoice::BlockStatus;
+use crate::miner::CancelToken;
+use crate::network::NetworkEvent;
+use crate::storage::Storage;
+use crate::transaction::Transaction;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+/// Blocks a subscriber may fall behind by before it starts missing some.
+pub const BLOCK_CHANNEL_CAPACITY: usize = 64;
+
+// Pause before retrying after a mining round fails, e.g. on a full disk
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+// How often requests peers left unanswered are checked when no event arrives
+const EXPIRE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long `shutdown` waits for work it did not start to let go of the
+/// blocktree, such as a mining round whose `stop_mining` was abandoned.
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+// Pause between checks that shutdown holds the only reference to the blocktree
+const SHUTDOWN_POLL: Duration = Duration::from_millis(10);
+
+struct MiningTask {
+    stop: CancelToken,
+    handle: JoinHandle<()>,
+}
+
+struct NetworkTask {
+    stop: oneshot::Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+/// Async front end of a `Blocktree` for the tokio runtime. Each branch being
+/// mined gets a task whose rounds run on the blocking pool, holding the
+/// blocktree lock only to prepare and to commit blocks, so transactions can
+/// be submitted while a block is being mined. Another task hands events from
+/// the network to the blocktree as they arrive.
+pub struct BlocktreeService<S: Storage, C: Consensus, K: Clustering> {
+    blocktree: Arc<Mutex<Blocktree<S, C, K>>>,
+    consensus: Arc<C>,
+    blocks: broadcast::Sender<Block>,
+    mining: Mutex<HashMap<String, MiningTask>>,
+    network: Option<NetworkTask>,
+}
+
+impl<S, C, K> BlocktreeService<S, C, K>
+where
+    S: Storage + Send + 'static,
+    C: Consensus + Clone + Send + Sync + 'static,
+    K: Clustering + Send + 'static,
+{
+    /// Wraps `blocktree`, starting to receive its network's events. Must be
+    /// called within a tokio runtime if the network has events.
+    pub fn new(mut blocktree: Blocktree<S, C, K>) -> Self {
+        let consensus = Arc::new(blocktree.consensus().clone());
+        let (blocks, _) = broadcast::channel(BLOCK_CHANNEL_CAPACITY);
+        let events = blocktree.take_network_events();
+        let blocktree = Arc::new(Mutex::new(blocktree));
+        let network = events.map(|events| {
+            let (stop, stopped) = oneshot::channel();
+            let handle = tokio::spawn(receive_events(blocktree.clone(), events, stopped));
+            NetworkTask { stop, handle }
+        });
+        BlocktreeService {
+            blocktree,
+            consensus,
+            blocks,
+            mining: Mutex::new(HashMap::new()),
+            network,
+        }
+    }
+
+    /// Queues a transaction for the next blocks of `branch_id`.
+    pub async fn submit_transaction(
+        &self,
+        tx: Transaction,
+        branch_id: &str,
+    ) -> Result<bool, BlocktreeError> {
+        lock(&self.blocktree).submit_transaction(tx, branch_id)
+    }
+
+    /// Receives every block this service mines, on any branch.
+    pub fn subscribe_blocks(&self) -> broadcast::Receiver<Block> {
+        self.blocks.subscribe()
+    }
+
+    /// Runs `f` with exclusive access to the blocktree, e.g. to query
+    /// balances. Mining rounds wait for it at their next prepare or commit.
+    pub fn with_blocktree<R>(&self, f: impl FnOnce(&mut Blocktree<S, C, K>) -> R) -> R {
+        f(&mut lock(&self.blocktree))
+    }
+
+    /// Starts mining `branch_id` continuously, from its mempool. Does nothing
+    /// if the branch is already being mined. Must be called within a tokio
+    /// runtime.
+    pub fn start_mining(&self, branch_id: &str) -> Result<(), BlocktreeError> {
+        if !lock(&self.blocktree)
+            .get_branches()
+            .iter()
+            .any(|b| b == branch_id)
+        {
+            return Err(BlocktreeError::BranchNotFound(branch_id.to_string()));
+        }
+        let mut mining = lock(&self.mining);
+        if mining
+            .get(branch_id)
+            .is_some_and(|task| !task.handle.is_finished())
+        {
+            return Ok(());
+        }
+        let stop = CancelToken::new();
+        let handle = tokio::spawn(mine_branch(
+            self.blocktree.clone(),
+            self.consensus.clone(),
+            self.blocks.clone(),
+            branch_id.to_string(),
+            stop.clone(),
+        ));
+        mining.insert(branch_id.to_string(), MiningTask { stop, handle });
+        Ok(())
+    }
+
+    /// Stops mining `branch_id`, abandoning the block in progress, and waits
+    /// for its task to finish.
+    pub async fn stop_mining(&self, branch_id: &str) {
+        let task = lock(&self.mining).remove(branch_id);
+        if let Some(task) = task {
+            self.stop_task(branch_id, task).await;
+        }
+    }
+
+    /// Branches currently being mined.
+    pub fn mining_branches(&self) -> Vec<String> {
+        let mut branches: Vec<String> = lock(&self.mining).keys().cloned().collect();
+        branches.sort();
+        branches
+    }
+
+    /// Stops all mining and receiving, letting any commit or event in
+    /// progress finish, and hands back the blocktree. Fails if something
+    /// still holds it after `SHUTDOWN_TIMEOUT`.
+    pub async fn shutdown(mut self) -> Result<Blocktree<S, C, K>, BlocktreeError> {
+        let tasks: Vec<(String, MiningTask)> = lock(&self.mining).drain().collect();
+        for (branch_id, task) in tasks {
+            self.stop_task(&branch_id, task).await;
+        }
+        if let Some(network) = self.network.take() {
+            let _ = network.stop.send(());
+            if let Err(e) = network.handle.await {
+                println!("Network task failed: {}", e);
+            }
+        }
+        let deadline = tokio::time::Instant::now() + SHUTDOWN_TIMEOUT;
+        let mut shared = self.blocktree;
+        loop {
+            shared = match Arc::try_unwrap(shared) {
+                Ok(blocktree) => {
+                    return Ok(blocktree.into_inner().unwrap_or_else(|e| e.into_inner()))
+                }
+                Err(shared) if tokio::time::Instant::now() < deadline => shared,
+                Err(_) => {
+                    return Err(BlocktreeError::ServiceError(format!(
+                        "Blocktree still in use {:?} after shutdown",
+                        SHUTDOWN_TIMEOUT
+                    )))
+                }
+            };
+            tokio::time::sleep(SHUTDOWN_POLL).await;
+        }
+    }
+
+    async fn stop_task(&self, branch_id: &str, task: MiningTask) {
+        // Set the flag before cancelling under the lock, so a round that
+        // prepares after this sees the flag and one that prepared before is
+        // cancelled
+        task.stop.cancel();
+        lock(&self.blocktree).cancel_mining(branch_id);
+        if let Err(e) = task.handle.await {
+            println!("Mining task for {} failed: {}", branch_id, e);
+        }
+    }
+}
+
+// Recovers the data of a poisoned lock; a panicking mining round leaves the
+// blocktree as consistent as its last completed commit
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+async fn mine_branch<S, C, K>(
+    blocktree: Arc<Mutex<Blocktree<S, C, K>>>,
+    consensus: Arc<C>,
+    blocks: broadcast::Sender<Block>,
+    branch_id: String,
+    stop: CancelToken,
+) where
+    S: Storage + Send + 'static,
+    C: Consensus + Send + Sync + 'static,
+    K: Clustering + Send + 'static,
+{
+    while !stop.is_cancelled() {
+        let round = {
+            let (blocktree, consensus) = (blocktree.clone(), consensus.clone());
+            let (branch_id, stop) = (branch_id.clone(), stop.clone());
+            tokio::task::spawn_blocking(move || {
+                mine_round(&blocktree, &*consensus, &branch_id, &stop)
+            })
+        };
+        match round.await {
+            Ok(Ok(Some(block))) => {
+                // Having no subscribers is fine
+                let _ = blocks.send(block);
+            }
+            Ok(Ok(None)) => {}
+            Ok(Err(e)) => {
+                println!("Mining on {} failed: {}", branch_id, e);
+                tokio::time::sleep(RETRY_DELAY).await;
+            }
+            Err(e) => {
+                println!("Mining on {} panicked: {}", branch_id, e);
+                return;
+            }
+        }
+    }
+}
+
+// Hands network events to the blocktree in order, each on the blocking pool
+// since a block may take a while to validate, and expires unanswered
+// requests between events
+async fn receive_events<S, C, K>(
+    blocktree: Arc<Mutex<Blocktree<S, C, K>>>,
+    mut events: mpsc::Receiver<NetworkEvent>,
+    mut stop: oneshot::Receiver<()>,
+) where
+    S: Storage + Send + 'static,
+    C: Consensus + Send + Sync + 'static,
+    K: Clustering + Send + 'static,
+{
+    let mut expire = tokio::time::interval(EXPIRE_INTERVAL);
+    loop {
+        let event = tokio::select! {
+            event = events.recv() => match event {
+                Some(event) => Some(event),
+                None => return,
+            },
+            _ = expire.tick() => None,
+            _ = &mut stop => return,
+        };
+        let blocktree = blocktree.clone();
+        let handled = tokio::task::spawn_blocking(move || {
+            let mut blocktree = lock(&blocktree);
+            match event {
+                Some(event) => blocktree.handle_network_event(event),
+                None => blocktree.expire_requests(),
+            }
+        })
+        .await;
+        match handled {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => println!("Failed to handle network event: {}", e),
+            Err(e) => {
+                println!("Handling a network event panicked: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+// Mines one block of `branch_id` from its mempool. Returns `None` when the
+// round was stopped or overtaken by another block on the branch.
+fn mine_round<S: Storage, C: Consensus, K: Clustering>(
+    blocktree: &Mutex<Blocktree<S, C, K>>,
+    consensus: &C,
+    branch_id: &str,
+    stop: &CancelToken,
+) -> Result<Option<Block>, BlocktreeError> {
+    let (block, cancel) = {
+        let mut blocktree = lock(blocktree);
+        if stop.is_cancelled() {
+            return Ok(None);
+        }
+        blocktree.prepare_pending(branch_id)?
+    };
+    let mined = match consensus.mine_block(block, &cancel) {
+        Ok(mined) => mined,
+        Err(BlocktreeError::MiningCancelled(_)) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let block = mined.block.clone();
+    let mut blocktree = lock(blocktree);
+    if cancel.is_cancelled() {
+        return Ok(None);
+    }
+    match blocktree.commit_mined_block(mined, branch_id)? {
+        BlockStatus::Extended | BlockStatus::Reorganized { .. } => Ok(Some(block)),
+        _ => Ok(None),
+    }
+}
@@ -0,0 +1,438 @@
+//! Deterministic in-process network for reproducible multi-node scenarios.
+//!
+//! Every node of a `Simulator` gets a `SimNetwork`. Messages sent over a
+//! link are scheduled on a virtual clock, in milliseconds, after the link's
+//! latency and jitter and the time its bandwidth takes to carry them, or are
+//! lost at the link's loss rate. Partitions and heals are scheduled on the
+//! same clock, and peers cut off from each other see the other disconnect
+//! until the heal. Jitter and loss come from one RNG seeded up front, so the
+//! same seed and the same sends replay the same deliveries.
+//!
+//! Nodes built with `Simulator::node_builder` also read the time from the
+//! virtual clock, for block timestamps, the future-drift check, retargeting
+//! and request timeouts, and mine on a single thread, so scenarios that mine
+//! blocks replay exactly too.
+
+use crate::block::Block;
+use crate::blocktree::{Blocktree, BlocktreeBuilder};
+use crate::clock::Clock;
+use crate::clustering::Clustering;
+use crate::config::BlocktreeConfig;
+use crate::consensus::Consensus;
+use crate::encoding;
+use crate::error::BlocktreeError;
+use crate::network::{Message, Network, NetworkEvent, PeerId, LOCAL_NODE};
+use crate::storage::Storage;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Delay, throughput and reliability of a link, the same both ways.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LinkModel {
+    pub latency_ms: u64,
+    /// Extra delay drawn uniformly up to this, per message.
+    pub jitter_ms: u64,
+    /// Bytes carried per second, or `None` for no limit.
+    pub bandwidth: Option<u64>,
+    /// Chance of a message being lost, from 0 to 1.
+    pub loss: f64,
+}
+
+impl LinkModel {
+    pub fn with_latency(latency_ms: u64) -> Self {
+        LinkModel {
+            latency_ms,
+            ..Self::default()
+        }
+    }
+}
+
+impl Default for LinkModel {
+    fn default() -> Self {
+        LinkModel {
+            latency_ms: 0,
+            jitter_ms: 0,
+            bandwidth: None,
+            loss: 0.0,
+        }
+    }
+}
+
+/// Counts of what happened to the messages sent so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SimStats {
+    pub sent: usize,
+    pub delivered: usize,
+    pub lost: usize,
+    /// Sent into or caught in flight by a partition.
+    pub partitioned: usize,
+}
+
+enum Scheduled {
+    Deliver {
+        from: PeerId,
+        to: PeerId,
+        message: Box<Message>,
+    },
+    Partition(Vec<Vec<PeerId>>),
+    Heal,
+}
+
+#[derive(Default)]
+struct Link {
+    model: LinkModel,
+    busy_until: [u64; 2],   // Per direction, while carrying earlier messages
+    last_arrival: [u64; 2], // Per direction, so messages arrive in order
+}
+
+struct SimState {
+    now: u64,
+    rng: StdRng,
+    nodes: PeerId,
+    links: BTreeMap<(PeerId, PeerId), Link>, // Keyed with the lower id first
+    groups: BTreeMap<PeerId, usize>,         // Partition each node is in, if split
+    branches: BTreeMap<PeerId, Vec<String>>,
+    queue: BinaryHeap<Reverse<(u64, u64)>>, // (time, sequence) of `scheduled`
+    scheduled: BTreeMap<u64, Scheduled>,
+    next_sequence: u64,
+    due: VecDeque<(PeerId, NetworkEvent)>, // Raised by a regroup, handed out first
+    stats: SimStats,
+}
+
+impl SimState {
+    fn schedule(&mut self, time: u64, item: Scheduled) {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        self.queue.push(Reverse((time, sequence)));
+        self.scheduled.insert(sequence, item);
+    }
+
+    fn reachable(&self, a: PeerId, b: PeerId) -> bool {
+        self.groups.get(&a) == self.groups.get(&b)
+    }
+
+    fn peers_of(&self, node: PeerId) -> Vec<PeerId> {
+        self.links
+            .keys()
+            .filter_map(|&(a, b)| match node {
+                _ if node == a => Some(b),
+                _ if node == b => Some(a),
+                _ => None,
+            })
+            .filter(|&peer| self.reachable(node, peer))
+            .collect()
+    }
+
+    fn send(&mut self, from: PeerId, to: PeerId, message: Message) -> Result<(), BlocktreeError> {
+        let key = (from.min(to), from.max(to));
+        let direction = usize::from(from > to);
+        let now = self.now;
+        let size = encoding::to_bytes(&message).len() as u64;
+        if !self.links.contains_key(&key) {
+            return Err(BlocktreeError::NetworkError(format!(
+                "Node {} has no link to {}",
+                from, to
+            )));
+        }
+        self.stats.sent += 1;
+        if !self.reachable(from, to) {
+            self.stats.partitioned += 1;
+            return Ok(());
+        }
+        let model = self.links[&key].model.clone();
+        if model.loss > 0.0 && self.rng.gen_bool(model.loss.min(1.0)) {
+            self.stats.lost += 1;
+            return Ok(());
+        }
+        let jitter = match model.jitter_ms {
+            0 => 0,
+            jitter_ms => self.rng.gen_range(0..=jitter_ms),
+        };
+        let link = self.links.get_mut(&key).unwrap();
+        let start = now.max(link.busy_until[direction]);
+        let transmit = match model.bandwidth {
+            Some(bandwidth) => (size * 1000).div_ceil(bandwidth.max(1)),
+            None => 0,
+        };
+        link.busy_until[direction] = start + transmit;
+        let arrival =
+            (start + transmit + model.latency_ms + jitter).max(link.last_arrival[direction]);
+        link.last_arrival[direction] = arrival;
+        self.schedule(
+            arrival,
+            Scheduled::Deliver {
+                from,
+                to,
+                message: Box::new(message),
+            },
+        );
+        Ok(())
+    }
+
+    // Regroups the nodes, telling linked nodes that lost or regained each
+    // other; unlisted nodes share a group of their own
+    fn regroup(&mut self, groups: BTreeMap<PeerId, usize>) -> Vec<(PeerId, NetworkEvent)> {
+        let before: Vec<(PeerId, PeerId, bool)> = self
+            .links
+            .keys()
+            .map(|&(a, b)| (a, b, self.reachable(a, b)))
+            .collect();
+        self.groups = groups;
+        let mut events = Vec::new();
+        for (a, b, was_reachable) in before {
+            match (was_reachable, self.reachable(a, b)) {
+                (true, false) => {
+                    events.push((a, NetworkEvent::PeerDisconnected(b)));
+                    events.push((b, NetworkEvent::PeerDisconnected(a)));
+                }
+                (false, true) => {
+                    for (node, peer) in [(a, b), (b, a)] {
+                        let branches = self.branches.get(&peer).cloned().unwrap_or_default();
+                        events.push((node, NetworkEvent::PeerConnected { peer, branches }));
+                    }
+                }
+                _ => {}
+            }
+        }
+        events
+    }
+}
+
+/// Virtual clock and links shared by every node of a simulation.
+#[derive(Clone)]
+pub struct Simulator {
+    state: Arc<Mutex<SimState>>,
+}
+
+impl Simulator {
+    pub fn new(seed: u64) -> Self {
+        let state = SimState {
+            now: 0,
+            rng: StdRng::seed_from_u64(seed),
+            nodes: 0,
+            links: BTreeMap::new(),
+            groups: BTreeMap::new(),
+            branches: BTreeMap::new(),
+            queue: BinaryHeap::new(),
+            scheduled: BTreeMap::new(),
+            next_sequence: 0,
+            due: VecDeque::new(),
+            stats: SimStats::default(),
+        };
+        Simulator {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, SimState> {
+        self.state.lock().unwrap()
+    }
+
+    /// Network of a new node, numbered from 1 in the order they are added.
+    pub fn add_node(&self) -> SimNetwork {
+        let mut state = self.state();
+        state.nodes += 1;
+        SimNetwork {
+            id: state.nodes,
+            simulator: self.clone(),
+        }
+    }
+
+    /// Builder of the blocktree of a new node, reading the virtual clock and
+    /// mining on one thread whatever `config` asks for.
+    pub fn node_builder(&self, mut config: BlocktreeConfig) -> BlocktreeBuilder {
+        config.miner_threads = 1;
+        let network = self.add_node();
+        let clock = network.clock();
+        BlocktreeBuilder::from_config(config, Box::new(network)).clock(clock)
+    }
+
+    /// Links two nodes, making them peers, or changes the model of their
+    /// link. Messages already in flight keep the delay they were sent with.
+    pub fn link(&self, a: PeerId, b: PeerId, model: LinkModel) {
+        let mut state = self.state();
+        let link = state.links.entry((a.min(b), a.max(b))).or_default();
+        link.model = model;
+    }
+
+    /// Links every pair of nodes added so far.
+    pub fn link_all(&self, model: LinkModel) {
+        let nodes = self.state().nodes;
+        for a in 1..=nodes {
+            for b in a + 1..=nodes {
+                self.link(a, b, model.clone());
+            }
+        }
+    }
+
+    /// Splits the nodes into `groups` at `at_ms`, nodes in none of them
+    /// forming one more. Messages between groups are dropped, including
+    /// those in flight, until the next heal.
+    pub fn partition_at(&self, at_ms: u64, groups: Vec<Vec<PeerId>>) {
+        self.state().schedule(at_ms, Scheduled::Partition(groups));
+    }
+
+    /// Reconnects every node at `at_ms`.
+    pub fn heal_at(&self, at_ms: u64) {
+        self.state().schedule(at_ms, Scheduled::Heal);
+    }
+
+    /// Virtual time in milliseconds.
+    pub fn now(&self) -> u64 {
+        self.state().now
+    }
+
+    pub fn stats(&self) -> SimStats {
+        self.state().stats
+    }
+
+    /// Advances the clock to the next event due no later than `until_ms`,
+    /// returning the node it is for along with the event.
+    pub fn next_event(&self, until_ms: u64) -> Option<(PeerId, NetworkEvent)> {
+        let mut state = self.state();
+        loop {
+            if let Some(due) = state.due.pop_front() {
+                return Some(due);
+            }
+            let Reverse((time, sequence)) = *state.queue.peek()?;
+            if time > until_ms {
+                return None;
+            }
+            state.queue.pop();
+            state.now = state.now.max(time);
+            let item = state.scheduled.remove(&sequence)?;
+            let due = match item {
+                Scheduled::Deliver { from, to, message } => {
+                    if !state.reachable(from, to) {
+                        state.stats.partitioned += 1;
+                        continue;
+                    }
+                    state.stats.delivered += 1;
+                    return Some((to, NetworkEvent::from_message(from, *message)));
+                }
+                Scheduled::Partition(groups) => {
+                    let groups = groups
+                        .into_iter()
+                        .enumerate()
+                        .flat_map(|(group, nodes)| nodes.into_iter().map(move |node| (node, group)))
+                        .collect();
+                    state.regroup(groups)
+                }
+                Scheduled::Heal => state.regroup(BTreeMap::new()),
+            };
+            state.due = due.into();
+        }
+    }
+
+    /// Hands every event due by `until_ms` to its node, at `nodes[id - 1]`,
+    /// then moves the clock to `until_ms`. Returns how many were handled.
+    pub fn run_until<S: Storage, C: Consensus, K: Clustering>(
+        &self,
+        until_ms: u64,
+        nodes: &mut [Blocktree<S, C, K>],
+    ) -> Result<usize, BlocktreeError> {
+        let handled = self.deliver(until_ms, nodes)?;
+        let mut state = self.state();
+        state.now = state.now.max(until_ms);
+        Ok(handled)
+    }
+
+    /// Runs until nothing is left to deliver or scheduled, leaving the clock
+    /// at the last event.
+    pub fn run<S: Storage, C: Consensus, K: Clustering>(
+        &self,
+        nodes: &mut [Blocktree<S, C, K>],
+    ) -> Result<usize, BlocktreeError> {
+        self.deliver(u64::MAX, nodes)
+    }
+
+    fn deliver<S: Storage, C: Consensus, K: Clustering>(
+        &self,
+        until_ms: u64,
+        nodes: &mut [Blocktree<S, C, K>],
+    ) -> Result<usize, BlocktreeError> {
+        let mut handled = 0;
+        while let Some((node, event)) = self.next_event(until_ms) {
+            let blocktree = nodes.get_mut(node as usize - 1).ok_or_else(|| {
+                BlocktreeError::NetworkError(format!("No blocktree for node {}", node))
+            })?;
+            blocktree.handle_network_event(event)?;
+            handled += 1;
+        }
+        Ok(handled)
+    }
+}
+
+impl Clock for Simulator {
+    fn now_ms(&self) -> i64 {
+        self.now() as i64
+    }
+}
+
+/// One node's view of a `Simulator`.
+pub struct SimNetwork {
+    id: PeerId,
+    simulator: Simulator,
+}
+
+impl SimNetwork {
+    pub fn id(&self) -> PeerId {
+        self.id
+    }
+
+    /// The simulation's virtual clock, for this node's blocktree.
+    pub fn clock(&self) -> Box<dyn Clock> {
+        Box::new(self.simulator.clone())
+    }
+
+    fn broadcast(&self, message: Message) -> Result<(), BlocktreeError> {
+        let mut state = self.simulator.state();
+        for peer in state.peers_of(self.id) {
+            state.send(self.id, peer, message.clone())?;
+        }
+        Ok(())
+    }
+}
+
+impl Network for SimNetwork {
+    fn broadcast_block(&self, block: Block) -> Result<(), BlocktreeError> {
+        self.broadcast(Message::Block(block))
+    }
+
+    fn broadcast_transaction(&self, branch_id: &str, tx_id: &str) -> Result<(), BlocktreeError> {
+        self.broadcast(Message::Inventory {
+            branch_id: branch_id.to_string(),
+            tx_ids: vec![tx_id.to_string()],
+        })
+    }
+
+    fn get_latency(&self, node1: u32, node2: u32) -> f64 {
+        let local = |node| if node == LOCAL_NODE { self.id } else { node };
+        let (a, b) = (local(node1), local(node2));
+        if a == b {
+            return 0.0;
+        }
+        match self.simulator.state().links.get(&(a.min(b), a.max(b))) {
+            Some(link) => link.model.latency_ms as f64,
+            None => f64::INFINITY,
+        }
+    }
+
+    fn send_to(&self, peer: PeerId, message: Message) -> Result<(), BlocktreeError> {
+        self.simulator.state().send(self.id, peer, message)
+    }
+
+    fn peer_ids(&self) -> Vec<PeerId> {
+        self.simulator.state().peers_of(self.id)
+    }
+
+    fn announce_branches(&self, branches: &[String]) {
+        self.simulator
+            .state()
+            .branches
+            .insert(self.id, branches.to_vec());
+    }
+}
@@ -1,11 +1,23 @@
-use crate::block::Block;
+use crate::block::{Block, BlockHeader, BLOCK_VERSION};
+use crate::difficulty::{self, DEFAULT_POW_LIMIT_BITS};
+use crate::encoding::{self, ENCODING_VERSION};
 use crate::error::BlocktreeError;
-use std::collections::HashMap;
+use crate::miner::{CancelToken, Miner};
+use crate::transaction::Transaction;
+use serde::Deserialize;
+use sha3::{Digest, Sha3_256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 
 pub trait Storage {
     fn save_block(&mut self, block: Block, branch_id: &str) -> Result<(), BlocktreeError>;
     fn get_branch(&self, branch_id: &str) -> Option<&Vec<Block>>;
     fn get_branch_keys(&self) -> Vec<String>;
+    /// Truncates `branch_id` to its first `len` blocks, returning the removed
+    /// ones, e.g. to roll back a chain reorganization.
+    fn rewind_branch(&mut self, branch_id: &str, len: usize) -> Result<Vec<Block>, BlocktreeError>;
 }
 
 pub struct InMemoryStorage {
@@ -42,4 +54,407 @@ impl Storage for InMemoryStorage {
     fn get_branch_keys(&self) -> Vec<String> {
         self.branches.keys().cloned().collect()
     }
+
+    fn rewind_branch(&mut self, branch_id: &str, len: usize) -> Result<Vec<Block>, BlocktreeError> {
+        let chain = self
+            .branches
+            .get_mut(branch_id)
+            .ok_or_else(|| BlocktreeError::BranchNotFound(branch_id.to_string()))?;
+        Ok(chain.split_off(len.min(chain.len())))
+    }
+}
+
+// Record layout in a segment file: [payload length: u32 LE][checksum: 4 bytes][payload],
+// the payload being the block's canonical encoding (or JSON or an older encoding
+// version, in legacy segments)
+const RECORD_HEADER_LEN: usize = 8;
+const INDEX_FILE: &str = "index.json";
+
+/// Progress of a legacy migration, reported after each block.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MigrationProgress {
+    pub branch_id: String,
+    pub blocks_done: usize, // Across every branch
+    pub blocks_total: usize,
+    pub remined: usize, // Blocks whose proof of work was redone so far
+}
+
+/// File-backed storage: one append-only segment file per branch plus an index
+/// mapping branch ids to segment files. Blocks are cached in memory after load.
+pub struct FileStorage {
+    dir: PathBuf,
+    index: BTreeMap<String, String>,
+    branches: HashMap<String, Vec<Block>>,
+    segments: HashMap<String, File>,
+}
+
+impl FileStorage {
+    /// Opens (or creates) a store in `dir`, truncating any torn trailing records.
+    /// A store still holding legacy blocks, in JSON or an older encoding
+    /// version, is refused until `migrate` has converted it.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self, BlocktreeError> {
+        let (storage, legacy_branches) = Self::load(dir.as_ref())?;
+        if !legacy_branches.is_empty() {
+            return Err(BlocktreeError::StorageError(format!(
+                "{} holds legacy blocks in {} branches; run FileStorage::migrate first",
+                storage.dir.display(),
+                legacy_branches.len()
+            )));
+        }
+        Ok(storage)
+    }
+
+    /// Opens the store in `dir` like `open`, first converting any legacy
+    /// blocks: every block is rehashed with `rehash_branches`, re-mined with
+    /// `miner` where its proof of work has to be kept, and the changed
+    /// segments are rewritten in the canonical encoding. `progress` is called
+    /// after each block. Cancelling stops the migration with an error and
+    /// leaves the store as it was.
+    pub fn migrate<P: AsRef<Path>>(
+        dir: P,
+        miner: &Miner,
+        cancel: &CancelToken,
+        progress: impl FnMut(&MigrationProgress),
+    ) -> Result<Self, BlocktreeError> {
+        let (mut storage, legacy_branches) = Self::load(dir.as_ref())?;
+        if legacy_branches.is_empty() {
+            return Ok(storage);
+        }
+        let mut rewrite = rehash_branches(&mut storage.branches, miner, cancel, progress)?;
+        rewrite.extend(legacy_branches);
+        rewrite.sort();
+        rewrite.dedup();
+        println!(
+            "Migrating {} legacy branches in {}",
+            rewrite.len(),
+            storage.dir.display()
+        );
+        storage.rewrite_segments(&rewrite)?;
+        Ok(storage)
+    }
+
+    // Loads every segment, returning the ids of branches with legacy blocks
+    fn load(dir: &Path) -> Result<(Self, Vec<String>), BlocktreeError> {
+        let dir = dir.to_path_buf();
+        fs::create_dir_all(&dir).map_err(storage_error)?;
+        let index: BTreeMap<String, String> = match fs::read(dir.join(INDEX_FILE)) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|e| BlocktreeError::StorageError(format!("Corrupt index: {}", e)))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(storage_error(e)),
+        };
+        let mut storage = FileStorage {
+            dir,
+            index,
+            branches: HashMap::new(),
+            segments: HashMap::new(),
+        };
+        let entries: Vec<(String, String)> = storage
+            .index
+            .iter()
+            .map(|(branch, segment)| (branch.clone(), segment.clone()))
+            .collect();
+        let mut legacy_branches = Vec::new();
+        for (branch_id, segment) in entries {
+            let (blocks, legacy) = storage.recover_segment(&segment)?;
+            if legacy {
+                legacy_branches.push(branch_id.clone());
+            }
+            if !blocks.is_empty() {
+                storage.branches.insert(branch_id, blocks);
+            }
+        }
+        Ok((storage, legacy_branches))
+    }
+
+    /// Reads every record of a segment and truncates an incomplete trailing
+    /// one, a partially written block left by a crash. A complete record that
+    /// fails its checksum or does not decode is never a torn write, so the
+    /// segment is refused rather than truncated. Also reports whether any
+    /// record held a legacy block.
+    fn recover_segment(&self, segment: &str) -> Result<(Vec<Block>, bool), BlocktreeError> {
+        let path = self.dir.join(segment);
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((Vec::new(), false)),
+            Err(e) => return Err(storage_error(e)),
+        };
+        let mut blocks = Vec::new();
+        let mut legacy = false;
+        let mut offset = 0;
+        let corrupt = |offset: usize, e: BlocktreeError| {
+            BlocktreeError::StorageError(format!(
+                "Corrupt record at offset {} of {}: {}",
+                offset,
+                path.display(),
+                e
+            ))
+        };
+        while let Some((payload, len)) =
+            decode_record(&data[offset..]).map_err(|e| corrupt(offset, e))?
+        {
+            let (block, is_legacy) = decode_payload(payload).map_err(|e| corrupt(offset, e))?;
+            blocks.push(block);
+            legacy |= is_legacy;
+            offset += len;
+        }
+        if offset < data.len() {
+            println!(
+                "Truncating torn write in {}: {} trailing bytes",
+                path.display(),
+                data.len() - offset
+            );
+            let file = OpenOptions::new()
+                .write(true)
+                .open(&path)
+                .map_err(storage_error)?;
+            file.set_len(offset as u64).map_err(storage_error)?;
+            file.sync_all().map_err(storage_error)?;
+        }
+        Ok((blocks, legacy))
+    }
+
+    /// Replaces the segments of `branch_ids` with their cached blocks. Every
+    /// replacement is written and synced before any is renamed into place.
+    fn rewrite_segments(&mut self, branch_ids: &[String]) -> Result<(), BlocktreeError> {
+        let mut renames = Vec::new();
+        for branch_id in branch_ids {
+            let segment = self.dir.join(&self.index[branch_id]);
+            let tmp = segment.with_extension("seg.tmp");
+            let mut file = File::create(&tmp).map_err(storage_error)?;
+            for block in self.branches.get(branch_id).into_iter().flatten() {
+                file.write_all(&encode_record(block))
+                    .map_err(storage_error)?;
+            }
+            file.sync_all().map_err(storage_error)?;
+            renames.push((tmp, segment));
+            self.segments.remove(branch_id);
+        }
+        for (tmp, segment) in renames {
+            fs::rename(tmp, segment).map_err(storage_error)?;
+        }
+        Ok(())
+    }
+
+    fn segment_for(&mut self, branch_id: &str) -> Result<&mut File, BlocktreeError> {
+        if !self.index.contains_key(branch_id) {
+            // Persist the index entry before the segment so a crash never leaves an orphaned file
+            self.index.insert(
+                branch_id.to_string(),
+                format!("{}.seg", hex::encode(branch_id)),
+            );
+            self.write_index()?;
+        }
+        if !self.segments.contains_key(branch_id) {
+            let path = self.dir.join(&self.index[branch_id]);
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(storage_error)?;
+            self.segments.insert(branch_id.to_string(), file);
+        }
+        Ok(self.segments.get_mut(branch_id).unwrap())
+    }
+
+    fn write_index(&self) -> Result<(), BlocktreeError> {
+        let bytes = serde_json::to_vec_pretty(&self.index)
+            .map_err(|e| BlocktreeError::SerializationError(e.to_string()))?;
+        let tmp = self.dir.join(format!("{}.tmp", INDEX_FILE));
+        let mut file = File::create(&tmp).map_err(storage_error)?;
+        file.write_all(&bytes).map_err(storage_error)?;
+        file.sync_all().map_err(storage_error)?;
+        fs::rename(&tmp, self.dir.join(INDEX_FILE)).map_err(storage_error)?;
+        Ok(())
+    }
+}
+
+impl Storage for FileStorage {
+    fn save_block(&mut self, block: Block, branch_id: &str) -> Result<(), BlocktreeError> {
+        let record = encode_record(&block);
+        let segment = self.segment_for(branch_id)?;
+        segment.write_all(&record).map_err(storage_error)?;
+        segment.sync_data().map_err(storage_error)?;
+        self.branches
+            .entry(branch_id.to_string())
+            .or_default()
+            .push(block);
+        Ok(())
+    }
+
+    fn get_branch(&self, branch_id: &str) -> Option<&Vec<Block>> {
+        self.branches.get(branch_id)
+    }
+
+    fn get_branch_keys(&self) -> Vec<String> {
+        self.branches.keys().cloned().collect()
+    }
+
+    fn rewind_branch(&mut self, branch_id: &str, len: usize) -> Result<Vec<Block>, BlocktreeError> {
+        let chain = self
+            .branches
+            .get_mut(branch_id)
+            .ok_or_else(|| BlocktreeError::BranchNotFound(branch_id.to_string()))?;
+        let removed = chain.split_off(len.min(chain.len()));
+        if !removed.is_empty() {
+            self.rewrite_segments(&[branch_id.to_string()])?;
+        }
+        Ok(removed)
+    }
+}
+
+fn storage_error(e: io::Error) -> BlocktreeError {
+    BlocktreeError::StorageError(e.to_string())
+}
+
+fn record_checksum(payload: &[u8]) -> [u8; 4] {
+    let digest = Sha3_256::digest(payload);
+    [digest[0], digest[1], digest[2], digest[3]]
+}
+
+fn encode_record(block: &Block) -> Vec<u8> {
+    let payload = encoding::to_bytes(block);
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN + payload.len());
+    record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    record.extend_from_slice(&record_checksum(&payload));
+    record.extend_from_slice(&payload);
+    record
+}
+
+/// Frames one record at the front of `data`, returning its payload and the
+/// record length, or `None` if the record is incomplete. A complete record
+/// failing its checksum is an error.
+fn decode_record(data: &[u8]) -> Result<Option<(&[u8], usize)>, BlocktreeError> {
+    if data.len() < RECORD_HEADER_LEN {
+        return Ok(None);
+    }
+    let len = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+    let end = match RECORD_HEADER_LEN.checked_add(len) {
+        Some(end) if end <= data.len() => end,
+        _ => return Ok(None),
+    };
+    let payload = &data[RECORD_HEADER_LEN..end];
+    if data[4..8] != record_checksum(payload) {
+        return Err(BlocktreeError::StorageError(
+            "Checksum mismatch".to_string(),
+        ));
+    }
+    Ok(Some((payload, end)))
+}
+
+// Block payloads written as JSON before the canonical encoding: with the
+// header nested, hashed as JSON, or older still in the flat layout
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LegacyPayload {
+    Nested(Block),
+    Flat(LegacyBlock),
+}
+
+// Flat layout: the header fields inline, no version, and `bits` absent in the
+// oldest segments
+#[derive(Deserialize)]
+struct LegacyBlock {
+    index: u64,
+    timestamp: i64,
+    transactions: Vec<Transaction>,
+    previous_hash: String,
+    branch_id: String,
+    #[serde(default = "legacy_bits")]
+    bits: u32,
+    nonce: u64,
+    merkle_root: String,
+    hash: String,
+}
+
+fn legacy_bits() -> u32 {
+    DEFAULT_POW_LIMIT_BITS
+}
+
+/// Decodes a record payload, flagging legacy blocks: JSON ones, and those of
+/// an older encoding version, whose header hashes changed with the version.
+/// Their stored hash is kept as-is until `rehash_branches` recomputes it.
+fn decode_payload(payload: &[u8]) -> Result<(Block, bool), BlocktreeError> {
+    if payload.first() != Some(&b'{') {
+        let (block, version) = encoding::from_versioned_bytes(payload)?;
+        return Ok((block, version < ENCODING_VERSION));
+    }
+    let legacy = match serde_json::from_slice(payload)
+        .map_err(|e| BlocktreeError::SerializationError(e.to_string()))?
+    {
+        LegacyPayload::Nested(block) => return Ok((block, true)),
+        LegacyPayload::Flat(legacy) => legacy,
+    };
+    let block = Block {
+        header: BlockHeader {
+            version: BLOCK_VERSION,
+            index: legacy.index,
+            timestamp: legacy.timestamp,
+            previous_hash: legacy.previous_hash,
+            branch_id: legacy.branch_id,
+            merkle_root: legacy.merkle_root,
+            bits: legacy.bits,
+            nonce: legacy.nonce,
+        },
+        transactions: legacy.transactions,
+        hash: legacy.hash,
+    };
+    Ok((block, true))
+}
+
+/// Recomputes every stored block hash from its header, exactly as validation
+/// does, and relinks each `previous_hash` to the recomputed parent. Parents are
+/// processed before children, so the copy of a parent's tip that starts each
+/// split branch takes the parent's migrated header. A block whose old hash met its target is
+/// re-mined at that target with `miner`, keeping its proof of work, until
+/// `cancel` fires. Reports `progress` after each block and returns the ids of
+/// the branches that changed.
+pub fn rehash_branches(
+    branches: &mut HashMap<String, Vec<Block>>,
+    miner: &Miner,
+    cancel: &CancelToken,
+    mut progress: impl FnMut(&MigrationProgress),
+) -> Result<Vec<String>, BlocktreeError> {
+    let mut branch_ids: Vec<String> = branches.keys().cloned().collect();
+    branch_ids.sort_by_key(|id| (id.matches('.').count(), id.clone()));
+    let mut rehashed: HashMap<String, (BlockHeader, String)> = HashMap::new();
+    let mut changed = Vec::new();
+    let mut report = MigrationProgress {
+        branch_id: String::new(),
+        blocks_done: 0,
+        blocks_total: branches.values().map(Vec::len).sum(),
+        remined: 0,
+    };
+    for branch_id in branch_ids {
+        let mut branch_changed = false;
+        report.branch_id = branch_id.clone();
+        for block in branches.get_mut(&branch_id).into_iter().flatten() {
+            let old_hash = block.hash.clone();
+            if let Some((header, hash)) = rehashed.get(&old_hash) {
+                block.header = header.clone();
+                block.hash = hash.clone();
+            } else {
+                if let Some((_, parent)) = rehashed.get(&block.header.previous_hash) {
+                    block.header.previous_hash = parent.clone();
+                }
+                let proven =
+                    difficulty::hash_meets_target(&old_hash, block.header.bits).unwrap_or(false);
+                block.update_hash()?;
+                if proven && !difficulty::hash_meets_target(&block.hash, block.header.bits)? {
+                    *block = miner.mine(block.clone(), cancel)?.block;
+                    report.remined += 1;
+                }
+            }
+            report.blocks_done += 1;
+            progress(&report);
+            if block.hash != old_hash {
+                rehashed.insert(old_hash, (block.header.clone(), block.hash.clone()));
+                branch_changed = true;
+            }
+        }
+        if branch_changed {
+            changed.push(branch_id);
+        }
+    }
+    Ok(changed)
 }
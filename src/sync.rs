@@ -0,0 +1,512 @@
+//! Catching up with peers, headers first.
+//!
+//! A node asks its peers for their branches, then for the headers of every
+//! branch it holds where a peer has a tip it does not know. Headers are
+//! checked for linkage, work, timestamps and difficulty retargeting before
+//! any body is fetched, and at most `MAX_PENDING_HEADERS` of a branch wait
+//! for their bodies at once. Bodies are spread over every peer advertising
+//! the branch. A peer that does not answer a request in time is no longer
+//! asked about the branch, and another peer is asked instead. Each body is
+//! fully validated when it is committed in chain order; child branches
+//! appear as their parent is committed past its split point, and are synced
+//! in turn.
+
+use crate::block::{Block, BlockHeader};
+use crate::difficulty::U256;
+use crate::error::BlocktreeError;
+use crate::network::{BranchSummary, PeerId};
+use crate::validation;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Most headers sent in reply to one `GetHeaders`.
+pub const MAX_HEADERS: usize = 2000;
+/// Most blocks asked of a peer in one `GetBlocks`.
+pub const MAX_BLOCKS_PER_REQUEST: usize = 16;
+/// Most blocks requested from one peer and not yet received.
+pub const MAX_BLOCKS_IN_FLIGHT: usize = 64;
+/// Most headers of one branch waiting for their bodies.
+pub const MAX_PENDING_HEADERS: usize = 4 * MAX_HEADERS;
+
+// Locator entries listed one by one before the steps start doubling
+const LOCATOR_DENSE_ENTRIES: usize = 10;
+
+struct PendingHeader {
+    hash: String,
+    header: BlockHeader,
+    body: Option<Block>,
+}
+
+struct BodyRequest {
+    peer: PeerId,
+    branch_id: String,
+    hashes: Vec<String>,
+    deadline: i64,
+}
+
+/// Progress of syncing from peers: what they advertised, validated headers
+/// waiting for their bodies, and requests awaiting a reply.
+#[derive(Default)]
+pub struct SyncState {
+    advertised: HashMap<PeerId, HashMap<String, BranchSummary>>,
+    header_requests: HashMap<String, (PeerId, i64)>, // Peer and deadline, one per branch
+    pending: HashMap<String, VecDeque<PendingHeader>>, // In chain order
+    requests: VecDeque<BodyRequest>,                 // Body requests in the order they were sent
+    in_flight: HashSet<String>,
+}
+
+impl SyncState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the branches `peer` holds, replacing what it advertised before.
+    pub fn on_branches(&mut self, peer: PeerId, branches: Vec<BranchSummary>) {
+        let branches = branches
+            .into_iter()
+            .map(|summary| (summary.branch_id.clone(), summary))
+            .collect();
+        self.advertised.insert(peer, branches);
+    }
+
+    /// Peer to ask for headers of `branch_id`, if one has a tip neither
+    /// `known` nor pending, no headers request is outstanding and there is
+    /// room for more pending headers. Prefers the longest advertised chain.
+    pub fn headers_wanted(&self, branch_id: &str, known: impl Fn(&str) -> bool) -> Option<PeerId> {
+        if self.header_requests.contains_key(branch_id)
+            || self.pending_len(branch_id) >= MAX_PENDING_HEADERS
+        {
+            return None;
+        }
+        self.advertised
+            .iter()
+            .filter_map(|(&peer, branches)| branches.get(branch_id).map(|summary| (peer, summary)))
+            .filter(|(_, summary)| {
+                !known(&summary.tip_hash) && !self.is_pending(branch_id, &summary.tip_hash)
+            })
+            .max_by_key(|&(peer, summary)| (summary.length, std::cmp::Reverse(peer)))
+            .map(|(peer, _)| peer)
+    }
+
+    /// Records headers of `branch_id` as asked of `peer` at `now`, due
+    /// within `timeout`.
+    pub fn request_headers(&mut self, branch_id: &str, peer: PeerId, now: i64, timeout: i64) {
+        let deadline = now.saturating_add(timeout);
+        self.header_requests
+            .insert(branch_id.to_string(), (peer, deadline));
+    }
+
+    /// Hash of the last header of `branch_id` waiting for its body.
+    pub fn pending_tip(&self, branch_id: &str) -> Option<&str> {
+        self.pending
+            .get(branch_id)
+            .and_then(|pending| pending.back())
+            .map(|pending| pending.hash.as_str())
+    }
+
+    /// Queues headers of `branch_id` from `peer` for body download, up to
+    /// `MAX_PENDING_HEADERS`. They must follow the pending headers, or a
+    /// `known` block if none are pending, have targets no easier than
+    /// `pow_limit`, and pass `check` given the known
+    /// block they build on and the headers after it, as bodiless blocks. A
+    /// peer that sends invalid headers, or stops short of the tip it
+    /// advertised, is no longer asked about the branch. Returns how many
+    /// headers were new.
+    pub fn on_headers(
+        &mut self,
+        peer: PeerId,
+        branch_id: &str,
+        mut headers: Vec<BlockHeader>,
+        pow_limit: U256,
+        known: impl Fn(&str) -> bool,
+        check: impl Fn(&BlockHeader, &str, &[Block]) -> Result<(), BlocktreeError>,
+    ) -> Result<usize, BlocktreeError> {
+        if self.header_requests.get(branch_id).map(|&(asked, _)| asked) != Some(peer) {
+            return Err(BlocktreeError::NetworkError(format!(
+                "Unsolicited headers of {} from peer {}",
+                branch_id, peer
+            )));
+        }
+        self.header_requests.remove(branch_id);
+        // The rest is asked for again once bodies make room
+        let room = MAX_PENDING_HEADERS.saturating_sub(self.pending_len(branch_id));
+        let complete = headers.len() < MAX_HEADERS && headers.len() <= room;
+        let result = if headers.len() > MAX_HEADERS {
+            Err(BlocktreeError::NetworkError(format!(
+                "{} headers exceed {}",
+                headers.len(),
+                MAX_HEADERS
+            )))
+        } else {
+            headers.truncate(room);
+            self.append_headers(branch_id, headers, pow_limit, &known, &check)
+        };
+        let tip_reached = self
+            .advertised
+            .get(&peer)
+            .and_then(|branches| branches.get(branch_id))
+            .is_some_and(|summary| {
+                known(&summary.tip_hash) || self.is_pending(branch_id, &summary.tip_hash)
+            });
+        if result.is_err() || (complete && !tip_reached) {
+            self.forget(peer, branch_id);
+        }
+        if self.pending.get(branch_id).is_some_and(VecDeque::is_empty) {
+            self.pending.remove(branch_id);
+        }
+        result
+    }
+
+    fn append_headers(
+        &mut self,
+        branch_id: &str,
+        headers: Vec<BlockHeader>,
+        pow_limit: U256,
+        known: &impl Fn(&str) -> bool,
+        check: &impl Fn(&BlockHeader, &str, &[Block]) -> Result<(), BlocktreeError>,
+    ) -> Result<usize, BlocktreeError> {
+        let pending = self.pending.entry(branch_id.to_string()).or_default();
+        let mut previous = pending
+            .back()
+            .map(|last| (last.hash.clone(), last.header.index));
+        // Headers since the last known block, `base`
+        let mut base = String::new();
+        let mut run: Vec<Block> = pending
+            .iter()
+            .map(|pending| Block {
+                header: pending.header.clone(),
+                transactions: Vec::new(),
+                hash: pending.hash.clone(),
+            })
+            .collect();
+        if let Some(first) = pending.front() {
+            base = first.header.previous_hash.clone();
+        }
+        let mut added = 0;
+        for header in headers {
+            let hash = validation::validate_header(&header, branch_id, pow_limit)?;
+            let linked = match &previous {
+                Some((previous_hash, previous_index)) => {
+                    header.previous_hash == *previous_hash && header.index == previous_index + 1
+                }
+                None => known(&header.previous_hash),
+            };
+            if !linked {
+                return Err(BlocktreeError::InvalidPreviousHash(format!(
+                    "Header {} of {} does not connect to known blocks",
+                    hash, branch_id
+                )));
+            }
+            if run.is_empty() {
+                base = header.previous_hash.clone();
+            }
+            check(&header, &base, &run)?;
+            previous = Some((hash.clone(), header.index));
+            // Already held, e.g. when the peer matched an older locator entry
+            if known(&hash) {
+                run.clear();
+                continue;
+            }
+            run.push(Block {
+                header: header.clone(),
+                transactions: Vec::new(),
+                hash: hash.clone(),
+            });
+            pending.push_back(PendingHeader {
+                hash,
+                header,
+                body: None,
+            });
+            added += 1;
+        }
+        Ok(added)
+    }
+
+    /// Assigns bodies of pending headers to peers advertising their branch,
+    /// round robin within each peer's in-flight limit, returning the
+    /// `GetBlocks` requests to send at `now` as (peer, branch, hashes), each
+    /// due within `timeout(peer)`. Headers no peer can serve any more are
+    /// dropped.
+    pub fn body_requests(
+        &mut self,
+        now: i64,
+        timeout: impl Fn(PeerId) -> i64,
+    ) -> Vec<(PeerId, String, Vec<String>)> {
+        let mut load: HashMap<PeerId, usize> = HashMap::new();
+        for request in &self.requests {
+            *load.entry(request.peer).or_default() += request.hashes.len();
+        }
+        let mut branch_ids: Vec<String> = self.pending.keys().cloned().collect();
+        branch_ids.sort();
+        let mut sent = Vec::new();
+        for branch_id in branch_ids {
+            let mut peers: Vec<PeerId> = self
+                .advertised
+                .iter()
+                .filter(|(_, branches)| branches.contains_key(&branch_id))
+                .map(|(&peer, _)| peer)
+                .collect();
+            peers.sort();
+            let awaited = self.requests.iter().any(|r| r.branch_id == branch_id);
+            if peers.is_empty() {
+                if !awaited {
+                    self.pending.remove(&branch_id);
+                }
+                continue;
+            }
+            let wanted: Vec<String> = self.pending[&branch_id]
+                .iter()
+                .filter(|pending| pending.body.is_none() && !self.in_flight.contains(&pending.hash))
+                .map(|pending| pending.hash.clone())
+                .collect();
+            let mut turn = 0;
+            for chunk in wanted.chunks(MAX_BLOCKS_PER_REQUEST) {
+                let peer = (0..peers.len())
+                    .map(|offset| peers[(turn + offset) % peers.len()])
+                    .find(|peer| load.get(peer).copied().unwrap_or(0) < MAX_BLOCKS_IN_FLIGHT);
+                let peer = match peer {
+                    Some(peer) => peer,
+                    None => break, // Every peer is busy; the rest waits for replies
+                };
+                turn = peers.iter().position(|&p| p == peer).unwrap_or(0) + 1;
+                *load.entry(peer).or_default() += chunk.len();
+                self.in_flight.extend(chunk.iter().cloned());
+                self.requests.push_back(BodyRequest {
+                    peer,
+                    branch_id: branch_id.clone(),
+                    hashes: chunk.to_vec(),
+                    deadline: now.saturating_add(timeout(peer)),
+                });
+                sent.push((peer, branch_id.clone(), chunk.to_vec()));
+            }
+        }
+        sent
+    }
+
+    /// Attaches the bodies `peer` sent for its oldest outstanding request on
+    /// `branch_id`. A body must match its header, its Merkle root and its
+    /// transaction ids; a peer
+    /// that sends a bad body or leaves blocks out is no longer asked about
+    /// the branch, and the missing bodies are requested again.
+    pub fn on_blocks(
+        &mut self,
+        peer: PeerId,
+        branch_id: &str,
+        blocks: Vec<Block>,
+    ) -> Result<(), BlocktreeError> {
+        let position = self
+            .requests
+            .iter()
+            .position(|r| r.peer == peer && r.branch_id == branch_id)
+            .ok_or_else(|| {
+                BlocktreeError::NetworkError(format!(
+                    "Unsolicited blocks of {} from peer {}",
+                    branch_id, peer
+                ))
+            })?;
+        let request = self
+            .requests
+            .remove(position)
+            .expect("position is in range");
+        for hash in &request.hashes {
+            self.in_flight.remove(hash);
+        }
+        let delivered = blocks.len();
+        let result = self.attach_bodies(&request, blocks);
+        if result.is_err() || delivered < request.hashes.len() {
+            self.forget(peer, branch_id);
+        }
+        result
+    }
+
+    fn attach_bodies(
+        &mut self,
+        request: &BodyRequest,
+        blocks: Vec<Block>,
+    ) -> Result<(), BlocktreeError> {
+        let pending = match self.pending.get_mut(&request.branch_id) {
+            Some(pending) => pending,
+            None => return Ok(()), // Dropped while the request was out
+        };
+        for block in blocks {
+            if !request.hashes.contains(&block.hash) {
+                return Err(BlocktreeError::NetworkError(format!(
+                    "Block {} was not requested",
+                    block.hash
+                )));
+            }
+            let entry = match pending.iter_mut().find(|p| p.hash == block.hash) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let ids_match = block
+                .transactions
+                .iter()
+                .all(|tx| tx.calculate_hash().is_ok_and(|id| id == tx.tx_id));
+            if block.header != entry.header
+                || !ids_match
+                || Block::calculate_merkle_root(&block.transactions)? != block.header.merkle_root
+            {
+                return Err(BlocktreeError::InvalidHash(format!(
+                    "Body of block {} does not match its header",
+                    block.hash
+                )));
+            }
+            entry.body = Some(block);
+        }
+        Ok(())
+    }
+
+    /// Branches whose next pending block has its body.
+    pub fn ready_branches(&self) -> Vec<String> {
+        let mut ready: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.front().is_some_and(|p| p.body.is_some()))
+            .map(|(branch_id, _)| branch_id.clone())
+            .collect();
+        ready.sort();
+        ready
+    }
+
+    /// Next block of `branch_id` to commit, if its body has arrived.
+    pub fn ready(&self, branch_id: &str) -> Option<&Block> {
+        self.pending
+            .get(branch_id)
+            .and_then(|pending| pending.front())
+            .and_then(|pending| pending.body.as_ref())
+    }
+
+    /// Marks the next block of `branch_id` as committed.
+    pub fn pop_ready(&mut self, branch_id: &str) {
+        if let Some(pending) = self.pending.get_mut(branch_id) {
+            pending.pop_front();
+            if pending.is_empty() {
+                self.pending.remove(branch_id);
+            }
+        }
+    }
+
+    /// Gives up on the pending blocks of `branch_id` and what peers
+    /// advertised for it, e.g. once a block failed validation.
+    pub fn drop_branch(&mut self, branch_id: &str) {
+        self.pending.remove(branch_id);
+        self.header_requests.remove(branch_id);
+        for branches in self.advertised.values_mut() {
+            branches.remove(branch_id);
+        }
+    }
+
+    /// Drops the requests not answered by `now`, no longer asking their
+    /// peers about the branch, so the next requests go to other peers.
+    /// Returns how many expired.
+    pub fn expire(&mut self, now: i64) -> usize {
+        let mut expired: Vec<(PeerId, String)> = Vec::new();
+        self.header_requests
+            .retain(|branch_id, &mut (peer, deadline)| {
+                if deadline <= now {
+                    expired.push((peer, branch_id.clone()));
+                }
+                deadline > now
+            });
+        let in_flight = &mut self.in_flight;
+        self.requests.retain(|request| {
+            if request.deadline > now {
+                return true;
+            }
+            for hash in &request.hashes {
+                in_flight.remove(hash);
+            }
+            expired.push((request.peer, request.branch_id.clone()));
+            false
+        });
+        for (peer, branch_id) in &expired {
+            println!("Peer {} did not answer in time about {}", peer, branch_id);
+            self.forget(*peer, branch_id);
+        }
+        expired.len()
+    }
+
+    /// Forgets `peer`, freeing its outstanding requests for other peers.
+    pub fn remove_peer(&mut self, peer: PeerId) {
+        self.advertised.remove(&peer);
+        self.header_requests
+            .retain(|_, &mut (asked, _)| asked != peer);
+        let in_flight = &mut self.in_flight;
+        self.requests.retain(|request| {
+            if request.peer == peer {
+                for hash in &request.hashes {
+                    in_flight.remove(hash);
+                }
+            }
+            request.peer != peer
+        });
+    }
+
+    /// No request is awaiting a reply.
+    pub fn is_idle(&self) -> bool {
+        self.header_requests.is_empty() && self.requests.is_empty()
+    }
+
+    /// Idle with nothing left to commit.
+    pub fn is_synced(&self) -> bool {
+        self.is_idle() && self.pending.is_empty()
+    }
+
+    fn pending_len(&self, branch_id: &str) -> usize {
+        self.pending.get(branch_id).map_or(0, VecDeque::len)
+    }
+
+    fn is_pending(&self, branch_id: &str, hash: &str) -> bool {
+        self.pending
+            .get(branch_id)
+            .is_some_and(|pending| pending.iter().any(|p| p.hash == hash))
+    }
+
+    fn forget(&mut self, peer: PeerId, branch_id: &str) {
+        if let Some(branches) = self.advertised.get_mut(&peer) {
+            branches.remove(branch_id);
+        }
+    }
+}
+
+/// Hashes telling a peer how much of a branch this node has: `pending_tip`
+/// if headers are waiting for bodies, then the active `chain` from its tip
+/// back to its first block, one by one at first and then doubling the step.
+pub fn locator(chain: &[Block], pending_tip: Option<&str>) -> Vec<String> {
+    let mut locator: Vec<String> = pending_tip.map(str::to_string).into_iter().collect();
+    if chain.is_empty() {
+        return locator;
+    }
+    let mut position = chain.len() - 1;
+    let mut step = 1;
+    loop {
+        locator.push(chain[position].hash.clone());
+        if position == 0 {
+            return locator;
+        }
+        if locator.len() >= LOCATOR_DENSE_ENTRIES {
+            step *= 2;
+        }
+        position = position.saturating_sub(step);
+    }
+}
+
+/// Headers of `chain` after the first `locator` hash on it, or from its
+/// first block if none is, at most `MAX_HEADERS`.
+pub fn headers_after(chain: &[Block], locator: &[String]) -> Vec<BlockHeader> {
+    let positions: HashMap<&str, usize> = chain
+        .iter()
+        .enumerate()
+        .map(|(position, block)| (block.hash.as_str(), position))
+        .collect();
+    let start = locator
+        .iter()
+        .find_map(|hash| positions.get(hash.as_str()))
+        .map_or(0, |position| position + 1);
+    chain
+        .iter()
+        .skip(start)
+        .take(MAX_HEADERS)
+        .map(|block| block.header.clone())
+        .collect()
+}
@@ -0,0 +1,102 @@
+#[cfg(test)]
+mod tests {
+    use crate::block::{self, Block, TransactionProof, BLOCK_VERSION};
+    use crate::transaction::Transaction;
+
+    fn block_with(count: usize) -> Block {
+        let transactions = (0..count)
+            .map(|i| {
+                Transaction::with_timestamp("alice".to_string(), format!("bob{}", i), 1, 0).unwrap()
+            })
+            .collect();
+        Block::new(1, transactions, "0".to_string(), "root".to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_every_transaction_has_a_valid_proof() {
+        for count in 1..=7 {
+            let block = block_with(count);
+            for tx in &block.transactions {
+                let proof = block.transaction_proof(&tx.tx_id).unwrap();
+                assert!(block::verify_transaction_proof(
+                    &block.header.merkle_root,
+                    &tx.tx_id,
+                    &proof
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn test_proof_does_not_verify_other_data() {
+        let block = block_with(5);
+        let tx_id = &block.transactions[2].tx_id;
+        let proof = block.transaction_proof(tx_id).unwrap();
+        let other_id = &block.transactions[3].tx_id;
+        assert!(!block::verify_transaction_proof(
+            &block.header.merkle_root,
+            other_id,
+            &proof
+        ));
+        let other_root = block_with(4).header.merkle_root;
+        assert!(!block::verify_transaction_proof(&other_root, tx_id, &proof));
+
+        let mut tampered = proof.clone();
+        tampered.hashes[0] = "00".repeat(32);
+        assert!(!block::verify_transaction_proof(
+            &block.header.merkle_root,
+            tx_id,
+            &tampered
+        ));
+        let mut malformed = proof;
+        malformed.hashes[0] = "zz".to_string();
+        assert!(!block::verify_transaction_proof(
+            &block.header.merkle_root,
+            tx_id,
+            &malformed
+        ));
+        assert!(block.transaction_proof("missing").is_err());
+    }
+
+    #[test]
+    fn test_proof_round_trips_through_json() {
+        let block = block_with(3);
+        let tx_id = &block.transactions[1].tx_id;
+        let proof = block.transaction_proof(tx_id).unwrap();
+        let json = serde_json::to_string(&proof).unwrap();
+        let decoded: TransactionProof = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, proof);
+        assert!(block::verify_transaction_proof(
+            &block.header.merkle_root,
+            tx_id,
+            &decoded
+        ));
+    }
+
+    #[test]
+    fn test_hash_covers_header_only() {
+        let mut block = block_with(3);
+        assert_eq!(block.header.version, BLOCK_VERSION);
+        assert_eq!(block.hash, block.header.calculate_hash().unwrap());
+        // The body is committed through the Merkle root, not hashed directly
+        let original = block.hash.clone();
+        block.transactions.pop();
+        assert_eq!(block.calculate_hash().unwrap(), original);
+        block.header.merkle_root = Block::calculate_merkle_root(&block.transactions).unwrap();
+        assert_ne!(block.calculate_hash().unwrap(), original);
+        let header_hash = block.calculate_hash().unwrap();
+        block.header.nonce += 1;
+        assert_ne!(block.calculate_hash().unwrap(), header_hash);
+    }
+
+    #[test]
+    fn test_hash_ignores_stored_hash() {
+        let mut block = block_with(2);
+        let hash = block.hash.clone();
+        block.hash = "stale".to_string();
+        assert_eq!(block.calculate_hash().unwrap(), hash);
+        block.update_hash().unwrap();
+        block.update_hash().unwrap();
+        assert_eq!(block.hash, hash);
+    }
+}
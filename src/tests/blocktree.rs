@@ -0,0 +1,77 @@
+#[cfg(test)]
+mod tests {
+    use crate::blocktree::{Blocktree, BlocktreeBuilder};
+    use crate::clustering::Clustering;
+    use crate::error::BlocktreeError;
+    use crate::network::MockNetwork;
+    use crate::storage::{FileStorage, Storage};
+    use crate::tests::support::{funded_config, transfer};
+    use nalgebra::DVector;
+
+    struct FixedClustering;
+
+    impl Clustering for FixedClustering {
+        fn compute_fiedler_vector(&self) -> Result<DVector<f64>, BlocktreeError> {
+            Ok(DVector::from_vec(vec![1.0, -1.0, 1.0, -1.0]))
+        }
+
+        fn partition_nodes(&self, fiedler_vector: &DVector<f64>) -> (Vec<u32>, Vec<u32>) {
+            let (left, right): (Vec<_>, Vec<_>) =
+                (0..fiedler_vector.len() as u32).partition(|&i| fiedler_vector[i as usize] >= 0.0);
+            (left, right)
+        }
+    }
+
+    #[test]
+    fn test_builder_with_custom_components() {
+        let mut blocktree =
+            BlocktreeBuilder::from_config(funded_config(), Box::new(MockNetwork::new()))
+                .clustering(FixedClustering)
+                .build()
+                .unwrap();
+        for i in 1..=5 {
+            let tx = transfer(&blocktree, i as u8, &format!("receiver{}", i), 100, "root");
+            blocktree.add_block(vec![tx], "root").unwrap();
+        }
+        assert!(blocktree.storage.get_branch("root.1").is_some());
+        assert!(blocktree.storage.get_branch("root.2").is_some());
+    }
+
+    #[test]
+    fn test_builder_reopens_file_storage_without_new_genesis() {
+        let dir = tempfile::tempdir().unwrap();
+        let genesis_hash = {
+            let mut blocktree =
+                BlocktreeBuilder::from_config(funded_config(), Box::new(MockNetwork::new()))
+                    .storage(FileStorage::open(dir.path()).unwrap())
+                    .build()
+                    .unwrap();
+            let tx = transfer(&blocktree, 0, "receiver", 100, "root");
+            blocktree.add_block(vec![tx], "root").unwrap();
+            blocktree.storage.get_branch("root").unwrap()[0]
+                .hash
+                .clone()
+        };
+        let blocktree =
+            BlocktreeBuilder::from_config(funded_config(), Box::new(MockNetwork::new()))
+                .storage(FileStorage::open(dir.path()).unwrap())
+                .build()
+                .unwrap();
+        let root = blocktree.storage.get_branch("root").unwrap();
+        assert_eq!(root.len(), 2);
+        assert_eq!(root[0].hash, genesis_hash);
+    }
+
+    #[test]
+    fn test_branch_splits_only_once() {
+        let mut blocktree =
+            Blocktree::from_config(funded_config(), Box::new(MockNetwork::new())).unwrap();
+        for i in 1..=6 {
+            let tx = transfer(&blocktree, i as u8, &format!("receiver{}", i), 100, "root");
+            blocktree.add_block(vec![tx], "root").unwrap();
+        }
+        assert_eq!(blocktree.storage.get_branch("root").unwrap().len(), 7);
+        assert_eq!(blocktree.storage.get_branch("root.1").unwrap().len(), 1);
+        assert!(blocktree.validate_branch("root.1").unwrap().is_valid());
+    }
+}
@@ -0,0 +1,107 @@
+#[cfg(test)]
+mod tests {
+    use crate::blocktree::{Blocktree, BlocktreeBuilder};
+    use crate::config::BlocktreeConfig;
+    use crate::difficulty::DEFAULT_POW_LIMIT_BITS;
+    use crate::network::MockNetwork;
+    use crate::storage::{FileStorage, Storage};
+
+    const TESTNET_TOML: &str = r#"
+network_name = "testnet"
+difficulty_bits = 0x207fffff
+pow_limit_bits = 0x207fffff
+target_block_time = 0.5
+node_count = 4
+split_interval = 3
+base_reward = 25
+decay_factor = 0.99
+
+[genesis]
+timestamp = 1735689600000
+
+[[genesis.transactions]]
+sender = "genesis"
+receiver = "foundation"
+amount = 0
+
+[[genesis.allocations]]
+address = "alice"
+amount = 1000
+"#;
+
+    #[test]
+    fn test_default_config_matches_legacy_parameters() {
+        let config = BlocktreeConfig::default();
+        assert_eq!(config.difficulty_bits, DEFAULT_POW_LIMIT_BITS);
+        assert_eq!(config.target_block_time, 0.2);
+        assert_eq!(config.node_count, 10);
+        assert_eq!(config.split_interval, 5);
+        assert_eq!(config.base_reward, 50);
+        assert_eq!(config.decay_factor, 0.999);
+    }
+
+    #[test]
+    fn test_toml_and_json_configs_are_equivalent() {
+        let from_toml = BlocktreeConfig::from_toml_str(TESTNET_TOML).unwrap();
+        let json = serde_json::to_string(&from_toml).unwrap();
+        let from_json = BlocktreeConfig::from_json_str(&json).unwrap();
+        assert_eq!(from_toml, from_json);
+        assert_eq!(from_toml.network_name, "testnet");
+        assert_eq!(from_toml.genesis.allocations[0].amount, 1000);
+    }
+
+    #[test]
+    fn test_genesis_block_is_reproducible() {
+        let config = BlocktreeConfig::from_toml_str(TESTNET_TOML).unwrap();
+        let first = config.genesis_block().unwrap();
+        let second = config.genesis_block().unwrap();
+        assert_eq!(first.hash, second.hash);
+        assert_eq!(first.header.timestamp, 1735689600000);
+        assert_eq!(first.transactions.len(), 2);
+        assert_eq!(first.transactions[1].receiver, "alice");
+    }
+
+    #[test]
+    fn test_config_drives_split_interval() {
+        let config = BlocktreeConfig::from_toml_str(TESTNET_TOML).unwrap();
+        let mut blocktree = Blocktree::from_config(config, Box::new(MockNetwork::new())).unwrap();
+        for _ in 1..=2 {
+            blocktree.add_block(vec![], "root").unwrap();
+        }
+        assert!(blocktree.storage.get_branch("root.1").is_some());
+    }
+
+    #[test]
+    fn test_invalid_config_is_rejected() {
+        let json = r#"{"split_interval": 1}"#;
+        assert!(BlocktreeConfig::from_json_str(json).is_err());
+        let json = r#"{"claim_depth": 0}"#;
+        assert!(BlocktreeConfig::from_json_str(json).is_err());
+    }
+
+    #[test]
+    fn test_misspelled_field_is_rejected() {
+        let toml = TESTNET_TOML.replace("split_interval", "split_intervall");
+        assert!(BlocktreeConfig::from_toml_str(&toml).is_err());
+        let toml = TESTNET_TOML.replace("timestamp = ", "timestmp = ");
+        assert!(BlocktreeConfig::from_toml_str(&toml).is_err());
+        let json = r#"{"genesis": {"allocations": [{"address": "a", "amount": 1, "amout": 2}]}}"#;
+        assert!(BlocktreeConfig::from_json_str(json).is_err());
+    }
+
+    #[test]
+    fn test_mismatched_genesis_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let devnet = BlocktreeConfig::default();
+        Blocktree::builder(Box::new(MockNetwork::new()))
+            .storage(FileStorage::open(dir.path()).unwrap())
+            .build()
+            .unwrap();
+        let testnet = BlocktreeConfig::from_toml_str(TESTNET_TOML).unwrap();
+        assert_ne!(devnet.genesis, testnet.genesis);
+        let result = BlocktreeBuilder::from_config(testnet, Box::new(MockNetwork::new()))
+            .storage(FileStorage::open(dir.path()).unwrap())
+            .build();
+        assert!(result.is_err());
+    }
+}
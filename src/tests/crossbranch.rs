@@ -0,0 +1,153 @@
+#[cfg(test)]
+mod tests {
+    use crate::blocktree::Blocktree;
+    use crate::crossbranch::{self, CrossBranch};
+    use crate::error::{BlocktreeError, ValidationError};
+    use crate::network::MockNetwork;
+    use crate::tests::support::{account, funded_config, INITIAL_BALANCE};
+    use crate::transaction::Transaction;
+
+    /// Funded blocktree whose root has split into `root.1` and `root.2`.
+    fn split_blocktree() -> Blocktree {
+        let mut blocktree =
+            Blocktree::from_config(funded_config(), Box::new(MockNetwork::new())).unwrap();
+        for _ in 0..4 {
+            blocktree.add_block(vec![], "root").unwrap();
+        }
+        assert!(blocktree.get_branches().contains(&"root.2".to_string()));
+        blocktree
+    }
+
+    /// Adds `count` empty blocks to `branch_id`.
+    fn extend(blocktree: &mut Blocktree, branch_id: &str, count: usize) {
+        for _ in 0..count {
+            blocktree.add_block(vec![], branch_id).unwrap();
+        }
+    }
+
+    fn lock(blocktree: &Blocktree, source: &str, destination: &str, amount: u64) -> Transaction {
+        let alice = account(1);
+        let nonce = blocktree.nonce_of(&alice.address(), source).unwrap();
+        crossbranch::lock(
+            &alice,
+            destination,
+            account(2).address(),
+            amount,
+            0,
+            nonce,
+            source,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_transfer_between_siblings() {
+        let mut blocktree = split_blocktree();
+        let alice = account(1).address();
+        let bob = account(2).address();
+        let lock = lock(&blocktree, "root.1", "root.2", 500);
+        blocktree.add_block(vec![lock.clone()], "root.1").unwrap();
+        assert_eq!(
+            blocktree.balance_of(&alice, "root.1").unwrap(),
+            INITIAL_BALANCE - 500
+        );
+        assert_eq!(
+            blocktree.balance_of(&bob, "root.1").unwrap(),
+            INITIAL_BALANCE
+        );
+
+        // The lock block must be buried `claim_depth` deep before it can be claimed
+        let claim_depth = blocktree.config().claim_depth;
+        extend(&mut blocktree, "root.1", claim_depth - 1);
+        let early = blocktree.claim_transfer("root.1", &lock.tx_id).unwrap();
+        assert!(blocktree.submit_transaction(early, "root.2").is_err());
+        extend(&mut blocktree, "root.1", 1);
+
+        let claim = blocktree.claim_transfer("root.1", &lock.tx_id).unwrap();
+        assert!(blocktree
+            .submit_transaction(claim.clone(), "root.2")
+            .unwrap());
+        blocktree.mine_pending("root.2").unwrap();
+        assert_eq!(
+            blocktree.balance_of(&bob, "root.2").unwrap(),
+            INITIAL_BALANCE + 500
+        );
+        assert!(blocktree.mempool("root.2").unwrap().is_empty());
+        assert!(blocktree.is_branch_valid("root.2").unwrap());
+
+        // A second claim of the same lock is refused
+        let again = blocktree.claim_transfer("root.1", &lock.tx_id).unwrap();
+        assert!(blocktree
+            .submit_transaction(again.clone(), "root.2")
+            .is_err());
+        assert!(matches!(
+            blocktree.add_block(vec![again], "root.2"),
+            Err(BlocktreeError::Validation(ValidationError::BadClaim { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_transfer_from_ancestor_to_descendant() {
+        let mut blocktree = split_blocktree();
+        let lock = lock(&blocktree, "root", "root.1", 300);
+        blocktree.add_block(vec![lock.clone()], "root").unwrap();
+        let claim_depth = blocktree.config().claim_depth;
+        extend(&mut blocktree, "root", claim_depth);
+        let claim = blocktree.claim_transfer("root", &lock.tx_id).unwrap();
+        // Only the destination named in the lock accepts the claim
+        let mut elsewhere = claim.clone();
+        elsewhere.branch_id = "root.2".to_string();
+        elsewhere.tx_id = elsewhere.calculate_hash().unwrap();
+        assert!(matches!(
+            blocktree.add_block(vec![elsewhere], "root.2"),
+            Err(BlocktreeError::Validation(ValidationError::BadClaim { .. }))
+        ));
+        blocktree.add_block(vec![claim], "root.1").unwrap();
+        assert_eq!(
+            blocktree
+                .balance_of(&account(2).address(), "root.1")
+                .unwrap(),
+            INITIAL_BALANCE + 300
+        );
+    }
+
+    #[test]
+    fn test_forged_claims_are_rejected() {
+        let mut blocktree = split_blocktree();
+        let lock = lock(&blocktree, "root.1", "root.2", 500);
+        blocktree.add_block(vec![lock.clone()], "root.1").unwrap();
+        let claim_depth = blocktree.config().claim_depth;
+        extend(&mut blocktree, "root.1", claim_depth);
+        let claim = blocktree.claim_transfer("root.1", &lock.tx_id).unwrap();
+
+        // Inflating the lock breaks its signature and id
+        let mut inflated = claim.clone();
+        inflated.amount = 5_000;
+        if let Some(CrossBranch::Claim(inner)) = &mut inflated.cross_branch {
+            inner.lock.amount = 5_000;
+        }
+        inflated.tx_id = inflated.calculate_hash().unwrap();
+        assert!(blocktree.submit_transaction(inflated, "root.2").is_err());
+
+        // A proof for another leaf does not prove the lock
+        let mut misproven = claim;
+        if let Some(CrossBranch::Claim(inner)) = &mut misproven.cross_branch {
+            inner.proof.leaf_index = 0;
+        }
+        misproven.tx_id = misproven.calculate_hash().unwrap();
+        assert!(matches!(
+            blocktree.add_block(vec![misproven], "root.2"),
+            Err(BlocktreeError::Validation(ValidationError::BadClaim { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_branches_related() {
+        assert!(crossbranch::branches_related("root.1", "root.2"));
+        assert!(crossbranch::branches_related("root", "root.2.1"));
+        assert!(crossbranch::branches_related("root.2.1", "root.2"));
+        assert!(!crossbranch::branches_related("root.1", "root.2.1"));
+        assert!(!crossbranch::branches_related("root.1", "root.1"));
+        assert!(!crossbranch::branches_related("root.1", "root.10.1"));
+    }
+}
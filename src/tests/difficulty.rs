@@ -0,0 +1,157 @@
+#[cfg(test)]
+mod tests {
+    use crate::block::Block;
+    use crate::blocktree::Blocktree;
+    use crate::config::BlocktreeConfig;
+    use crate::consensus::{Consensus, ProofOfWork};
+    use crate::difficulty::{self, DEFAULT_POW_LIMIT_BITS, U256};
+    use crate::miner::CancelToken;
+    use crate::network::MockNetwork;
+    use crate::storage::Storage;
+    use crate::tests::support::{funded_config, transfer};
+    use crate::transaction::Transaction;
+    use crate::wallet::Keypair;
+    use chrono::Utc;
+
+    #[test]
+    fn test_compact_round_trip() {
+        let target = difficulty::compact_to_target(0x1d00ffff).unwrap();
+        assert_eq!(
+            target.to_string(),
+            "00000000ffff0000000000000000000000000000000000000000000000000000"
+        );
+        assert_eq!(difficulty::target_to_compact(target), 0x1d00ffff);
+        for bits in [0x207fffff, 0x2000ffff, 0x1b0404cb, 0x03123456, 0x0400ff00] {
+            let target = difficulty::compact_to_target(bits).unwrap();
+            assert_eq!(difficulty::target_to_compact(target), bits);
+        }
+    }
+
+    #[test]
+    fn test_invalid_compact_values() {
+        assert!(difficulty::compact_to_target(0x04923456).is_err()); // negative
+        assert!(difficulty::compact_to_target(0xff123456).is_err()); // overflow
+        assert!(difficulty::compact_to_target(0x00000000).is_err()); // zero
+    }
+
+    #[test]
+    fn test_work_scales_with_target() {
+        // 0x207fffff is ~2^255, so two hashes are expected per block
+        assert_eq!(
+            difficulty::bits_to_work(0x207fffff).unwrap(),
+            U256::from_u64(2)
+        );
+        assert_eq!(
+            difficulty::bits_to_work(0x1d00ffff).unwrap(),
+            U256::from_u64(0x0100010001)
+        );
+        let easy = difficulty::bits_to_work(DEFAULT_POW_LIMIT_BITS).unwrap();
+        let hard = difficulty::bits_to_work(0x1f00ffff).unwrap();
+        assert_eq!(hard / easy, U256::from_u64(256));
+    }
+
+    #[test]
+    fn test_u256_arithmetic() {
+        let value = U256::from_u64(u64::MAX);
+        let shifted = value << 100;
+        assert_eq!(shifted >> 100, value);
+        assert_eq!((value.checked_mul_u64(3).unwrap()).div_u64(3), value);
+        assert_eq!(U256::MAX.checked_add(U256::ONE), None);
+        assert_eq!(shifted / value, U256::ONE << 100);
+    }
+
+    #[test]
+    fn test_mined_block_meets_target() {
+        let keypair = Keypair::from_seed(&[0; 32]);
+        let tx = Transaction::new_signed(&keypair, "receiver".to_string(), 1, 0, "root").unwrap();
+        let mut block = Block::new(1, vec![tx], "0".to_string(), "root".to_string()).unwrap();
+        block.header.bits = 0x207fffff;
+        let mined = ProofOfWork::new(0x207fffff, 1.0)
+            .mine_block(block, &CancelToken::new())
+            .unwrap()
+            .block;
+        assert_eq!(mined.header.bits, 0x207fffff);
+        assert!(difficulty::hash_meets_target(&mined.hash, mined.header.bits).unwrap());
+        assert_eq!(mined.work().unwrap(), U256::from_u64(2));
+    }
+
+    fn chain_with_timestamps(bits: u32, timestamps: &[i64]) -> Vec<Block> {
+        timestamps
+            .iter()
+            .enumerate()
+            .map(|(index, &timestamp)| {
+                let mut block = Block::with_timestamp(
+                    index as u64,
+                    vec![],
+                    "0".to_string(),
+                    "root".to_string(),
+                    timestamp,
+                )
+                .unwrap();
+                block.header.bits = bits;
+                block
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_adjust_difficulty_is_fine_grained() {
+        let pow = ProofOfWork::new(0x1f00ffff, 0.2).with_retarget_window(2);
+        // 1.5x slower than target over the window
+        let slow = chain_with_timestamps(0x1f00ffff, &[0, 300, 600]);
+        assert_eq!(pow.adjust_difficulty(&slow), 0x1f017ffe);
+        // Instant blocks are clamped to a 4x harder target
+        let fast = chain_with_timestamps(0x1f00ffff, &[0, 0, 0]);
+        assert_eq!(pow.adjust_difficulty(&fast), 0x1e3fffbf);
+    }
+
+    #[test]
+    fn test_adjust_difficulty_uses_full_window() {
+        let pow = ProofOfWork::new(0x1f00ffff, 0.2).with_retarget_window(4);
+        assert_eq!(pow.adjust_difficulty(&[]), 0x1f00ffff);
+        // Too few blocks for a window: the last target carries over
+        let short = chain_with_timestamps(0x1e3fffbf, &[0, 0, 0]);
+        assert_eq!(pow.adjust_difficulty(&short), 0x1e3fffbf);
+        // One slow block in an otherwise on-target window only nudges the target
+        let chain = chain_with_timestamps(0x1f00ffff, &[0, 200, 400, 600, 1000]);
+        let nudged = difficulty::compact_to_target(pow.adjust_difficulty(&chain)).unwrap();
+        let base = difficulty::compact_to_target(0x1f00ffff).unwrap();
+        assert!(nudged > base && nudged < base.checked_mul_u64(2).unwrap());
+    }
+
+    #[test]
+    fn test_easier_than_pow_limit_is_capped() {
+        let pow = ProofOfWork::new(DEFAULT_POW_LIMIT_BITS, 0.2).with_retarget_window(2);
+        let slow = chain_with_timestamps(DEFAULT_POW_LIMIT_BITS, &[0, 10_000, 20_000]);
+        assert_eq!(pow.adjust_difficulty(&slow), DEFAULT_POW_LIMIT_BITS);
+    }
+
+    #[test]
+    fn test_branches_record_retargeted_difficulty() {
+        let mut config = BlocktreeConfig {
+            retarget_window: 2,
+            target_block_time: 2.0,
+            ..funded_config()
+        };
+        config.genesis.timestamp = Utc::now().timestamp_millis();
+        let mut blocktree = Blocktree::from_config(config, Box::new(MockNetwork::new())).unwrap();
+        for i in 1..=4 {
+            let tx = transfer(&blocktree, i as u8, &format!("receiver{}", i), 1, "root");
+            blocktree.add_block(vec![tx], "root").unwrap();
+        }
+        for i in 1..=3 {
+            let tx = transfer(&blocktree, i as u8, &format!("receiver{}", i), 1, "root.1");
+            blocktree.add_block(vec![tx], "root.1").unwrap();
+        }
+        let pow = ProofOfWork::new(DEFAULT_POW_LIMIT_BITS, 2.0).with_retarget_window(2);
+        for branch_id in ["root", "root.1"] {
+            let chain = blocktree.storage.get_branch(branch_id).unwrap();
+            // Fast blocks against a 2s target retarget harder than the genesis limit
+            assert_eq!(
+                chain.last().unwrap().header.bits,
+                pow.adjust_difficulty(&chain[..chain.len() - 1])
+            );
+            assert_ne!(chain.last().unwrap().header.bits, DEFAULT_POW_LIMIT_BITS);
+        }
+    }
+}
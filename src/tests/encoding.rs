@@ -0,0 +1,192 @@
+#[cfg(test)]
+mod tests {
+    use crate::block::{Block, BlockHeader, BLOCK_VERSION};
+    use crate::crossbranch;
+    use crate::encoding::{self, ENCODING_VERSION, MIN_ENCODING_VERSION};
+    use crate::transaction::Transaction;
+    use crate::wallet::Keypair;
+
+    const TIMESTAMP: i64 = 1_700_000_000_000;
+
+    fn golden_header() -> BlockHeader {
+        BlockHeader {
+            version: BLOCK_VERSION,
+            index: 7,
+            timestamp: TIMESTAMP,
+            previous_hash: "ab".repeat(32),
+            branch_id: "root.1".to_string(),
+            merkle_root: "cd".repeat(32),
+            bits: 0x2000ffff,
+            nonce: 42,
+        }
+    }
+
+    fn golden_transaction() -> Transaction {
+        let keypair = Keypair::from_seed(&[1; 32]);
+        let mut tx =
+            Transaction::with_timestamp(keypair.address(), "bob".to_string(), 10, TIMESTAMP)
+                .unwrap();
+        tx.fee = 2;
+        tx.nonce = 3;
+        tx.branch_id = "root.1".to_string();
+        tx.sign(&keypair).unwrap();
+        tx
+    }
+
+    // Length-prefixed UTF-8 string, as hex
+    fn string(value: &str) -> String {
+        format!(
+            "{}{}",
+            hex::encode((value.len() as u32).to_le_bytes()),
+            hex::encode(value)
+        )
+    }
+
+    #[test]
+    fn test_header_golden_vector() {
+        let header = golden_header();
+        let expected = [
+            "02".to_string(),               // encoding version
+            "01000000".to_string(),         // block version
+            "0700000000000000".to_string(), // index
+            "0068e5cf8b010000".to_string(), // timestamp
+            string(&"ab".repeat(32)),
+            string("root.1"),
+            string(&"cd".repeat(32)),
+            "ffff0020".to_string(),         // bits
+            "2a00000000000000".to_string(), // nonce
+        ]
+        .concat();
+        assert_eq!(hex::encode(encoding::to_bytes(&header)), expected);
+        assert_eq!(
+            header.calculate_hash().unwrap(),
+            "8c5cef7325f74cd7f447c694d66eaf12d999f95bbd52391d708a7aa9be5a4e30"
+        );
+    }
+
+    #[test]
+    fn test_transaction_golden_vector() {
+        let tx = golden_transaction();
+        let tx_id = "9f493a1a0ec01506558a7be81ceb159b3684e3222057d159cf46f9abc2f9139a";
+        let expected = [
+            "02".to_string(),
+            string("0ffb0d05edcc648bc5dd06e844490b98384df4a1"),
+            string("bob"),
+            "0a00000000000000".to_string(), // amount
+            "0200000000000000".to_string(), // fee
+            "0300000000000000".to_string(), // nonce
+            string("root.1"),
+            "0068e5cf8b010000".to_string(), // timestamp
+            string("8a88e3dd7409f195fd52db2d3cba5d72ca6709bf1d94121bf3748801b40f6f5c"),
+            string(
+                "ff3693eb9664a1bdbc32acffb86ac656906f5d3c8ce00932e5487a7c8597b543\
+                 dea5e69315c576c5b5a8fe6afde8ceec67657c924abb2208a6fcd282b037c202",
+            ),
+            "00".to_string(), // no cross-branch role
+            string(tx_id),
+        ]
+        .concat();
+        assert_eq!(hex::encode(encoding::to_bytes(&tx)), expected);
+        assert_eq!(tx.tx_id, tx_id);
+        assert_eq!(tx.size().unwrap(), expected.len() / 2);
+    }
+
+    #[test]
+    fn test_round_trips() {
+        let header = golden_header();
+        let decoded: BlockHeader = encoding::from_bytes(&encoding::to_bytes(&header)).unwrap();
+        assert_eq!(decoded, header);
+
+        let keypair = Keypair::from_seed(&[1; 32]);
+        let lock =
+            crossbranch::lock(&keypair, "root.2", "bob".to_string(), 5, 1, 0, "root.1").unwrap();
+        let source = Block::new(
+            1,
+            vec![
+                Transaction::coinbase("miner".to_string(), 50, 1, "root.1", 0).unwrap(),
+                lock.clone(),
+            ],
+            "0".to_string(),
+            "root.1".to_string(),
+        )
+        .unwrap();
+        let claim = crossbranch::claim("root.1", &source, &lock.tx_id).unwrap();
+        let block = Block::new(
+            2,
+            vec![golden_transaction(), lock, claim],
+            source.hash.clone(),
+            "root.2".to_string(),
+        )
+        .unwrap();
+
+        let bytes = encoding::to_bytes(&block);
+        let decoded: Block = encoding::from_bytes(&bytes).unwrap();
+        assert_eq!(encoding::to_bytes(&decoded), bytes);
+        assert_eq!(decoded.calculate_hash().unwrap(), block.hash);
+        for (decoded, original) in decoded.transactions.iter().zip(&block.transactions) {
+            assert_eq!(decoded.calculate_hash().unwrap(), original.tx_id);
+            assert!(decoded.is_valid());
+        }
+    }
+
+    #[test]
+    fn test_older_versions_are_read_only_when_stored() {
+        let mut bytes = encoding::to_bytes(&golden_header());
+        bytes[0] = MIN_ENCODING_VERSION;
+        assert!(encoding::from_bytes::<BlockHeader>(&bytes).is_err());
+        let (header, version) = encoding::from_versioned_bytes::<BlockHeader>(&bytes).unwrap();
+        assert_eq!(header, golden_header());
+        assert_eq!(version, MIN_ENCODING_VERSION);
+    }
+
+    #[test]
+    fn test_rejects_malformed_input() {
+        let bytes = encoding::to_bytes(&golden_transaction());
+
+        let mut unknown_version = bytes.clone();
+        unknown_version[0] = ENCODING_VERSION + 1;
+        assert!(encoding::from_bytes::<Transaction>(&unknown_version).is_err());
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(encoding::from_bytes::<Transaction>(&trailing).is_err());
+
+        for len in 0..bytes.len() {
+            assert!(encoding::from_bytes::<Transaction>(&bytes[..len]).is_err());
+        }
+
+        // A count far beyond the input fails without a huge allocation
+        let mut oversized = vec![ENCODING_VERSION];
+        oversized.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert!(encoding::from_bytes::<Vec<Transaction>>(&oversized).is_err());
+    }
+
+    #[test]
+    fn test_rejects_nested_claims() {
+        let tx = golden_transaction();
+        let bytes = encoding::to_bytes(&tx);
+        // Fields before the cross-branch tag, then the tag and the tx id
+        let fields = &bytes[1..bytes.len() - 1 - (4 + tx.tx_id.len())];
+        let tail = &bytes[fields.len() + 1..];
+        let claim_of = |depth: usize| {
+            let mut out = vec![ENCODING_VERSION];
+            for _ in 0..depth {
+                out.extend_from_slice(fields);
+                out.push(2);
+                out.extend_from_slice(&hex::decode(string("root.1")).unwrap());
+                out.extend_from_slice(&hex::decode(string(&"ab".repeat(32))).unwrap());
+            }
+            out.extend_from_slice(fields);
+            out.extend_from_slice(tail);
+            for _ in 0..depth {
+                out.extend_from_slice(&[0; 8 + 8 + 4]); // empty proof
+                out.extend_from_slice(&tail[1..]);
+            }
+            out
+        };
+        assert!(encoding::from_bytes::<Transaction>(&claim_of(1)).is_ok());
+        assert!(encoding::from_bytes::<Transaction>(&claim_of(2)).is_err());
+        // Deep enough to overflow the stack if decoded recursively
+        assert!(encoding::from_bytes::<Transaction>(&claim_of(100_000)).is_err());
+    }
+}
@@ -0,0 +1,246 @@
+#[cfg(test)]
+mod tests {
+    use crate::block::Block;
+    use crate::blocktree::Blocktree;
+    use crate::coin::Coin;
+    use crate::consensus::Consensus;
+    use crate::crossbranch;
+    use crate::error::{BlocktreeError, ValidationError};
+    use crate::forkchoice::{BlockDag, BlockStatus, MAX_ORPHANS};
+    use crate::miner::{CancelToken, Miner};
+    use crate::network::MockNetwork;
+    use crate::storage::Storage;
+    use crate::tests::support::{account, funded_config, transfer, INITIAL_BALANCE};
+    use crate::transaction::Transaction;
+
+    /// Mines a block of `transactions` on the last block of `ancestry`,
+    /// whether or not that block is the active tip.
+    fn mine_on(blocktree: &Blocktree, ancestry: &[Block], transactions: Vec<Transaction>) -> Block {
+        let config = blocktree.config();
+        let parent = ancestry.last().unwrap();
+        let index = parent.header.index + 1;
+        let fees: u64 = transactions.iter().map(|tx| tx.fee).sum();
+        let reward = Coin::with_schedule(config.base_reward, config.decay_factor).reward_at(index);
+        let coinbase = Transaction::coinbase(
+            config.miner_address.clone(),
+            reward + fees,
+            index,
+            &parent.header.branch_id,
+            parent.header.timestamp,
+        )
+        .unwrap();
+        let mut block = Block::new(
+            index,
+            std::iter::once(coinbase).chain(transactions).collect(),
+            parent.hash.clone(),
+            parent.header.branch_id.clone(),
+        )
+        .unwrap();
+        block.header.bits = blocktree.consensus().adjust_difficulty(ancestry);
+        Miner::new(1)
+            .mine(block, &CancelToken::new())
+            .unwrap()
+            .block
+    }
+
+    #[test]
+    fn test_heavier_side_chain_reorganizes() {
+        let mut blocktree =
+            Blocktree::from_config(funded_config(), Box::new(MockNetwork::new())).unwrap();
+        let genesis = blocktree.tip("root").unwrap().clone();
+        let supply = blocktree.get_bkt_supply();
+        let tx = transfer(&blocktree, 1, &account(2).address(), 300, "root");
+        blocktree.add_block(vec![tx.clone()], "root").unwrap();
+        assert_eq!(
+            blocktree.balance_of(&account(2).address(), "root").unwrap(),
+            INITIAL_BALANCE + 300
+        );
+
+        // Equal work keeps the first chain seen
+        let a1 = mine_on(&blocktree, std::slice::from_ref(&genesis), vec![]);
+        assert_eq!(
+            blocktree.process_block(a1.clone(), "root").unwrap(),
+            BlockStatus::SideChain
+        );
+        assert_eq!(blocktree.side_block_count("root").unwrap(), (1, 0));
+
+        let a2 = mine_on(&blocktree, &[genesis, a1.clone()], vec![]);
+        assert_eq!(
+            blocktree.process_block(a2.clone(), "root").unwrap(),
+            BlockStatus::Reorganized {
+                disconnected: 1,
+                connected: 2
+            }
+        );
+        assert_eq!(blocktree.tip("root").unwrap().hash, a2.hash);
+        assert_eq!(
+            blocktree.balance_of(&account(2).address(), "root").unwrap(),
+            INITIAL_BALANCE
+        );
+        assert!(blocktree.mempool("root").unwrap().contains(&tx.tx_id));
+        let coin = Coin::new();
+        assert_eq!(
+            blocktree.get_bkt_supply(),
+            supply + coin.reward_at(1) + coin.reward_at(2)
+        );
+        assert_eq!(blocktree.side_block_count("root").unwrap(), (1, 0));
+        assert!(blocktree.is_branch_valid("root").unwrap());
+    }
+
+    #[test]
+    fn test_orphan_connects_when_parent_arrives() {
+        let mut blocktree =
+            Blocktree::from_config(funded_config(), Box::new(MockNetwork::new())).unwrap();
+        let genesis = blocktree.tip("root").unwrap().clone();
+        let b1 = mine_on(&blocktree, std::slice::from_ref(&genesis), vec![]);
+        let b2 = mine_on(&blocktree, &[genesis, b1.clone()], vec![]);
+
+        assert_eq!(
+            blocktree.process_block(b2.clone(), "root").unwrap(),
+            BlockStatus::Orphan
+        );
+        assert_eq!(
+            blocktree.process_block(b2.clone(), "root").unwrap(),
+            BlockStatus::Duplicate
+        );
+        assert_eq!(blocktree.side_block_count("root").unwrap(), (0, 1));
+
+        assert_eq!(
+            blocktree.process_block(b1.clone(), "root").unwrap(),
+            BlockStatus::Extended
+        );
+        assert_eq!(blocktree.tip("root").unwrap().hash, b2.hash);
+        assert_eq!(blocktree.side_block_count("root").unwrap(), (0, 0));
+        assert_eq!(
+            blocktree.process_block(b1, "root").unwrap(),
+            BlockStatus::Duplicate
+        );
+    }
+
+    #[test]
+    fn test_orphan_must_be_as_hard_as_the_branch() {
+        let mut config = funded_config();
+        config.difficulty_bits = 0x1f7fffff;
+        let mut blocktree = Blocktree::from_config(config, Box::new(MockNetwork::new())).unwrap();
+        let genesis = blocktree.tip("root").unwrap().clone();
+        let b1 = mine_on(&blocktree, std::slice::from_ref(&genesis), vec![]);
+        let mut b2 = mine_on(&blocktree, &[genesis, b1], vec![]);
+        b2.header.bits = 0x2000ffff;
+        let b2 = Miner::new(1).mine(b2, &CancelToken::new()).unwrap().block;
+
+        assert!(matches!(
+            blocktree.process_block(b2, "root"),
+            Err(BlocktreeError::Validation(ValidationError::TargetTooEasy {
+                bits: 0x2000ffff,
+                limit_bits: 0x1f7fffff,
+                ..
+            }))
+        ));
+        assert_eq!(blocktree.side_block_count("root").unwrap(), (0, 0));
+    }
+
+    #[test]
+    fn test_orphan_with_least_work_is_evicted() {
+        let orphan = |n: usize, bits: u32| {
+            let mut block =
+                Block::new(1, vec![], format!("{:064x}", n), "root".to_string()).unwrap();
+            block.header.bits = bits;
+            block.update_hash().unwrap();
+            block
+        };
+        let mut dag = BlockDag::default();
+        let light: Vec<Block> = (0..MAX_ORPHANS).map(|n| orphan(n, 0x2000ffff)).collect();
+        for block in &light {
+            dag.add_orphan(block.clone()).unwrap();
+        }
+
+        // Lighter than every waiting orphan, so not kept
+        let lighter = orphan(MAX_ORPHANS, 0x207fffff);
+        dag.add_orphan(lighter.clone()).unwrap();
+        assert!(!dag.is_orphan(&lighter.hash));
+
+        // Heavier, so the oldest of the lightest makes room
+        let heavier = orphan(MAX_ORPHANS + 1, 0x1f7fffff);
+        dag.add_orphan(heavier.clone()).unwrap();
+        assert!(dag.is_orphan(&heavier.hash));
+        assert!(!dag.is_orphan(&light[0].hash));
+        assert!(dag.is_orphan(&light[1].hash));
+        assert_eq!(dag.orphan_count(), MAX_ORPHANS);
+    }
+
+    #[test]
+    fn test_split_blocks_are_never_rolled_back() {
+        let mut blocktree =
+            Blocktree::from_config(funded_config(), Box::new(MockNetwork::new())).unwrap();
+        let genesis = blocktree.tip("root").unwrap().clone();
+        for _ in 1..blocktree.config().split_interval {
+            blocktree.add_block(vec![], "root").unwrap();
+        }
+        let tip = blocktree.tip("root").unwrap().hash.clone();
+        assert!(blocktree.get_branches().contains(&"root.1".to_string()));
+
+        // A heavier chain forking below the split point stays a side chain
+        let mut side = vec![genesis];
+        for _ in 0..blocktree.config().split_interval {
+            let block = mine_on(&blocktree, &side, vec![]);
+            assert_eq!(
+                blocktree.process_block(block.clone(), "root").unwrap(),
+                BlockStatus::SideChain
+            );
+            side.push(block);
+        }
+        assert_eq!(blocktree.tip("root").unwrap().hash, tip);
+        assert!(blocktree.is_branch_valid("root").unwrap());
+    }
+
+    #[test]
+    fn test_claimed_locks_are_never_rolled_back() {
+        let mut blocktree =
+            Blocktree::from_config(funded_config(), Box::new(MockNetwork::new())).unwrap();
+        for _ in 1..blocktree.config().split_interval {
+            blocktree.add_block(vec![], "root").unwrap();
+        }
+        let before_lock = blocktree.storage.get_branch("root").unwrap().clone();
+        let alice = account(1);
+        let nonce = blocktree.nonce_of(&alice.address(), "root").unwrap();
+        let lock = crossbranch::lock(
+            &alice,
+            "root.1",
+            account(2).address(),
+            500,
+            0,
+            nonce,
+            "root",
+        )
+        .unwrap();
+        blocktree.add_block(vec![lock.clone()], "root").unwrap();
+        for _ in 0..blocktree.config().claim_depth {
+            blocktree.add_block(vec![], "root").unwrap();
+        }
+        let claim = blocktree.claim_transfer("root", &lock.tx_id).unwrap();
+        blocktree.add_block(vec![claim], "root.1").unwrap();
+        let tip = blocktree.tip("root").unwrap().hash.clone();
+
+        // A heavier chain without the lock would mint the amount twice
+        let mut side = before_lock;
+        for _ in 0..blocktree.config().claim_depth + 2 {
+            let block = mine_on(&blocktree, &side, vec![]);
+            assert_eq!(
+                blocktree.process_block(block.clone(), "root").unwrap(),
+                BlockStatus::SideChain
+            );
+            side.push(block);
+        }
+        assert_eq!(blocktree.tip("root").unwrap().hash, tip);
+        assert_eq!(
+            blocktree.balance_of(&alice.address(), "root").unwrap(),
+            INITIAL_BALANCE - 500
+        );
+        assert_eq!(
+            blocktree
+                .balance_of(&account(2).address(), "root.1")
+                .unwrap(),
+            INITIAL_BALANCE + 500
+        );
+    }
+}
@@ -0,0 +1,162 @@
+#[cfg(test)]
+mod tests {
+    use crate::blocktree::Blocktree;
+    use crate::gossip::{
+        request_timeout, TxGossip, MAX_REQUESTED_TRANSACTIONS, REQUEST_TIMEOUT_MS,
+    };
+    use crate::network::{Message, NetworkEvent};
+    use crate::tests::support::{account, hub_nodes, transfer, Envelope};
+    use std::sync::mpsc;
+
+    /// Delivers messages until none are left, counting `GetTransactions`.
+    fn pump(nodes: &mut [Blocktree], inbox: &mpsc::Receiver<Envelope>) -> usize {
+        let mut requests = 0;
+        while let Ok((from, to, message)) = inbox.try_recv() {
+            if matches!(message, Message::GetTransactions { .. }) {
+                requests += 1;
+            }
+            nodes[to as usize - 1]
+                .handle_network_event(NetworkEvent::from_message(from, message))
+                .unwrap();
+        }
+        requests
+    }
+
+    fn inventory(tx_id: &str) -> Message {
+        Message::Inventory {
+            branch_id: "root".to_string(),
+            tx_ids: vec![tx_id.to_string()],
+        }
+    }
+
+    #[test]
+    fn test_submitted_transaction_reaches_every_node_once() {
+        let (mut nodes, inbox) = hub_nodes(3);
+        let tx = transfer(&nodes[0], 1, &account(2).address(), 100, "root");
+        assert!(nodes[0].submit_transaction(tx.clone(), "root").unwrap());
+
+        // Each peer fetches it once, ignoring the other's announcement
+        assert_eq!(pump(&mut nodes, &inbox), 2);
+        for node in &nodes {
+            assert!(node.mempool("root").unwrap().contains(&tx.tx_id));
+        }
+
+        nodes[2].mine_pending("root").unwrap();
+        let block = nodes[2].tip("root").unwrap();
+        assert!(block.transactions.iter().any(|t| t.tx_id == tx.tx_id));
+    }
+
+    #[test]
+    fn test_announced_transaction_is_requested_from_one_peer() {
+        let (mut nodes, inbox) = hub_nodes(3);
+        let tx = transfer(&nodes[1], 1, &account(2).address(), 100, "root");
+        for peer in [2, 3] {
+            let event = NetworkEvent::from_message(peer, inventory(&tx.tx_id));
+            nodes[0].handle_network_event(event).unwrap();
+        }
+        let sent: Vec<Envelope> = inbox.try_iter().collect();
+        assert_eq!(sent.len(), 1);
+        assert!(matches!(sent[0], (1, 2, Message::GetTransactions { .. })));
+
+        // Once the asked peer is gone, another announcing it is asked
+        nodes[0]
+            .handle_network_event(NetworkEvent::PeerDisconnected(2))
+            .unwrap();
+        let event = NetworkEvent::from_message(3, inventory(&tx.tx_id));
+        nodes[0].handle_network_event(event).unwrap();
+        let sent: Vec<Envelope> = inbox.try_iter().collect();
+        assert!(matches!(
+            sent[..],
+            [(1, 3, Message::GetTransactions { .. })]
+        ));
+    }
+
+    #[test]
+    fn test_confirmed_transaction_is_not_requested() {
+        let (mut nodes, inbox) = hub_nodes(2);
+        let tx = transfer(&nodes[0], 1, &account(2).address(), 100, "root");
+        nodes[0].add_block(vec![tx.clone()], "root").unwrap();
+        let event = NetworkEvent::from_message(2, inventory(&tx.tx_id));
+        nodes[0].handle_network_event(event).unwrap();
+        assert!(inbox.try_recv().is_err());
+    }
+
+    const TIMEOUT: i64 = REQUEST_TIMEOUT_MS;
+
+    #[test]
+    fn test_undelivered_request_moves_to_next_announcer() {
+        let mut gossip = TxGossip::new();
+        let ids = vec!["a".to_string()];
+        assert_eq!(
+            gossip.on_inventory(1, "root", ids.clone(), 0, TIMEOUT, |_| false),
+            ids
+        );
+        assert!(gossip
+            .on_inventory(2, "root", ids.clone(), 5, TIMEOUT, |_| false)
+            .is_empty());
+        assert!(gossip.expire(TIMEOUT - 1, |_| TIMEOUT).is_empty());
+
+        let retries = gossip.expire(TIMEOUT, |_| TIMEOUT);
+        assert_eq!(retries, vec![(2, "root".to_string(), "a".to_string())]);
+        // Only peers asked may deliver it
+        assert!(!gossip.on_transaction(3, "a"));
+
+        // With nobody left to ask, the next announcement is asked again
+        assert!(gossip.expire(2 * TIMEOUT, |_| TIMEOUT).is_empty());
+        let later = 2 * TIMEOUT;
+        assert_eq!(
+            gossip.on_inventory(3, "root", ids.clone(), later, TIMEOUT, |_| false),
+            ids
+        );
+        assert!(gossip.on_transaction(3, "a"));
+    }
+
+    #[test]
+    fn test_late_delivery_is_taken_while_unknown() {
+        let mut gossip = TxGossip::new();
+        let ids = vec!["a".to_string(), "b".to_string()];
+        gossip.on_inventory(1, "root", ids, 0, TIMEOUT, |_| false);
+        assert!(gossip.expire(TIMEOUT, |_| TIMEOUT).is_empty());
+
+        assert!(gossip.on_transaction(1, "a"));
+        gossip.insert("a");
+        assert!(!gossip.on_transaction(1, "a"));
+        // Seen from elsewhere by the time the slow peer delivers it
+        gossip.insert("b");
+        assert!(!gossip.on_transaction(1, "b"));
+    }
+
+    #[test]
+    fn test_request_timeout_allows_for_round_trip() {
+        assert_eq!(request_timeout(f64::INFINITY), REQUEST_TIMEOUT_MS);
+        assert_eq!(request_timeout(100.0), REQUEST_TIMEOUT_MS + 200);
+        let mars = 20.0 * 60.0 * 1000.0;
+        assert_eq!(request_timeout(mars), REQUEST_TIMEOUT_MS + 2_400_000);
+    }
+
+    #[test]
+    fn test_outstanding_requests_are_capped() {
+        let mut gossip = TxGossip::new();
+        let flood: Vec<String> = (0..MAX_REQUESTED_TRANSACTIONS + 1000)
+            .map(|i| i.to_string())
+            .collect();
+        let mut asked = 0;
+        for chunk in flood.chunks(1000) {
+            asked += gossip
+                .on_inventory(1, "root", chunk.to_vec(), 0, TIMEOUT, |_| false)
+                .len();
+        }
+        assert_eq!(asked, MAX_REQUESTED_TRANSACTIONS);
+        let honest = vec!["honest".to_string()];
+        assert!(gossip
+            .on_inventory(2, "root", honest.clone(), 0, TIMEOUT, |_| false)
+            .is_empty());
+
+        // The flood expires, making room again
+        assert!(gossip.expire(TIMEOUT, |_| TIMEOUT).is_empty());
+        assert_eq!(
+            gossip.on_inventory(2, "root", honest.clone(), TIMEOUT, TIMEOUT, |_| false),
+            honest
+        );
+    }
+}
@@ -2,7 +2,8 @@
 mod tests {
     use crate::blocktree::Blocktree;
     use crate::network::MockNetwork;
-    use crate::transaction::Transaction;
+    use crate::storage::Storage;
+    use crate::tests::support::{funded_config, transfer};
 
     #[test]
     fn test_genesis_block() {
@@ -10,21 +11,16 @@ mod tests {
         let blocktree = Blocktree::new(network);
         let root_branch = blocktree.storage.get_branch("root").unwrap();
         assert_eq!(root_branch.len(), 1);
-        assert_eq!(root_branch[0].index, 0);
+        assert_eq!(root_branch[0].header.index, 0);
         assert_eq!(root_branch[0].transactions[0].sender, "genesis");
     }
 
     #[test]
     fn test_add_block_and_split() {
         let network = Box::new(MockNetwork::new());
-        let mut blocktree = Blocktree::new(network);
+        let mut blocktree = Blocktree::from_config(funded_config(), network).unwrap();
         for i in 1..=5 {
-            let tx = Transaction::new(
-                format!("sender{}", i),
-                format!("receiver{}", i),
-                100,
-            )
-            .unwrap();
+            let tx = transfer(&blocktree, i as u8, &format!("receiver{}", i), 100, "root");
             blocktree
                 .add_block(vec![tx], "root")
                 .expect("Failed to add block");
@@ -36,11 +32,11 @@ mod tests {
     #[test]
     fn test_branch_validation() {
         let network = Box::new(MockNetwork::new());
-        let mut blocktree = Blocktree::new(network);
-        let tx = Transaction::new("sender".to_string(), "receiver".to_string(), 100).unwrap();
+        let mut blocktree = Blocktree::from_config(funded_config(), network).unwrap();
+        let tx = transfer(&blocktree, 0, "receiver", 100, "root");
         blocktree
             .add_block(vec![tx], "root")
             .expect("Failed to add block");
         assert!(blocktree.is_branch_valid("root").unwrap());
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,250 @@
+#[cfg(test)]
+mod tests {
+    use crate::blocktree::{Blocktree, BlocktreeBuilder};
+    use crate::coin::Coin;
+    use crate::error::{BlocktreeError, ValidationError};
+    use crate::ledger::{self, Ledger};
+    use crate::network::MockNetwork;
+    use crate::storage::{FileStorage, Storage};
+    use crate::tests::support::{account, funded_config, transfer, INITIAL_BALANCE};
+    use crate::transaction::Transaction;
+
+    fn funded_blocktree() -> Blocktree {
+        Blocktree::from_config(funded_config(), Box::new(MockNetwork::new())).unwrap()
+    }
+
+    #[test]
+    fn test_transfers_move_balances() {
+        let mut blocktree = funded_blocktree();
+        let bob = account(2).address();
+        let tx = transfer(&blocktree, 1, &bob, 300, "root");
+        blocktree.add_block(vec![tx], "root").unwrap();
+        let alice = account(1).address();
+        assert_eq!(
+            blocktree.balance_of(&alice, "root").unwrap(),
+            INITIAL_BALANCE - 300
+        );
+        assert_eq!(
+            blocktree.balance_of(&bob, "root").unwrap(),
+            INITIAL_BALANCE + 300
+        );
+        assert_eq!(blocktree.nonce_of(&alice, "root").unwrap(), 1);
+        assert_eq!(blocktree.nonce_of(&bob, "root").unwrap(), 0);
+        assert!(blocktree.balance_of(&alice, "missing").is_err());
+    }
+
+    #[test]
+    fn test_reward_decays_in_integers() {
+        let coin = Coin::with_schedule(1_000_000_000, 0.999);
+        assert_eq!(coin.reward_at(0), 1_000_000_000);
+        assert_eq!(coin.reward_at(1), 999_000_000);
+        // floor(10^9 * 0.999^1000), exactly
+        assert_eq!(coin.reward_at(1000), 367_695_424);
+        assert_eq!(coin.reward_at(u64::MAX), 0);
+
+        let halving = Coin::with_schedule(1 << 20, 0.5);
+        for height in 0..=20 {
+            assert_eq!(halving.reward_at(height), (1 << 20) >> height);
+        }
+        assert_eq!(halving.reward_at(21), 0);
+        assert_eq!(Coin::with_schedule(50, 1.0).reward_at(u64::MAX), 50);
+    }
+
+    #[test]
+    fn test_coinbase_pays_miner() {
+        let mut config = funded_config();
+        config.miner_address = account(9).address();
+        let mut blocktree = Blocktree::from_config(config, Box::new(MockNetwork::new())).unwrap();
+        blocktree.add_block(vec![], "root").unwrap();
+        blocktree.add_block(vec![], "root").unwrap();
+        // 50 * 0.999^height, truncated, at heights 1 and 2
+        let miner = account(9).address();
+        assert_eq!(
+            blocktree.balance_of(&miner, "root").unwrap(),
+            INITIAL_BALANCE + 49 + 49
+        );
+        assert_eq!(blocktree.get_bkt_supply(), 98);
+        let coinbase = &blocktree.storage.get_branch("root").unwrap()[1].transactions[0];
+        assert!(coinbase.is_coinbase());
+        assert_eq!(coinbase.receiver, miner);
+        assert!(blocktree.is_branch_valid("root").unwrap());
+    }
+
+    #[test]
+    fn test_fees_are_paid_to_the_miner() {
+        let mut config = funded_config();
+        config.miner_address = account(9).address();
+        let mut blocktree = Blocktree::from_config(config, Box::new(MockNetwork::new())).unwrap();
+        let alice = account(1);
+        let tx =
+            Transaction::new_signed_with_fee(&alice, "bob".to_string(), 100, 7, 0, "root").unwrap();
+        blocktree.add_block(vec![tx], "root").unwrap();
+        assert_eq!(
+            blocktree.balance_of(&alice.address(), "root").unwrap(),
+            INITIAL_BALANCE - 107
+        );
+        assert_eq!(
+            blocktree.balance_of(&account(9).address(), "root").unwrap(),
+            INITIAL_BALANCE + 49 + 7
+        );
+        assert!(blocktree.is_branch_valid("root").unwrap());
+
+        // The fee counts towards what the sender must hold
+        let all_in = Transaction::new_signed_with_fee(
+            &alice,
+            "bob".to_string(),
+            INITIAL_BALANCE - 107,
+            1,
+            1,
+            "root",
+        )
+        .unwrap();
+        assert!(matches!(
+            blocktree.add_block(vec![all_in], "root"),
+            Err(BlocktreeError::Validation(ValidationError::Overspend { required, .. }))
+                if required == INITIAL_BALANCE - 106
+        ));
+    }
+
+    #[test]
+    fn test_overspend_is_rejected() {
+        let mut blocktree = funded_blocktree();
+        let tx = transfer(&blocktree, 1, "bob", INITIAL_BALANCE + 1, "root");
+        match blocktree.add_block(vec![tx], "root") {
+            Err(BlocktreeError::Validation(ValidationError::Overspend { balance, .. })) => {
+                assert_eq!(balance, INITIAL_BALANCE)
+            }
+            other => panic!("expected overspend, got {:?}", other),
+        }
+        // An unfunded address cannot spend at all
+        let tx = Transaction::new_signed(&account(42), "bob".to_string(), 1, 0, "root").unwrap();
+        assert!(blocktree.add_block(vec![tx], "root").is_err());
+    }
+
+    #[test]
+    fn test_replayed_transaction_is_rejected() {
+        let mut blocktree = funded_blocktree();
+        let tx = transfer(&blocktree, 1, "bob", 10, "root");
+        blocktree.add_block(vec![tx.clone()], "root").unwrap();
+        match blocktree.add_block(vec![tx], "root") {
+            Err(BlocktreeError::Validation(ValidationError::BadNonce {
+                expected, found, ..
+            })) => {
+                assert_eq!((expected, found), (1, 0))
+            }
+            other => panic!("expected bad nonce, got {:?}", other),
+        }
+        assert_eq!(blocktree.balance_of("bob", "root").unwrap(), 10);
+    }
+
+    #[test]
+    fn test_block_is_applied_atomically() {
+        let genesis = funded_config().genesis_block().unwrap();
+        let mut ledger = Ledger::from_genesis(&genesis).unwrap();
+        let alice = account(1);
+        let mut block = genesis.clone();
+        block.header.index = 1;
+        block.transactions = vec![
+            Transaction::new_signed(&alice, "bob".to_string(), 10, 0, "root").unwrap(),
+            Transaction::new_signed(&alice, "bob".to_string(), INITIAL_BALANCE, 1, "root").unwrap(),
+        ];
+        assert!(ledger.apply_block(&block).is_err());
+        assert_eq!(ledger.balance_of(&alice.address()), INITIAL_BALANCE);
+        assert_eq!(ledger.nonce_of(&alice.address()), 0);
+    }
+
+    #[test]
+    fn test_branches_keep_separate_state_after_split() {
+        let mut blocktree = funded_blocktree();
+        for i in 1..=4 {
+            let tx = transfer(&blocktree, i, "bob", 100, "root");
+            blocktree.add_block(vec![tx], "root").unwrap();
+        }
+        assert_eq!(blocktree.balance_of("bob", "root.1").unwrap(), 400);
+        let tx = transfer(&blocktree, 1, "bob", 50, "root.1");
+        assert_eq!(tx.nonce, 1);
+        blocktree.add_block(vec![tx], "root.1").unwrap();
+        assert_eq!(blocktree.balance_of("bob", "root.1").unwrap(), 450);
+        assert_eq!(blocktree.balance_of("bob", "root.2").unwrap(), 400);
+        assert!(blocktree.validate_branch("root.1").unwrap().is_valid());
+    }
+
+    #[test]
+    fn test_transfers_cannot_be_replayed_on_a_sibling_branch() {
+        let mut blocktree = funded_blocktree();
+        for i in 1..=4 {
+            let tx = transfer(&blocktree, i, "bob", 100, "root");
+            blocktree.add_block(vec![tx], "root").unwrap();
+        }
+        // Both siblings start from the same ledger, so the nonce matches
+        let tx = transfer(&blocktree, 1, "bob", 50, "root.1");
+        blocktree.add_block(vec![tx.clone()], "root.1").unwrap();
+        assert!(blocktree.submit_transaction(tx.clone(), "root.2").is_err());
+        assert!(matches!(
+            blocktree.add_block(vec![tx], "root.2"),
+            Err(BlocktreeError::Validation(
+                ValidationError::BadTransaction { .. }
+            ))
+        ));
+        assert_eq!(blocktree.balance_of("bob", "root.2").unwrap(), 400);
+    }
+
+    #[test]
+    fn test_ledgers_are_rebuilt_from_storage() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let mut blocktree =
+                BlocktreeBuilder::from_config(funded_config(), Box::new(MockNetwork::new()))
+                    .storage(FileStorage::open(dir.path()).unwrap())
+                    .build()
+                    .unwrap();
+            for i in 1..=4 {
+                let tx = transfer(&blocktree, i, "bob", 100, "root");
+                blocktree.add_block(vec![tx], "root").unwrap();
+            }
+            let tx = transfer(&blocktree, 5, "bob", 7, "root.2");
+            blocktree.add_block(vec![tx], "root.2").unwrap();
+        }
+        let blocktree =
+            BlocktreeBuilder::from_config(funded_config(), Box::new(MockNetwork::new()))
+                .storage(FileStorage::open(dir.path()).unwrap())
+                .build()
+                .unwrap();
+        assert_eq!(blocktree.balance_of("bob", "root").unwrap(), 400);
+        assert_eq!(blocktree.balance_of("bob", "root.1").unwrap(), 400);
+        assert_eq!(blocktree.balance_of("bob", "root.2").unwrap(), 407);
+        assert_eq!(
+            blocktree.nonce_of(&account(5).address(), "root.2").unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_supply_is_rebuilt_from_storage() {
+        let dir = tempfile::tempdir().unwrap();
+        let open = || {
+            BlocktreeBuilder::from_config(funded_config(), Box::new(MockNetwork::new()))
+                .storage(FileStorage::open(dir.path()).unwrap())
+                .build()
+                .unwrap()
+        };
+        let supply = {
+            let mut blocktree = open();
+            for _ in 0..4 {
+                blocktree.add_block(vec![], "root").unwrap();
+            }
+            blocktree.add_block(vec![], "root.1").unwrap();
+            blocktree.add_block(vec![], "root.2").unwrap();
+            blocktree.get_bkt_supply()
+        };
+        assert_eq!(supply, 6 * 49);
+        assert_eq!(open().get_bkt_supply(), supply);
+    }
+
+    #[test]
+    fn test_parent_branch() {
+        assert_eq!(ledger::parent_branch("root"), None);
+        assert_eq!(ledger::parent_branch("root.1"), Some("root"));
+        assert_eq!(ledger::parent_branch("root.1.2"), Some("root.1"));
+    }
+}
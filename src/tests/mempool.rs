@@ -0,0 +1,184 @@
+#[cfg(test)]
+mod tests {
+    use crate::blocktree::Blocktree;
+    use crate::ledger::Ledger;
+    use crate::mempool::Mempool;
+    use crate::network::MockNetwork;
+    use crate::storage::Storage;
+    use crate::tests::support::{account, funded_config, INITIAL_BALANCE};
+    use crate::transaction::Transaction;
+
+    fn funded_blocktree() -> Blocktree {
+        Blocktree::from_config(funded_config(), Box::new(MockNetwork::new())).unwrap()
+    }
+
+    fn funded_ledger() -> Ledger {
+        Ledger::from_genesis(&funded_config().genesis_block().unwrap()).unwrap()
+    }
+
+    fn signed(sender: u8, amount: u64, nonce: u64) -> Transaction {
+        Transaction::new_signed(&account(sender), "bob".to_string(), amount, nonce, "root").unwrap()
+    }
+
+    fn with_fee(sender: u8, fee: u64, nonce: u64) -> Transaction {
+        Transaction::new_signed_with_fee(
+            &account(sender),
+            "bob".to_string(),
+            10,
+            fee,
+            nonce,
+            "root",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_submit_validates_and_deduplicates() {
+        let mut blocktree = funded_blocktree();
+        let tx = signed(1, 100, 0);
+        assert!(blocktree.submit_transaction(tx.clone(), "root").unwrap());
+        assert!(!blocktree.submit_transaction(tx.clone(), "root").unwrap());
+        assert_eq!(blocktree.mempool("root").unwrap().len(), 1);
+        assert!(blocktree.mempool("root").unwrap().contains(&tx.tx_id));
+
+        let mut forged = signed(2, 100, 0);
+        forged.amount = 500;
+        forged.tx_id = forged.calculate_hash().unwrap();
+        assert!(blocktree.submit_transaction(forged, "root").is_err());
+        let overspend = signed(2, INITIAL_BALANCE + 1, 0);
+        assert!(blocktree.submit_transaction(overspend, "root").is_err());
+        let coinbase = Transaction::coinbase("miner".to_string(), 50, 1, "root", 0).unwrap();
+        assert!(blocktree.submit_transaction(coinbase, "root").is_err());
+        // Same sender and nonce as a pending transaction
+        assert!(blocktree
+            .submit_transaction(signed(1, 200, 0), "root")
+            .is_err());
+        assert!(blocktree
+            .submit_transaction(signed(3, 1, 0), "missing")
+            .is_err());
+        assert_eq!(blocktree.mempool("root").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_mined_block_takes_pending_transactions_in_nonce_order() {
+        let mut blocktree = funded_blocktree();
+        // Submitted out of order; the template waits for nonce 0
+        blocktree
+            .submit_transaction(signed(1, 10, 1), "root")
+            .unwrap();
+        blocktree
+            .submit_transaction(signed(1, 20, 0), "root")
+            .unwrap();
+        blocktree
+            .submit_transaction(signed(2, 30, 0), "root")
+            .unwrap();
+        blocktree.mine_pending("root").unwrap();
+
+        let block = blocktree.storage.get_branch("root").unwrap()[1].clone();
+        let nonces: Vec<(String, u64)> = block.transactions[1..]
+            .iter()
+            .map(|tx| (tx.sender.clone(), tx.nonce))
+            .collect();
+        assert_eq!(nonces.len(), 3);
+        let alice = account(1).address();
+        let alice_nonces: Vec<u64> = nonces
+            .iter()
+            .filter(|(sender, _)| *sender == alice)
+            .map(|(_, nonce)| *nonce)
+            .collect();
+        assert_eq!(alice_nonces, vec![0, 1]);
+        assert!(blocktree.mempool("root").unwrap().is_empty());
+        assert_eq!(blocktree.nonce_of(&alice, "root").unwrap(), 2);
+        // Already applied, so it is stale now
+        assert!(blocktree
+            .submit_transaction(signed(1, 20, 0), "root")
+            .is_err());
+    }
+
+    #[test]
+    fn test_template_respects_limit_and_arrival_order() {
+        let ledger = funded_ledger();
+        let mut mempool = Mempool::default();
+        let txs: Vec<Transaction> = (1..=4).map(|n| signed(n, 10, 0)).collect();
+        for tx in &txs {
+            mempool.insert(tx.clone(), "root", &ledger).unwrap();
+        }
+        let template: Vec<String> = mempool
+            .block_template(&ledger, 2)
+            .into_iter()
+            .map(|tx| tx.tx_id)
+            .collect();
+        assert_eq!(template, vec![txs[0].tx_id.clone(), txs[1].tx_id.clone()]);
+        // A gap in the nonces keeps a transaction out of the template
+        mempool.insert(signed(5, 10, 1), "root", &ledger).unwrap();
+        assert_eq!(mempool.block_template(&ledger, 10).len(), 4);
+    }
+
+    #[test]
+    fn test_full_pool_rejects_without_changing() {
+        let ledger = funded_ledger();
+        let first = signed(1, 10, 0);
+        let size = first.size().unwrap();
+        let mut mempool = Mempool::new(size * 2 + size / 2);
+        mempool.insert(first, "root", &ledger).unwrap();
+        mempool.insert(signed(2, 10, 0), "root", &ledger).unwrap();
+        let bytes = mempool.size_bytes();
+        // Equal fee rates keep the earlier arrivals
+        assert!(mempool.insert(signed(3, 10, 0), "root", &ledger).is_err());
+        assert_eq!(mempool.len(), 2);
+        assert_eq!(mempool.size_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_higher_fees_go_first_and_evict_cheaper() {
+        let ledger = funded_ledger();
+        let cheap = with_fee(1, 1, 0);
+        let size = cheap.size().unwrap();
+        let mut mempool = Mempool::new(size * 2 + size / 2);
+        mempool.insert(cheap.clone(), "root", &ledger).unwrap();
+        mempool
+            .insert(with_fee(2, 500, 0), "root", &ledger)
+            .unwrap();
+        let rich = with_fee(3, 900, 0);
+        mempool.insert(rich.clone(), "root", &ledger).unwrap();
+        assert_eq!(mempool.len(), 2);
+        assert!(!mempool.contains(&cheap.tx_id));
+        assert_eq!(mempool.block_template(&ledger, 1)[0].tx_id, rich.tx_id);
+
+        // Replacing a pending transaction needs a higher fee rate
+        let same_fee =
+            Transaction::new_signed_with_fee(&account(3), "carol".to_string(), 10, 900, 0, "root")
+                .unwrap();
+        assert!(mempool.insert(same_fee, "root", &ledger).is_err());
+        let bumped = with_fee(3, 1_000, 0);
+        mempool.insert(bumped.clone(), "root", &ledger).unwrap();
+        assert!(!mempool.contains(&rich.tx_id));
+        assert!(mempool.contains(&bumped.tx_id));
+    }
+
+    #[test]
+    fn test_fee_estimate_follows_blocks_and_congestion() {
+        let mut config = funded_config();
+        config.max_block_transactions = 2;
+        let mut blocktree = Blocktree::from_config(config, Box::new(MockNetwork::new())).unwrap();
+        assert_eq!(blocktree.estimate_fee_rate("root").unwrap(), 0);
+
+        let paid: Vec<Transaction> = [100, 300, 500]
+            .iter()
+            .enumerate()
+            .map(|(n, fee)| with_fee(n as u8 + 1, *fee, 0))
+            .collect();
+        let median = paid[1].fee_rate().unwrap();
+        blocktree.add_block(paid, "root").unwrap();
+        assert_eq!(blocktree.estimate_fee_rate("root").unwrap(), median);
+
+        // Three pending for two slots: outbid the second best
+        for (n, fee) in [(4, 2_000), (5, 3_000), (6, 4_000)] {
+            blocktree
+                .submit_transaction(with_fee(n, fee, 0), "root")
+                .unwrap();
+        }
+        let second = with_fee(5, 3_000, 0).fee_rate().unwrap();
+        assert_eq!(blocktree.estimate_fee_rate("root").unwrap(), second + 1);
+    }
+}
@@ -0,0 +1,90 @@
+#[cfg(test)]
+mod tests {
+    use crate::block::Block;
+    use crate::blocktree::Blocktree;
+    use crate::difficulty;
+    use crate::error::BlocktreeError;
+    use crate::forkchoice::BlockStatus;
+    use crate::miner::{CancelToken, Miner};
+    use crate::network::MockNetwork;
+    use crate::tests::support::{account, funded_config, transfer};
+    use std::thread;
+    use std::time::Duration;
+
+    // About one in 4096 hashes meets this target
+    const BITS: u32 = 0x1f0fffff;
+    // Only a hash of zero meets this target
+    const IMPOSSIBLE_BITS: u32 = 0x03000001;
+
+    fn unmined_block(bits: u32) -> Block {
+        let mut block =
+            Block::with_timestamp(1, vec![], "0".to_string(), "root".to_string(), 1_000).unwrap();
+        block.header.bits = bits;
+        block
+    }
+
+    #[test]
+    fn test_workers_find_block_meeting_target() {
+        let mined = Miner::new(4)
+            .mine(unmined_block(BITS), &CancelToken::new())
+            .unwrap();
+        assert!(difficulty::hash_meets_target(&mined.block.hash, BITS).unwrap());
+        assert_eq!(mined.block.hash, mined.block.calculate_hash().unwrap());
+        assert!(mined.stats.hashes > 0);
+        assert!(mined.stats.hashrate() > 0.0);
+    }
+
+    #[test]
+    fn test_exhausted_nonces_roll_timestamp() {
+        let block = unmined_block(BITS);
+        let mined = Miner::new(2)
+            .with_nonce_space(4)
+            .mine(block.clone(), &CancelToken::new())
+            .unwrap();
+        assert!(mined.block.header.nonce < 4);
+        assert!(mined.block.header.timestamp > block.header.timestamp);
+        assert!(difficulty::hash_meets_target(&mined.block.hash, BITS).unwrap());
+    }
+
+    #[test]
+    fn test_cancel_stops_mining() {
+        let cancelled = CancelToken::new();
+        cancelled.cancel();
+        assert!(matches!(
+            Miner::new(2).mine(unmined_block(BITS), &cancelled),
+            Err(BlocktreeError::MiningCancelled(_))
+        ));
+
+        let cancel = CancelToken::new();
+        let canceller = cancel.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            canceller.cancel();
+        });
+        let result = Miner::new(2).mine(unmined_block(IMPOSSIBLE_BITS), &cancel);
+        handle.join().unwrap();
+        assert!(matches!(result, Err(BlocktreeError::MiningCancelled(_))));
+    }
+
+    #[test]
+    fn test_competing_block_cancels_stale_mining() {
+        let mut blocktree =
+            Blocktree::from_config(funded_config(), Box::new(MockNetwork::new())).unwrap();
+        let tx = transfer(&blocktree, 1, &account(2).address(), 10, "root");
+        let (stale, cancel) = blocktree.prepare_block(vec![tx], "root").unwrap();
+        assert!(!cancel.is_cancelled());
+
+        blocktree.add_block(vec![], "root").unwrap();
+        assert!(cancel.is_cancelled());
+        assert!(blocktree.mining_stats().unwrap().hashes > 0);
+
+        // The stale block, mined anyway, only competes with the new tip
+        let mined = Miner::new(1).mine(stale, &CancelToken::new()).unwrap();
+        assert_eq!(
+            blocktree.commit_block(mined.block, "root").unwrap(),
+            BlockStatus::SideChain
+        );
+        let (_, next) = blocktree.prepare_block(vec![], "root").unwrap();
+        assert!(!next.is_cancelled());
+    }
+}
@@ -0,0 +1,21 @@
+mod block;
+mod blocktree;
+mod config;
+mod crossbranch;
+mod difficulty;
+mod encoding;
+mod forkchoice;
+mod gossip;
+mod integration;
+mod ledger;
+mod mempool;
+mod miner;
+mod network;
+mod p2p;
+mod service;
+mod simulation;
+mod storage;
+pub(crate) mod support;
+mod sync;
+mod transaction;
+mod validation;
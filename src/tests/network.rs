@@ -0,0 +1,129 @@
+#[cfg(test)]
+mod tests {
+    use crate::blocktree::Blocktree;
+    use crate::network::{Message, NetworkEvent, PeerId};
+    use crate::tests::support::{account, hub_nodes, transfer, Envelope};
+    use crate::transaction::Transaction;
+    use std::sync::mpsc;
+
+    /// Messages queued so far, without delivering them.
+    fn drain(inbox: &mpsc::Receiver<Envelope>) -> Vec<Envelope> {
+        inbox.try_iter().collect()
+    }
+
+    /// Delivers messages until none are left.
+    fn pump(nodes: &mut [Blocktree], inbox: &mpsc::Receiver<Envelope>) {
+        while let Ok((from, to, message)) = inbox.try_recv() {
+            nodes[to as usize - 1]
+                .handle_network_event(NetworkEvent::from_message(from, message))
+                .unwrap();
+        }
+    }
+
+    fn receivers(sent: &[Envelope]) -> Vec<PeerId> {
+        let mut receivers: Vec<PeerId> = sent.iter().map(|(_, to, _)| *to).collect();
+        receivers.sort();
+        receivers
+    }
+
+    fn announce(peer: PeerId, tx: &Transaction) -> NetworkEvent {
+        NetworkEvent::from_message(
+            peer,
+            Message::Inventory {
+                branch_id: "root".to_string(),
+                tx_ids: vec![tx.tx_id.clone()],
+            },
+        )
+    }
+
+    fn deliver(peer: PeerId, tx: &Transaction) -> NetworkEvent {
+        NetworkEvent::Transaction {
+            peer,
+            branch_id: "root".to_string(),
+            transaction: tx.clone(),
+        }
+    }
+
+    #[test]
+    fn test_valid_transactions_are_relayed_to_other_peers() {
+        let (mut nodes, inbox) = hub_nodes(3);
+        let tx = transfer(&nodes[0], 1, &account(2).address(), 100, "root");
+        nodes[0].handle_network_event(announce(3, &tx)).unwrap();
+        let sent = drain(&inbox);
+        assert_eq!(receivers(&sent), vec![3]);
+        assert!(matches!(&sent[0].2, Message::GetTransactions { .. }));
+
+        nodes[0].handle_network_event(deliver(3, &tx)).unwrap();
+        let sent = drain(&inbox);
+        assert_eq!(receivers(&sent), vec![2]);
+        assert!(matches!(
+            &sent[0].2,
+            Message::Inventory { tx_ids, .. } if tx_ids == &vec![tx.tx_id.clone()]
+        ));
+
+        // Already seen, so neither asked for nor relayed again
+        nodes[0].handle_network_event(announce(2, &tx)).unwrap();
+        nodes[0].handle_network_event(deliver(3, &tx)).unwrap();
+        assert!(drain(&inbox).is_empty());
+    }
+
+    #[test]
+    fn test_unrequested_or_forged_transactions_are_dropped() {
+        let (mut nodes, inbox) = hub_nodes(3);
+        let tx = transfer(&nodes[0], 1, &account(2).address(), 100, "root");
+        nodes[0].handle_network_event(deliver(3, &tx)).unwrap();
+        assert!(drain(&inbox).is_empty());
+        assert!(nodes[0].mempool("root").unwrap().is_empty());
+
+        // Garbage under the real id does not keep the transaction out
+        let mut forged = tx.clone();
+        forged.amount += 1;
+        nodes[0].handle_network_event(announce(3, &tx)).unwrap();
+        nodes[0].handle_network_event(deliver(3, &forged)).unwrap();
+        drain(&inbox);
+        nodes[0].handle_network_event(announce(2, &tx)).unwrap();
+        assert_eq!(receivers(&drain(&inbox)), vec![2]);
+        nodes[0].handle_network_event(deliver(2, &tx)).unwrap();
+        assert!(nodes[0].mempool("root").unwrap().contains(&tx.tx_id));
+    }
+
+    #[test]
+    fn test_extending_blocks_are_relayed_once() {
+        let (mut nodes, inbox) = hub_nodes(3);
+        nodes[2].add_block(vec![], "root").unwrap();
+        let block = nodes[2].tip("root").unwrap().clone();
+        let event = NetworkEvent::Block { peer: 3, block };
+        nodes[0].handle_network_event(event.clone()).unwrap();
+        assert_eq!(receivers(&drain(&inbox)), vec![2]);
+
+        nodes[0].handle_network_event(event).unwrap();
+        assert!(drain(&inbox).is_empty());
+    }
+
+    #[test]
+    fn test_orphan_block_triggers_sync_with_sender() {
+        let (mut nodes, inbox) = hub_nodes(2);
+        for _ in 0..3 {
+            nodes[1].add_block(vec![], "root").unwrap();
+        }
+        let block = nodes[1].tip("root").unwrap().clone();
+        nodes[0]
+            .handle_network_event(NetworkEvent::Block { peer: 2, block })
+            .unwrap();
+        let sent = drain(&inbox);
+        assert_eq!(receivers(&sent), vec![2]);
+        assert!(matches!(sent[0].2, Message::GetBranches));
+
+        for (from, to, message) in sent {
+            nodes[to as usize - 1]
+                .handle_network_event(NetworkEvent::from_message(from, message))
+                .unwrap();
+        }
+        pump(&mut nodes, &inbox);
+        assert_eq!(
+            nodes[0].tip("root").unwrap().hash,
+            nodes[1].tip("root").unwrap().hash
+        );
+        assert!(nodes[0].is_synced());
+    }
+}
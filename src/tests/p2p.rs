@@ -0,0 +1,292 @@
+#[cfg(test)]
+mod tests {
+    use crate::block::Block;
+    use crate::blocktree::Blocktree;
+    use crate::clustering::SpectralClustering;
+    use crate::consensus::ProofOfWork;
+    use crate::error::BlocktreeError;
+    use crate::network::{Handshake, Message, Network, NetworkEvent, LOCAL_NODE, PROTOCOL_VERSION};
+    use crate::p2p::{
+        read_frame, write_frame, TcpNetwork, DEFAULT_MAX_PEERS, EVENT_CAPACITY, MAX_FRAME_BYTES,
+        MAX_HANDSHAKE_BYTES,
+    };
+    use crate::service::BlocktreeService;
+    use crate::storage::InMemoryStorage;
+    use crate::tests::support::{account, funded_config, transfer, INITIAL_BALANCE};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio::sync::mpsc;
+    use tokio::time::{sleep, timeout};
+
+    async fn node(genesis_hash: &str) -> TcpNetwork {
+        TcpNetwork::bind("127.0.0.1:0", genesis_hash.to_string(), DEFAULT_MAX_PEERS)
+            .await
+            .unwrap()
+    }
+
+    async fn wait_for_peers(network: &TcpNetwork, count: usize) {
+        timeout(Duration::from_secs(5), async {
+            while network.peers().len() != count {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("peers never connected");
+    }
+
+    async fn next_block(events: &mut mpsc::Receiver<NetworkEvent>) -> Block {
+        timeout(Duration::from_secs(5), async {
+            loop {
+                if let NetworkEvent::Block { block, .. } = events.recv().await.unwrap() {
+                    return block;
+                }
+            }
+        })
+        .await
+        .expect("block never arrived")
+    }
+
+    #[tokio::test]
+    async fn test_frames_round_trip_and_reject_oversized() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let handshake = Handshake {
+            version: PROTOCOL_VERSION,
+            genesis_hash: "abc".to_string(),
+            branches: vec!["root".to_string(), "root.1".to_string()],
+        };
+        write_frame(&mut client, &Message::Handshake(handshake.clone()))
+            .await
+            .unwrap();
+        write_frame(&mut client, &Message::Ping(7)).await.unwrap();
+        assert!(matches!(
+            read_frame(&mut server, MAX_FRAME_BYTES).await.unwrap(),
+            Message::Handshake(decoded) if decoded == handshake
+        ));
+        assert!(matches!(
+            read_frame(&mut server, MAX_FRAME_BYTES).await.unwrap(),
+            Message::Ping(7)
+        ));
+
+        let oversized = (MAX_FRAME_BYTES as u32 + 1).to_le_bytes();
+        client.write_all(&oversized).await.unwrap();
+        assert!(matches!(
+            read_frame(&mut server, MAX_FRAME_BYTES).await,
+            Err(BlocktreeError::NetworkError(_))
+        ));
+
+        // A frame within the limit may still end early
+        client.write_all(&1000u32.to_le_bytes()).await.unwrap();
+        client.write_all(&[1, 2, 3]).await.unwrap();
+        drop(client);
+        assert!(matches!(
+            read_frame(&mut server, MAX_FRAME_BYTES).await,
+            Err(BlocktreeError::NetworkError(_))
+        ));
+    }
+
+    // Reads from `stream` until the node closes it
+    async fn wait_for_close(stream: &mut TcpStream) {
+        let mut buffer = [0; 1024];
+        timeout(Duration::from_secs(5), async {
+            while stream.read(&mut buffer).await.unwrap_or(0) > 0 {}
+        })
+        .await
+        .expect("connection was never closed");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_handshake_frames_are_small() {
+        let node_a = node("genesis").await;
+        let mut stream = TcpStream::connect(node_a.local_addr()).await.unwrap();
+        let len = (MAX_HANDSHAKE_BYTES as u32 + 1).to_le_bytes();
+        stream.write_all(&len).await.unwrap();
+        wait_for_close(&mut stream).await;
+        assert!(node_a.peers().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_pending_handshakes_count_towards_peer_limit() {
+        let node_a = TcpNetwork::bind("127.0.0.1:0", "genesis".to_string(), 2)
+            .await
+            .unwrap();
+        let node_b = node("genesis").await;
+        // Two connections that never handshake fill every slot
+        let mut silent = Vec::new();
+        for _ in 0..2 {
+            silent.push(TcpStream::connect(node_a.local_addr()).await.unwrap());
+        }
+        sleep(Duration::from_millis(50)).await;
+        let mut refused = TcpStream::connect(node_a.local_addr()).await.unwrap();
+        wait_for_close(&mut refused).await;
+        assert!(node_b.connect(node_a.local_addr()).await.is_err());
+
+        // Their slots free up once they go away
+        drop(silent);
+        sleep(Duration::from_millis(50)).await;
+        node_b.connect(node_a.local_addr()).await.unwrap();
+        wait_for_peers(&node_a, 1).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_disconnection_is_reported_while_events_back_up() {
+        let mut node_a = node("genesis").await;
+        let mut events = node_a.take_events().unwrap();
+        let mut stream = TcpStream::connect(node_a.local_addr()).await.unwrap();
+        let handshake = Handshake {
+            version: PROTOCOL_VERSION,
+            genesis_hash: "genesis".to_string(),
+            branches: vec!["root".to_string()],
+        };
+        write_frame(&mut stream, &Message::Handshake(handshake))
+            .await
+            .unwrap();
+        read_frame(&mut stream, MAX_HANDSHAKE_BYTES).await.unwrap();
+        wait_for_peers(&node_a, 1).await;
+        let peer = node_a.peers()[0].id;
+
+        // More messages than the event queue holds, none of them read yet
+        for _ in 0..EVENT_CAPACITY + 2 {
+            let inventory = Message::Inventory {
+                branch_id: "root".to_string(),
+                tx_ids: Vec::new(),
+            };
+            write_frame(&mut stream, &inventory).await.unwrap();
+        }
+        sleep(Duration::from_millis(100)).await;
+        assert!(node_a.disconnect(peer));
+
+        timeout(Duration::from_secs(5), async {
+            loop {
+                if let NetworkEvent::PeerDisconnected(id) = events.recv().await.unwrap() {
+                    return id;
+                }
+            }
+        })
+        .await
+        .map(|id| assert_eq!(id, peer))
+        .expect("disconnection was never reported");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_handshake_rejects_foreign_genesis() {
+        let node_a = node("genesis-a").await;
+        let node_b = node("genesis-b").await;
+        assert!(matches!(
+            node_b.connect(node_a.local_addr()).await,
+            Err(BlocktreeError::NetworkError(_))
+        ));
+        sleep(Duration::from_millis(50)).await;
+        assert!(node_a.peers().is_empty());
+        assert!(node_b.peers().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_nodes_relay_mined_blocks_over_tcp() {
+        let config = funded_config();
+        let genesis_hash = config.genesis_block().unwrap().hash;
+        let miner = node(&genesis_hash).await;
+        let mut followers = Vec::new();
+        for _ in 0..2 {
+            let mut network = node(&genesis_hash).await;
+            let events = network.take_events().unwrap();
+            let id = network.connect(miner.local_addr()).await.unwrap();
+            followers.push((network, events, id));
+        }
+        wait_for_peers(&miner, 2).await;
+        let (follower, _, miner_id) = &followers[0];
+        assert!(follower.peers()[0].outbound);
+        assert_eq!(follower.peers()[0].branches, vec!["root".to_string()]);
+        timeout(Duration::from_secs(5), async {
+            while follower.get_latency(LOCAL_NODE, *miner_id).is_infinite() {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("latency was never measured");
+
+        let mut mining = Blocktree::from_config(config.clone(), Box::new(miner)).unwrap();
+        mining.add_block(vec![], "root").unwrap();
+        let tip = mining.tip("root").unwrap().hash.clone();
+        for (network, mut events, _) in followers {
+            let block = next_block(&mut events).await;
+            let mut replica = Blocktree::from_config(config.clone(), Box::new(network)).unwrap();
+            replica.process_block(block, "root").unwrap();
+            assert_eq!(replica.tip("root").unwrap().hash, tip);
+        }
+    }
+
+    /// Services a - b - c, where a and c only reach each other through b.
+    async fn line_of_services(
+    ) -> Vec<BlocktreeService<InMemoryStorage, ProofOfWork, SpectralClustering>> {
+        let config = funded_config();
+        let genesis_hash = config.genesis_block().unwrap().hash;
+        let mut networks = Vec::new();
+        for _ in 0..3 {
+            networks.push(node(&genesis_hash).await);
+        }
+        networks[1].connect(networks[0].local_addr()).await.unwrap();
+        networks[2].connect(networks[1].local_addr()).await.unwrap();
+        wait_for_peers(&networks[1], 2).await;
+        networks
+            .into_iter()
+            .map(|network| {
+                let blocktree = Blocktree::from_config(config.clone(), Box::new(network)).unwrap();
+                BlocktreeService::new(blocktree)
+            })
+            .collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_blocks_are_relayed_along_a_line_of_services() {
+        let services = line_of_services().await;
+
+        services[0].with_blocktree(|blocktree| {
+            let tx = transfer(blocktree, 1, &account(2).address(), 300, "root");
+            blocktree.add_block(vec![tx], "root").unwrap();
+        });
+        let tip =
+            services[0].with_blocktree(|blocktree| blocktree.tip("root").unwrap().hash.clone());
+        timeout(Duration::from_secs(5), async {
+            while services[2]
+                .with_blocktree(|blocktree| blocktree.tip("root").unwrap().hash.clone())
+                != tip
+            {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("block never reached the end of the line");
+        let balance = services[2]
+            .with_blocktree(|blocktree| blocktree.balance_of(&account(2).address(), "root"));
+        assert_eq!(balance.unwrap(), INITIAL_BALANCE + 300);
+
+        for service in services {
+            service.shutdown().await.unwrap();
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_transactions_gossip_along_a_line_of_services() {
+        let services = line_of_services().await;
+        let tx = services[2]
+            .with_blocktree(|blocktree| transfer(blocktree, 1, &account(2).address(), 300, "root"));
+        assert!(services[2]
+            .submit_transaction(tx.clone(), "root")
+            .await
+            .unwrap());
+        timeout(Duration::from_secs(5), async {
+            while !services[0]
+                .with_blocktree(|blocktree| blocktree.mempool("root").unwrap().contains(&tx.tx_id))
+            {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("transaction never reached the other end of the line");
+
+        for service in services {
+            service.shutdown().await.unwrap();
+        }
+    }
+}
@@ -0,0 +1,151 @@
+#[cfg(test)]
+mod tests {
+    use crate::block::Block;
+    use crate::blocktree::Blocktree;
+    use crate::consensus::{Consensus, ProofOfWork};
+    use crate::difficulty::U256;
+    use crate::error::BlocktreeError;
+    use crate::miner::{CancelToken, MinedBlock};
+    use crate::network::MockNetwork;
+    use crate::service::BlocktreeService;
+    use crate::storage::Storage;
+    use crate::tests::support::{account, funded_config, transfer, INITIAL_BALANCE};
+    use std::thread;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    /// Consensus whose mining never succeeds, only stops when cancelled.
+    #[derive(Clone)]
+    struct Stalled(ProofOfWork);
+
+    impl Consensus for Stalled {
+        fn mine_block(
+            &self,
+            block: Block,
+            cancel: &CancelToken,
+        ) -> Result<MinedBlock, BlocktreeError> {
+            while !cancel.is_cancelled() {
+                thread::sleep(Duration::from_millis(5));
+            }
+            Err(BlocktreeError::MiningCancelled(block.hash))
+        }
+
+        fn adjust_difficulty(&self, chain: &[Block]) -> u32 {
+            self.0.adjust_difficulty(chain)
+        }
+
+        fn pow_limit(&self) -> U256 {
+            self.0.pow_limit()
+        }
+    }
+
+    /// Consensus whose mining rounds take a while to wind down once
+    /// cancelled.
+    #[derive(Clone)]
+    struct Lingering(ProofOfWork);
+
+    impl Consensus for Lingering {
+        fn mine_block(
+            &self,
+            block: Block,
+            cancel: &CancelToken,
+        ) -> Result<MinedBlock, BlocktreeError> {
+            while !cancel.is_cancelled() {
+                thread::sleep(Duration::from_millis(5));
+            }
+            thread::sleep(Duration::from_millis(300));
+            Err(BlocktreeError::MiningCancelled(block.hash))
+        }
+
+        fn adjust_difficulty(&self, chain: &[Block]) -> u32 {
+            self.0.adjust_difficulty(chain)
+        }
+
+        fn pow_limit(&self) -> U256 {
+            self.0.pow_limit()
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_mined_blocks_include_submitted_transactions() {
+        let blocktree =
+            Blocktree::from_config(funded_config(), Box::new(MockNetwork::new())).unwrap();
+        let tx = transfer(&blocktree, 1, &account(2).address(), 250, "root");
+        let service = BlocktreeService::new(blocktree);
+        let mut blocks = service.subscribe_blocks();
+        service.start_mining("root").unwrap();
+        assert_eq!(service.mining_branches(), vec!["root".to_string()]);
+        assert!(service
+            .submit_transaction(tx.clone(), "root")
+            .await
+            .unwrap());
+
+        let included = timeout(Duration::from_secs(30), async {
+            loop {
+                let block = blocks.recv().await.unwrap();
+                if block.transactions.iter().any(|t| t.tx_id == tx.tx_id) {
+                    return block;
+                }
+            }
+        })
+        .await
+        .expect("transaction was never mined");
+        assert_eq!(included.header.branch_id, "root");
+
+        let blocktree = service.shutdown().await.unwrap();
+        assert_eq!(
+            blocktree.balance_of(&account(2).address(), "root").unwrap(),
+            INITIAL_BALANCE + 250
+        );
+        assert!(blocktree.is_branch_valid("root").unwrap());
+        assert!(blocktree.mining_stats().is_some());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_shutdown_cancels_mining_in_progress() {
+        let config = funded_config();
+        let consensus = Stalled(ProofOfWork::new(config.difficulty_bits, 1.0));
+        let blocktree = Blocktree::builder(Box::new(MockNetwork::new()))
+            .consensus(consensus)
+            .build()
+            .unwrap();
+        let service = BlocktreeService::new(blocktree);
+        assert!(matches!(
+            service.start_mining("missing"),
+            Err(BlocktreeError::BranchNotFound(_))
+        ));
+        service.start_mining("root").unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        service.stop_mining("root").await;
+        assert!(service.mining_branches().is_empty());
+        service.start_mining("root").unwrap();
+        let blocktree = timeout(Duration::from_secs(5), service.shutdown())
+            .await
+            .expect("shutdown hung on a mining round")
+            .unwrap();
+        assert_eq!(blocktree.storage.get_branch("root").unwrap().len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_shutdown_waits_for_abandoned_mining_round() {
+        let config = funded_config();
+        let consensus = Lingering(ProofOfWork::new(config.difficulty_bits, 1.0));
+        let blocktree = Blocktree::builder(Box::new(MockNetwork::new()))
+            .consensus(consensus)
+            .build()
+            .unwrap();
+        let service = BlocktreeService::new(blocktree);
+        service.start_mining("root").unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // Giving up on stopping leaves the round running, holding the blocktree
+        assert!(
+            timeout(Duration::from_millis(10), service.stop_mining("root"))
+                .await
+                .is_err()
+        );
+        let blocktree = service.shutdown().await.unwrap();
+        assert_eq!(blocktree.storage.get_branch("root").unwrap().len(), 1);
+    }
+}
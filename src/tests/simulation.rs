@@ -0,0 +1,261 @@
+#[cfg(test)]
+mod tests {
+    use crate::blocktree::Blocktree;
+    use crate::consensus::Consensus;
+    use crate::encoding;
+    use crate::error::{BlocktreeError, ValidationError};
+    use crate::miner::{CancelToken, Miner};
+    use crate::network::{Message, Network, PeerId};
+    use crate::simulation::{LinkModel, SimStats, Simulator};
+    use crate::storage::Storage;
+    use crate::tests::support::{account, funded_config, transfer};
+    use chrono::Utc;
+
+    const EARTH_MARS_MS: u64 = 20 * 60 * 1000;
+
+    /// Blocktrees for `count` new nodes of `simulator`, at `nodes[id - 1]`.
+    fn sim_nodes(simulator: &Simulator, count: usize) -> Vec<Blocktree> {
+        // Keeps difficulty at its floor however fast the test mines
+        let mut config = funded_config();
+        config.target_block_time = 0.001;
+        (0..count)
+            .map(|_| simulator.node_builder(config.clone()).build().unwrap())
+            .collect()
+    }
+
+    fn tip(node: &Blocktree) -> String {
+        node.tip("root").unwrap().hash.clone()
+    }
+
+    /// Deliveries of pings over lossy, jittery links, as (time, node, event).
+    fn ping_trace(seed: u64) -> Vec<(u64, PeerId, String)> {
+        let simulator = Simulator::new(seed);
+        let networks: Vec<_> = (0..3).map(|_| simulator.add_node()).collect();
+        simulator.link_all(LinkModel {
+            latency_ms: 100,
+            jitter_ms: 50,
+            bandwidth: None,
+            loss: 0.2,
+        });
+        for nonce in 0..30 {
+            for network in &networks {
+                for peer in network.peer_ids() {
+                    network.send_to(peer, Message::Ping(nonce)).unwrap();
+                }
+            }
+        }
+        let mut trace = Vec::new();
+        while let Some((node, event)) = simulator.next_event(u64::MAX) {
+            trace.push((simulator.now(), node, format!("{:?}", event)));
+        }
+        let stats = simulator.stats();
+        assert_eq!(stats.sent, 180);
+        assert!(stats.lost > 0);
+        assert_eq!(stats.delivered + stats.lost, stats.sent);
+        trace
+    }
+
+    #[test]
+    fn test_same_seed_replays_same_deliveries() {
+        let trace = ping_trace(7);
+        assert_eq!(ping_trace(7), trace);
+        assert_ne!(ping_trace(8), trace);
+        assert!(trace.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+    }
+
+    /// Every block of every node's branches after a seeded mining scenario
+    /// with a race, as (node, branch, hash, timestamp), and what happened to
+    /// messages.
+    fn mining_trace(seed: u64) -> (Vec<(usize, String, String, i64)>, SimStats) {
+        let simulator = Simulator::new(seed);
+        // Distinct miners, so racing nodes mine different blocks
+        let mut nodes: Vec<Blocktree> = (1..=3)
+            .map(|id| {
+                let mut config = funded_config();
+                config.target_block_time = 0.001;
+                config.miner_address = account(id).address();
+                simulator.node_builder(config).build().unwrap()
+            })
+            .collect();
+        simulator.link_all(LinkModel {
+            latency_ms: 200,
+            jitter_ms: 400,
+            bandwidth: Some(10_000),
+            loss: 0.0,
+        });
+        for round in 0..6 {
+            nodes[round % 3].add_block(vec![], "root").unwrap();
+            if round == 3 {
+                nodes[2].add_block(vec![], "root").unwrap();
+            }
+            simulator
+                .run_until(60_000 * (round as u64 + 1), &mut nodes)
+                .unwrap();
+        }
+        simulator.run(&mut nodes).unwrap();
+        // The race left each node a losing block on the side
+        for node in &nodes {
+            assert!(node.side_block_count("root").unwrap().0 > 0);
+        }
+        let mut trace = Vec::new();
+        for (node, blocktree) in nodes.iter().enumerate() {
+            let mut branch_ids = blocktree.get_branches();
+            branch_ids.sort();
+            for branch_id in branch_ids {
+                for block in blocktree.storage.get_branch(&branch_id).unwrap() {
+                    let hash = block.hash.clone();
+                    trace.push((node, branch_id.clone(), hash, block.header.timestamp));
+                }
+            }
+        }
+        (trace, simulator.stats())
+    }
+
+    #[test]
+    fn test_same_seed_replays_mined_blocks() {
+        let (trace, stats) = mining_trace(11);
+        assert_eq!(mining_trace(11), (trace.clone(), stats));
+        assert!(stats.delivered > 0);
+
+        // Blocks are stamped with the virtual time they were mined at
+        let timestamps: Vec<i64> = trace
+            .iter()
+            .filter(|(node, branch_id, _, _)| *node == 0 && branch_id == "root")
+            .map(|(_, _, _, timestamp)| *timestamp)
+            .collect();
+        assert_eq!(timestamps[..3], [0, 0, 60_000]);
+        assert!(timestamps.iter().all(|&timestamp| timestamp <= 360_000));
+    }
+
+    #[test]
+    fn test_future_drift_is_checked_against_virtual_time() {
+        let simulator = Simulator::new(4);
+        let mut nodes = sim_nodes(&simulator, 2);
+        simulator.link(1, 2, LinkModel::with_latency(100));
+        let (block, _) = nodes[0].prepare_block(vec![], "root").unwrap();
+        assert_eq!(block.header.timestamp, 0);
+
+        // Wall-clock time is decades ahead of the simulation
+        let mut early = block.clone();
+        early.header.timestamp = Utc::now().timestamp_millis();
+        let early = Miner::new(1)
+            .mine(early, &CancelToken::new())
+            .unwrap()
+            .block;
+        assert!(matches!(
+            nodes[1].process_block(early, "root"),
+            Err(BlocktreeError::Validation(
+                ValidationError::TimestampOutOfRange { .. }
+            ))
+        ));
+        let mined = nodes[0]
+            .consensus()
+            .mine_block(block, &CancelToken::new())
+            .unwrap();
+        assert!(nodes[1].process_block(mined.block, "root").is_ok());
+    }
+
+    #[test]
+    fn test_bandwidth_queues_messages_behind_each_other() {
+        let simulator = Simulator::new(0);
+        let sender = simulator.add_node();
+        simulator.add_node();
+        simulator.link(
+            1,
+            2,
+            LinkModel {
+                bandwidth: Some(100),
+                ..LinkModel::with_latency(1000)
+            },
+        );
+        let size = encoding::to_bytes(&Message::Ping(0)).len() as u64;
+        let transmit = (size * 1000).div_ceil(100);
+        sender.send_to(2, Message::Ping(0)).unwrap();
+        sender.send_to(2, Message::Ping(1)).unwrap();
+
+        assert!(simulator.next_event(1000 + transmit - 1).is_none());
+        assert!(simulator.next_event(u64::MAX).is_some());
+        assert_eq!(simulator.now(), 1000 + transmit);
+        assert!(simulator.next_event(u64::MAX).is_some());
+        assert_eq!(simulator.now(), 1000 + 2 * transmit);
+        assert_eq!(sender.get_latency(1, 2), 1000.0);
+        assert!(sender.get_latency(1, 3).is_infinite());
+    }
+
+    #[test]
+    fn test_block_reaches_mars_after_light_delay() {
+        let simulator = Simulator::new(1);
+        let mut nodes = sim_nodes(&simulator, 3);
+        // Two nodes on Earth, one on Mars reached only through the second
+        simulator.link(1, 2, LinkModel::with_latency(50));
+        simulator.link(2, 3, LinkModel::with_latency(EARTH_MARS_MS));
+
+        nodes[0].add_block(vec![], "root").unwrap();
+        simulator.run_until(1000, &mut nodes).unwrap();
+        assert_eq!(tip(&nodes[1]), tip(&nodes[0]));
+        assert_ne!(tip(&nodes[2]), tip(&nodes[0]));
+
+        simulator.run(&mut nodes).unwrap();
+        assert_eq!(tip(&nodes[2]), tip(&nodes[0]));
+        assert!(simulator.now() >= 50 + EARTH_MARS_MS);
+    }
+
+    #[test]
+    fn test_transaction_reaches_mars_despite_request_timeout() {
+        let simulator = Simulator::new(5);
+        let mut nodes = sim_nodes(&simulator, 2);
+        simulator.link(1, 2, LinkModel::with_latency(EARTH_MARS_MS));
+        let tx = transfer(&nodes[0], 1, &account(2).address(), 100, "root");
+        assert!(nodes[0].submit_transaction(tx.clone(), "root").unwrap());
+
+        // Announcement, request and reply each take the light delay
+        simulator.run(&mut nodes).unwrap();
+        assert!(nodes[1].mempool("root").unwrap().contains(&tx.tx_id));
+        assert!(simulator.now() >= 3 * EARTH_MARS_MS);
+    }
+
+    #[test]
+    fn test_partitioned_sides_converge_after_heal() {
+        let simulator = Simulator::new(2);
+        let mut nodes = sim_nodes(&simulator, 4);
+        simulator.link_all(LinkModel::with_latency(100));
+        simulator.partition_at(0, vec![vec![1, 2], vec![3, 4]]);
+        simulator.heal_at(60_000);
+        simulator.run_until(0, &mut nodes).unwrap();
+
+        for _ in 0..3 {
+            nodes[0].add_block(vec![], "root").unwrap();
+        }
+        nodes[2].add_block(vec![], "root").unwrap();
+        simulator.run_until(59_999, &mut nodes).unwrap();
+        assert_eq!(tip(&nodes[1]), tip(&nodes[0]));
+        assert_eq!(tip(&nodes[3]), tip(&nodes[2]));
+        assert_ne!(tip(&nodes[0]), tip(&nodes[2]));
+        assert_eq!(simulator.stats().partitioned, 0);
+
+        // The heavier side wins once the peers reconnect and sync
+        simulator.run(&mut nodes).unwrap();
+        for node in &nodes {
+            assert_eq!(tip(node), tip(&nodes[0]));
+            assert!(node.is_synced());
+        }
+    }
+
+    #[test]
+    fn test_messages_in_flight_are_dropped_by_partition() {
+        let simulator = Simulator::new(3);
+        let mut nodes = sim_nodes(&simulator, 2);
+        simulator.link(1, 2, LinkModel::with_latency(EARTH_MARS_MS));
+        simulator.partition_at(1000, vec![vec![1], vec![2]]);
+        simulator.heal_at(2 * EARTH_MARS_MS);
+        nodes[0].add_block(vec![], "root").unwrap();
+        simulator.run_until(EARTH_MARS_MS + 1, &mut nodes).unwrap();
+        assert_eq!(simulator.stats().partitioned, 1);
+        assert_ne!(tip(&nodes[1]), tip(&nodes[0]));
+
+        // Synced once the link is back
+        simulator.run(&mut nodes).unwrap();
+        assert_eq!(tip(&nodes[1]), tip(&nodes[0]));
+        assert!(simulator.now() >= 3 * EARTH_MARS_MS);
+    }
+}
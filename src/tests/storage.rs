@@ -0,0 +1,341 @@
+#[cfg(test)]
+mod tests {
+    use crate::block::Block;
+    use crate::difficulty;
+    use crate::encoding::{Encode, ENCODING_VERSION};
+    use crate::miner::{CancelToken, Miner};
+    use crate::storage::{FileStorage, InMemoryStorage, MigrationProgress, Storage};
+    use crate::transaction::Transaction;
+    use serde_json::json;
+    use sha3::{Digest, Sha3_256};
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+
+    fn sample_block(index: u64, branch_id: &str) -> Block {
+        let tx = Transaction::new(
+            format!("sender{}", index),
+            format!("receiver{}", index),
+            100,
+        )
+        .unwrap();
+        Block::new(
+            index,
+            vec![tx],
+            format!("prev{}", index),
+            branch_id.to_string(),
+        )
+        .unwrap()
+    }
+
+    fn segment_path(dir: &std::path::Path, branch_id: &str) -> std::path::PathBuf {
+        dir.join(format!("{}.seg", hex::encode(branch_id)))
+    }
+
+    /// Writes a store holding `payloads` per branch in the segment record layout.
+    fn write_raw_store(dir: &std::path::Path, branches: &[(&str, Vec<Vec<u8>>)]) {
+        let mut index = serde_json::Map::new();
+        for (branch_id, payloads) in branches {
+            let path = segment_path(dir, branch_id);
+            let mut data = Vec::new();
+            for payload in payloads {
+                data.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+                data.extend_from_slice(&Sha3_256::digest(payload)[..4]);
+                data.extend_from_slice(payload);
+            }
+            fs::write(&path, data).unwrap();
+            let segment = path.file_name().unwrap().to_str().unwrap().to_string();
+            index.insert(branch_id.to_string(), json!(segment));
+        }
+        fs::write(dir.join("index.json"), serde_json::to_vec(&index).unwrap()).unwrap();
+    }
+
+    /// JSON block in the pre-header layout, with no `bits`.
+    fn legacy_block(
+        index: u64,
+        previous_hash: &str,
+        hash: &str,
+        txs: serde_json::Value,
+    ) -> Vec<u8> {
+        serde_json::to_vec(&json!({
+            "index": index,
+            "timestamp": 1_700_000_000_000i64 + index as i64,
+            "transactions": txs,
+            "previous_hash": previous_hash,
+            "branch_id": "root",
+            "nonce": 7,
+            "merkle_root": "0",
+            "hash": hash,
+        }))
+        .unwrap()
+    }
+
+    /// JSON block in the layout with the header nested, as written after
+    /// headers were split from bodies and before the canonical encoding.
+    fn nested_block(index: u64, previous_hash: &str, hash: &str) -> Vec<u8> {
+        serde_json::to_vec(&json!({
+            "header": {
+                "version": 1,
+                "index": index,
+                "timestamp": 1_700_000_000_000i64 + index as i64,
+                "previous_hash": previous_hash,
+                "branch_id": "root",
+                "merkle_root": "0",
+                "bits": difficulty::DEFAULT_POW_LIMIT_BITS,
+                "nonce": 7,
+            },
+            "transactions": [],
+            "hash": hash,
+        }))
+        .unwrap()
+    }
+
+    /// Block in version 1 of the canonical encoding, before transactions
+    /// carried their branch.
+    fn v1_block(index: u64, previous_hash: &str, hash: &str) -> Vec<u8> {
+        let mut block = sample_block(index, "root");
+        block.header.previous_hash = previous_hash.to_string();
+        let mut out = vec![1];
+        block.header.encode(&mut out);
+        (block.transactions.len() as u32).encode(&mut out);
+        for tx in &block.transactions {
+            tx.sender.encode(&mut out);
+            tx.receiver.encode(&mut out);
+            tx.amount.encode(&mut out);
+            tx.fee.encode(&mut out);
+            tx.nonce.encode(&mut out);
+            tx.timestamp.encode(&mut out);
+            tx.public_key.encode(&mut out);
+            tx.signature.encode(&mut out);
+            0u8.encode(&mut out);
+            tx.tx_id.encode(&mut out);
+        }
+        hash.encode(&mut out);
+        out
+    }
+
+    /// A store whose root branch switches from the flat to the nested legacy
+    /// layout, split after its proven tip, with one unproven block.
+    fn write_legacy_store(dir: &std::path::Path) {
+        let genesis = format!("00{}", "11".repeat(31));
+        let first = format!("00{}", "22".repeat(31));
+        let unproven = "ff".repeat(32);
+        let tip = format!("00{}", "33".repeat(31));
+        let tx = json!([{
+            "sender": "alice",
+            "receiver": "bob",
+            "amount": 5,
+            "timestamp": 1,
+            "tx_id": "legacy",
+        }]);
+        let tip_block = nested_block(3, &unproven, &tip);
+        write_raw_store(
+            dir,
+            &[
+                (
+                    "root",
+                    vec![
+                        legacy_block(0, "0", &genesis, json!([])),
+                        legacy_block(1, &genesis, &first, tx),
+                        nested_block(2, &first, &unproven),
+                        tip_block.clone(),
+                    ],
+                ),
+                ("root.1", vec![tip_block]),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_file_storage_matches_in_memory() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut file_storage = FileStorage::open(dir.path()).unwrap();
+        let mut memory_storage = InMemoryStorage::new();
+        for (index, branch_id) in [(0, "root"), (1, "root"), (1, "root.1"), (2, "root.2")] {
+            let block = sample_block(index, branch_id);
+            file_storage.save_block(block.clone(), branch_id).unwrap();
+            memory_storage.save_block(block, branch_id).unwrap();
+        }
+        let mut file_keys = file_storage.get_branch_keys();
+        let mut memory_keys = memory_storage.get_branch_keys();
+        file_keys.sort();
+        memory_keys.sort();
+        assert_eq!(file_keys, memory_keys);
+        for branch_id in memory_keys {
+            let file_hashes: Vec<_> = file_storage
+                .get_branch(&branch_id)
+                .unwrap()
+                .iter()
+                .map(|b| b.hash.clone())
+                .collect();
+            let memory_hashes: Vec<_> = memory_storage
+                .get_branch(&branch_id)
+                .unwrap()
+                .iter()
+                .map(|b| b.hash.clone())
+                .collect();
+            assert_eq!(file_hashes, memory_hashes);
+        }
+        assert!(file_storage.get_branch("missing").is_none());
+    }
+
+    #[test]
+    fn test_file_storage_survives_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = sample_block(0, "root");
+        let second = sample_block(1, "root.1");
+        {
+            let mut storage = FileStorage::open(dir.path()).unwrap();
+            storage.save_block(first.clone(), "root").unwrap();
+            storage.save_block(second.clone(), "root.1").unwrap();
+        }
+        let mut storage = FileStorage::open(dir.path()).unwrap();
+        assert_eq!(storage.get_branch("root").unwrap()[0].hash, first.hash);
+        assert_eq!(storage.get_branch("root.1").unwrap()[0].hash, second.hash);
+        storage.save_block(sample_block(1, "root"), "root").unwrap();
+        let reopened = FileStorage::open(dir.path()).unwrap();
+        assert_eq!(reopened.get_branch("root").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_file_storage_truncates_torn_write() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let mut storage = FileStorage::open(dir.path()).unwrap();
+            storage.save_block(sample_block(0, "root"), "root").unwrap();
+            storage.save_block(sample_block(1, "root"), "root").unwrap();
+        }
+        let path = segment_path(dir.path(), "root");
+        let intact_len = fs::metadata(&path).unwrap().len();
+        // Simulate a crash halfway through appending a third record
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[200, 0, 0, 0, 1, 2, 3, 4, b'{']).unwrap();
+        drop(file);
+
+        let mut storage = FileStorage::open(dir.path()).unwrap();
+        assert_eq!(storage.get_branch("root").unwrap().len(), 2);
+        assert_eq!(fs::metadata(&path).unwrap().len(), intact_len);
+        storage.save_block(sample_block(2, "root"), "root").unwrap();
+        let reopened = FileStorage::open(dir.path()).unwrap();
+        assert_eq!(reopened.get_branch("root").unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_file_storage_refuses_corrupt_record() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let mut storage = FileStorage::open(dir.path()).unwrap();
+            for index in 0..3 {
+                storage
+                    .save_block(sample_block(index, "root"), "root")
+                    .unwrap();
+            }
+        }
+        // Flip a byte inside the first record, leaving valid ones after it
+        let path = segment_path(dir.path(), "root");
+        let mut data = fs::read(&path).unwrap();
+        data[10] ^= 0xff;
+        fs::write(&path, &data).unwrap();
+
+        assert!(FileStorage::open(dir.path()).is_err());
+        assert_eq!(fs::read(&path).unwrap(), data);
+    }
+
+    #[test]
+    fn test_legacy_segments_are_rehashed_and_migrated() {
+        let dir = tempfile::tempdir().unwrap();
+        write_legacy_store(dir.path());
+        let segment = fs::read(segment_path(dir.path(), "root")).unwrap();
+        assert!(FileStorage::open(dir.path()).is_err());
+        assert_eq!(fs::read(segment_path(dir.path(), "root")).unwrap(), segment);
+
+        let mut reports: Vec<MigrationProgress> = Vec::new();
+        let storage = FileStorage::migrate(
+            dir.path(),
+            &Miner::default(),
+            &CancelToken::new(),
+            |progress| reports.push(progress.clone()),
+        )
+        .unwrap();
+        let last = reports.last().unwrap();
+        assert_eq!(reports.len(), 5);
+        assert_eq!((last.blocks_done, last.blocks_total), (5, 5));
+        // The split copy of the tip takes the migrated tip, not a new search
+        assert_eq!(last.remined, 3);
+
+        let root = storage.get_branch("root").unwrap().clone();
+        assert_eq!(root.len(), 4);
+        assert_eq!(root[1].transactions[0].receiver, "bob");
+        for (i, block) in root.iter().enumerate() {
+            assert_eq!(block.hash, block.calculate_hash().unwrap());
+            if i > 0 {
+                assert_eq!(block.header.previous_hash, root[i - 1].hash);
+            }
+        }
+        // Proof of work is kept where the legacy hash had it
+        for i in [0, 1, 3] {
+            assert!(difficulty::hash_meets_target(&root[i].hash, root[i].header.bits).unwrap());
+        }
+        assert_eq!(storage.get_branch("root.1").unwrap()[0].hash, root[3].hash);
+
+        // Segments were rewritten canonically, so reopening changes nothing
+        for branch_id in ["root", "root.1"] {
+            let data = fs::read(segment_path(dir.path(), branch_id)).unwrap();
+            assert_eq!(data[8], ENCODING_VERSION);
+        }
+        let reopened = FileStorage::open(dir.path()).unwrap();
+        let hashes = |chain: &Vec<Block>| chain.iter().map(|b| b.hash.clone()).collect::<Vec<_>>();
+        assert_eq!(hashes(reopened.get_branch("root").unwrap()), hashes(&root));
+    }
+
+    #[test]
+    fn test_version_1_segments_are_migrated() {
+        let dir = tempfile::tempdir().unwrap();
+        let genesis = "ee".repeat(32);
+        let first = "ff".repeat(32);
+        write_raw_store(
+            dir.path(),
+            &[(
+                "root",
+                vec![v1_block(0, "0", &genesis), v1_block(1, &genesis, &first)],
+            )],
+        );
+        assert!(FileStorage::open(dir.path()).is_err());
+
+        let storage =
+            FileStorage::migrate(dir.path(), &Miner::new(1), &CancelToken::new(), |_| {}).unwrap();
+        let root = storage.get_branch("root").unwrap().clone();
+        assert_eq!(root.len(), 2);
+        assert_eq!(root[1].transactions[0].receiver, "receiver1");
+        assert_eq!(root[1].transactions[0].branch_id, "");
+        assert_eq!(root[1].header.previous_hash, root[0].hash);
+        for block in &root {
+            assert_eq!(block.hash, block.calculate_hash().unwrap());
+        }
+        let data = fs::read(segment_path(dir.path(), "root")).unwrap();
+        assert_eq!(data[8], ENCODING_VERSION);
+        let reopened = FileStorage::open(dir.path()).unwrap();
+        assert_eq!(reopened.get_branch("root").unwrap()[1].hash, root[1].hash);
+    }
+
+    #[test]
+    fn test_cancelled_migration_leaves_store_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        write_legacy_store(dir.path());
+        let segment = fs::read(segment_path(dir.path(), "root")).unwrap();
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        assert!(FileStorage::migrate(dir.path(), &Miner::new(1), &cancel, |_| {}).is_err());
+        assert_eq!(fs::read(segment_path(dir.path(), "root")).unwrap(), segment);
+        assert!(FileStorage::open(dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_file_storage_refuses_undecodable_record() {
+        let dir = tempfile::tempdir().unwrap();
+        write_raw_store(dir.path(), &[("root", vec![b"not a block".to_vec()])]);
+        let path = segment_path(dir.path(), "root");
+        let len = fs::metadata(&path).unwrap().len();
+        assert!(FileStorage::open(dir.path()).is_err());
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::block::Block;
+use crate::blocktree::{Blocktree, BlocktreeBuilder};
+use crate::clock::{Clock, SystemClock};
+use crate::clustering::Clustering;
+use crate::config::{Allocation, BlocktreeConfig};
+use crate::consensus::Consensus;
+use crate::error::BlocktreeError;
+use crate::network::{Message, Network, PeerId};
+use crate::storage::Storage;
+use crate::transaction::Transaction;
+use crate::wallet::Keypair;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{mpsc, Arc};
+
+/// Genesis balance of every test account.
+pub const INITIAL_BALANCE: u64 = 1_000_000;
+
+/// Test account `n`, derived from a fixed seed.
+pub fn account(n: u8) -> Keypair {
+    Keypair::from_seed(&[n; 32])
+}
+
+/// Default config with accounts 0 through 10 funded at genesis.
+pub fn funded_config() -> BlocktreeConfig {
+    let mut config = BlocktreeConfig::default();
+    config.genesis.allocations = (0..=10)
+        .map(|n| Allocation {
+            address: account(n).address(),
+            amount: INITIAL_BALANCE,
+        })
+        .collect();
+    config
+}
+
+/// Signed transfer from account `sender` with its next nonce on `branch_id`.
+pub fn transfer<S: Storage, C: Consensus, K: Clustering>(
+    blocktree: &Blocktree<S, C, K>,
+    sender: u8,
+    receiver: &str,
+    amount: u64,
+    branch_id: &str,
+) -> Transaction {
+    let keypair = account(sender);
+    let nonce = blocktree.nonce_of(&keypair.address(), branch_id).unwrap();
+    Transaction::new_signed(&keypair, receiver.to_string(), amount, nonce, branch_id).unwrap()
+}
+
+/// Message in flight between two hub nodes: (from, to, message).
+pub type Envelope = (PeerId, PeerId, Message);
+
+/// Delivers every message into one queue, drained by the test.
+pub struct Hub {
+    id: PeerId,
+    peers: Vec<PeerId>,
+    outbox: mpsc::Sender<Envelope>,
+}
+
+impl Network for Hub {
+    fn broadcast_block(&self, _block: Block) -> Result<(), BlocktreeError> {
+        Ok(())
+    }
+
+    fn broadcast_transaction(&self, branch_id: &str, tx_id: &str) -> Result<(), BlocktreeError> {
+        for &peer in &self.peers {
+            let message = Message::Inventory {
+                branch_id: branch_id.to_string(),
+                tx_ids: vec![tx_id.to_string()],
+            };
+            self.send_to(peer, message)?;
+        }
+        Ok(())
+    }
+
+    fn get_latency(&self, _node1: u32, _node2: u32) -> f64 {
+        0.0
+    }
+
+    fn send_to(&self, peer: PeerId, message: Message) -> Result<(), BlocktreeError> {
+        self.outbox
+            .send((self.id, peer, message))
+            .map_err(|e| BlocktreeError::NetworkError(e.to_string()))
+    }
+
+    fn peer_ids(&self) -> Vec<PeerId> {
+        self.peers.clone()
+    }
+}
+
+/// Clock moved by hand, shared by its clones.
+#[derive(Clone)]
+pub struct ManualClock(Arc<AtomicI64>);
+
+impl ManualClock {
+    pub fn new(now_ms: i64) -> Self {
+        ManualClock(Arc::new(AtomicI64::new(now_ms)))
+    }
+
+    pub fn advance(&self, ms: i64) {
+        self.0.fetch_add(ms, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_ms(&self) -> i64 {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Fully connected nodes 1 to `count`, at `nodes[id - 1]`.
+pub fn hub_nodes(count: PeerId) -> (Vec<Blocktree>, mpsc::Receiver<Envelope>) {
+    hub_nodes_with_clock(count, SystemClock)
+}
+
+/// Like `hub_nodes`, every node reading the time from `clock`.
+pub fn hub_nodes_with_clock(
+    count: PeerId,
+    clock: impl Clock + Clone + 'static,
+) -> (Vec<Blocktree>, mpsc::Receiver<Envelope>) {
+    // Keeps difficulty at its floor however fast the test mines
+    let mut config = funded_config();
+    config.target_block_time = 0.001;
+    let (outbox, inbox) = mpsc::channel();
+    let nodes = (1..=count)
+        .map(|id| {
+            let network = Hub {
+                id,
+                peers: (1..=count).filter(|&peer| peer != id).collect(),
+                outbox: outbox.clone(),
+            };
+            BlocktreeBuilder::from_config(config.clone(), Box::new(network))
+                .clock(Box::new(clock.clone()))
+                .build()
+                .unwrap()
+        })
+        .collect();
+    (nodes, inbox)
+}
@@ -0,0 +1,292 @@
+#[cfg(test)]
+mod tests {
+    use crate::block::{Block, BlockHeader};
+    use crate::blocktree::Blocktree;
+    use crate::clock::{Clock, SystemClock};
+    use crate::consensus::{Consensus, ProofOfWork};
+    use crate::crossbranch;
+    use crate::difficulty;
+    use crate::gossip::REQUEST_TIMEOUT_MS;
+    use crate::miner::CancelToken;
+    use crate::network::{BranchSummary, Message, NetworkEvent, PeerId};
+    use crate::storage::Storage;
+    use crate::sync::{self, SyncState, MAX_BLOCKS_PER_REQUEST, MAX_HEADERS, MAX_PENDING_HEADERS};
+    use crate::tests::support::{account, hub_nodes, hub_nodes_with_clock, Envelope, ManualClock};
+    use std::collections::HashMap;
+    use std::sync::mpsc;
+
+    /// Delivers messages until none are left, counting `Blocks` replies per
+    /// (sender, receiver). Replies from `corrupt` have their coinbase altered.
+    fn pump(
+        nodes: &mut [Blocktree],
+        inbox: &mpsc::Receiver<Envelope>,
+        corrupt: Option<PeerId>,
+    ) -> HashMap<(PeerId, PeerId), usize> {
+        let mut replies = HashMap::new();
+        while let Ok((from, to, mut message)) = inbox.try_recv() {
+            if let Message::Blocks { blocks, .. } = &mut message {
+                *replies.entry((from, to)).or_default() += 1;
+                if Some(from) == corrupt {
+                    for block in blocks {
+                        block.transactions[0].amount += 1;
+                    }
+                }
+            }
+            nodes[to as usize - 1]
+                .handle_network_event(NetworkEvent::from_message(from, message))
+                .unwrap();
+        }
+        replies
+    }
+
+    /// Root long enough to need several body requests, split into children
+    /// with a cross-branch transfer from `root.2` claimed on `root.1`.
+    fn build_tree(blocktree: &mut Blocktree) {
+        for _ in 0..3 * MAX_BLOCKS_PER_REQUEST {
+            blocktree.add_block(vec![], "root").unwrap();
+        }
+        let alice = account(1);
+        let nonce = blocktree.nonce_of(&alice.address(), "root.2").unwrap();
+        let lock = crossbranch::lock(
+            &alice,
+            "root.1",
+            account(2).address(),
+            500,
+            0,
+            nonce,
+            "root.2",
+        )
+        .unwrap();
+        blocktree.add_block(vec![lock.clone()], "root.2").unwrap();
+        for _ in 0..blocktree.config().claim_depth {
+            blocktree.add_block(vec![], "root.2").unwrap();
+        }
+        let claim = blocktree.claim_transfer("root.2", &lock.tx_id).unwrap();
+        blocktree.add_block(vec![], "root.1").unwrap();
+        blocktree.add_block(vec![claim], "root.1").unwrap();
+    }
+
+    fn assert_same_tree(expected: &Blocktree, actual: &Blocktree) {
+        let mut branches = expected.get_branches();
+        branches.sort();
+        let mut synced = actual.get_branches();
+        synced.sort();
+        assert_eq!(synced, branches);
+        for branch_id in &branches {
+            assert_eq!(
+                actual.tip(branch_id).unwrap().hash,
+                expected.tip(branch_id).unwrap().hash
+            );
+            assert!(actual.is_branch_valid(branch_id).unwrap());
+        }
+        assert!(actual.is_synced());
+    }
+
+    #[test]
+    fn test_fresh_node_syncs_tree_from_several_peers() {
+        let (mut nodes, inbox) = hub_nodes(3);
+        build_tree(&mut nodes[0]);
+
+        nodes[1].start_sync();
+        pump(&mut nodes, &inbox, None);
+        assert_same_tree(&nodes[0], &nodes[1]);
+        assert_eq!(
+            nodes[1]
+                .balance_of(&account(2).address(), "root.1")
+                .unwrap(),
+            nodes[0]
+                .balance_of(&account(2).address(), "root.1")
+                .unwrap()
+        );
+
+        // Bodies are fetched from both peers holding the tree
+        nodes[2].start_sync();
+        let replies = pump(&mut nodes, &inbox, None);
+        assert_same_tree(&nodes[0], &nodes[2]);
+        assert!(replies[&(1, 3)] > 0);
+        assert!(replies[&(2, 3)] > 0);
+    }
+
+    /// Syncs node 3 from nodes 1 and 2 while node 1 never sends the replies
+    /// `silent` matches, returning whether node 3 got stuck before timing out.
+    fn sync_past_silent_peer(silent: fn(&Message) -> bool) -> bool {
+        let clock = ManualClock::new(SystemClock.now_ms());
+        let (mut nodes, inbox) = hub_nodes_with_clock(3, clock.clone());
+        for _ in 0..3 {
+            nodes[0].add_block(vec![], "root").unwrap();
+        }
+        nodes[1].start_sync();
+        pump(&mut nodes, &inbox, None);
+
+        nodes[2].start_sync();
+        let deliver = |nodes: &mut [Blocktree]| {
+            while let Ok((from, to, message)) = inbox.try_recv() {
+                if from == 1 && to == 3 && silent(&message) {
+                    continue;
+                }
+                let event = NetworkEvent::from_message(from, message);
+                nodes[to as usize - 1].handle_network_event(event).unwrap();
+            }
+        };
+        deliver(&mut nodes);
+        let stuck = !nodes[2].is_synced();
+
+        // Node 1 is passed over once its request times out
+        clock.advance(REQUEST_TIMEOUT_MS);
+        nodes[2].expire_requests().unwrap();
+        deliver(&mut nodes);
+        assert_eq!(tip_hash(&nodes[2]), tip_hash(&nodes[0]));
+        assert!(nodes[2].is_synced());
+        stuck
+    }
+
+    fn tip_hash(node: &Blocktree) -> String {
+        node.tip("root").unwrap().hash.clone()
+    }
+
+    #[test]
+    fn test_silent_peer_is_passed_over() {
+        assert!(sync_past_silent_peer(|message| matches!(
+            message,
+            Message::Headers { .. }
+        )));
+        assert!(sync_past_silent_peer(|message| matches!(
+            message,
+            Message::Blocks { .. }
+        )));
+    }
+
+    #[test]
+    fn test_sync_refetches_bodies_not_matching_headers() {
+        let (mut nodes, inbox) = hub_nodes(3);
+        build_tree(&mut nodes[0]);
+        nodes[1].start_sync();
+        pump(&mut nodes, &inbox, None);
+
+        nodes[2].start_sync();
+        pump(&mut nodes, &inbox, Some(2));
+        assert_same_tree(&nodes[0], &nodes[2]);
+    }
+
+    #[test]
+    fn test_locator_thins_out_and_finds_fork_point() {
+        let mut blocktree = hub_nodes(1).0.remove(0);
+        for _ in 0..20 {
+            blocktree.add_block(vec![], "root").unwrap();
+        }
+        let chain = blocktree.storage.get_branch("root").unwrap().clone();
+        let locator = sync::locator(&chain, Some("pending"));
+        assert_eq!(locator[0], "pending");
+        assert_eq!(locator[1], chain[20].hash);
+        assert_eq!(locator.last().unwrap(), &chain[0].hash);
+        assert!(locator.len() < chain.len());
+
+        let headers = sync::headers_after(&chain, &[chain[15].hash.clone()]);
+        assert_eq!(headers.len(), 5);
+        assert_eq!(headers[0], chain[16].header);
+        assert_eq!(
+            sync::headers_after(&chain, &["unknown".to_string()]).len(),
+            chain.len()
+        );
+    }
+
+    /// `count` mined headers of `root` at `bits`, following `parent`.
+    fn mined_headers(parent: &Block, bits: u32, count: usize) -> Vec<BlockHeader> {
+        let mut headers: Vec<BlockHeader> = Vec::new();
+        let mut previous = (parent.hash.clone(), parent.header.index);
+        for _ in 0..count {
+            let mut block = Block::new(
+                previous.1 + 1,
+                vec![],
+                previous.0.clone(),
+                "root".to_string(),
+            )
+            .unwrap();
+            block.header.bits = bits;
+            let mined = ProofOfWork::new(bits, 1.0)
+                .mine_block(block, &CancelToken::new())
+                .unwrap()
+                .block;
+            previous = (mined.hash.clone(), mined.header.index);
+            headers.push(mined.header);
+        }
+        headers
+    }
+
+    #[test]
+    fn test_headers_must_follow_difficulty_retarget() {
+        let (mut nodes, inbox) = hub_nodes(2);
+        let genesis = nodes[0].tip("root").unwrap().clone();
+        let expected = nodes[0]
+            .consensus()
+            .adjust_difficulty(std::slice::from_ref(&genesis));
+        // Harder than expected, so the work is real but the bits are wrong
+        let harder = difficulty::target_to_compact(
+            difficulty::compact_to_target(expected).unwrap().div_u64(2),
+        );
+        for (bits, accepted) in [(harder, false), (expected, true)] {
+            let headers = mined_headers(&genesis, bits, 3);
+            let tip_hash = headers.last().unwrap().calculate_hash().unwrap();
+            let summary = BranchSummary {
+                branch_id: "root".to_string(),
+                length: 4,
+                tip_hash,
+            };
+            let branches = NetworkEvent::from_message(2, Message::Branches(vec![summary]));
+            nodes[0].handle_network_event(branches).unwrap();
+            let sent: Vec<Envelope> = inbox.try_iter().collect();
+            assert!(matches!(sent[..], [(1, 2, Message::GetHeaders { .. })]));
+
+            let reply = Message::Headers {
+                branch_id: "root".to_string(),
+                headers,
+            };
+            nodes[0]
+                .handle_network_event(NetworkEvent::from_message(2, reply))
+                .unwrap();
+            let sent: Vec<Envelope> = inbox.try_iter().collect();
+            assert_eq!(
+                sent.iter()
+                    .any(|(_, _, m)| matches!(m, Message::GetBlocks { .. })),
+                accepted
+            );
+        }
+    }
+
+    #[test]
+    fn test_pending_headers_are_capped() {
+        let genesis = Block::new(0, vec![], "0".to_string(), "root".to_string()).unwrap();
+        let headers = mined_headers(&genesis, 0x207fffff, MAX_PENDING_HEADERS + 1);
+        let pow_limit = difficulty::compact_to_target(0x207fffff).unwrap();
+        let tip_hash = headers.last().unwrap().calculate_hash().unwrap();
+        let mut state = SyncState::new();
+        let summary = BranchSummary {
+            branch_id: "root".to_string(),
+            length: headers.len() as u64 + 1,
+            tip_hash,
+        };
+        state.on_branches(1, vec![summary]);
+        let known = |hash: &str| hash == genesis.hash;
+        let mut added = 0;
+        for batch in headers.chunks(MAX_HEADERS) {
+            assert_eq!(state.headers_wanted("root", known), Some(1));
+            state.request_headers("root", 1, 0, REQUEST_TIMEOUT_MS);
+            added += state
+                .on_headers(
+                    1,
+                    "root",
+                    batch.to_vec(),
+                    pow_limit,
+                    known,
+                    |_, _, _| Ok(()),
+                )
+                .unwrap();
+            if added == MAX_PENDING_HEADERS {
+                break;
+            }
+        }
+        assert_eq!(added, MAX_PENDING_HEADERS);
+        // Full, so nothing more is asked for until bodies make room
+        assert_eq!(state.headers_wanted("root", known), None);
+    }
+}
@@ -0,0 +1,98 @@
+#[cfg(test)]
+mod tests {
+    use crate::blocktree::Blocktree;
+    use crate::error::{BlocktreeError, ValidationError};
+    use crate::network::MockNetwork;
+    use crate::tests::support::{account, funded_config};
+    use crate::transaction::Transaction;
+    use crate::wallet::{self, Keypair};
+
+    #[test]
+    fn test_signed_transaction_is_valid() {
+        let alice = Keypair::from_seed(&[1; 32]);
+        let tx = Transaction::new_signed(&alice, "bob".to_string(), 10, 0, "root").unwrap();
+        assert_eq!(tx.sender, alice.address());
+        assert_eq!(tx.sender.len(), 40);
+        assert!(tx.verify_signature());
+        assert!(tx.is_valid());
+        assert_eq!(tx.tx_id, tx.calculate_hash().unwrap());
+    }
+
+    #[test]
+    fn test_unsigned_and_tampered_transactions_are_invalid() {
+        let alice = Keypair::from_seed(&[1; 32]);
+        let unsigned = Transaction::new(alice.address(), "bob".to_string(), 10).unwrap();
+        assert!(!unsigned.is_valid());
+
+        let mut tampered =
+            Transaction::new_signed(&alice, "bob".to_string(), 10, 0, "root").unwrap();
+        tampered.amount = 1_000;
+        assert!(!tampered.verify_signature());
+
+        let mut redirected =
+            Transaction::new_signed(&alice, "bob".to_string(), 10, 0, "root").unwrap();
+        redirected.receiver = "mallory".to_string();
+        assert!(!redirected.verify_signature());
+    }
+
+    #[test]
+    fn test_cannot_spend_from_another_address() {
+        let alice = Keypair::from_seed(&[1; 32]);
+        let mallory = Keypair::from_seed(&[2; 32]);
+        let mut forged = Transaction::new(alice.address(), "mallory".to_string(), 10).unwrap();
+        assert!(forged.sign(&mallory).is_err());
+        // Mallory's key with Alice's address does not verify either
+        forged.public_key = mallory.public_key_hex();
+        forged.signature = mallory.sign(&forged.signing_payload().unwrap());
+        assert!(!forged.verify_signature());
+    }
+
+    #[test]
+    fn test_fee_is_signed_and_hashed() {
+        let alice = Keypair::from_seed(&[1; 32]);
+        let mut tx =
+            Transaction::new_signed_with_fee(&alice, "bob".to_string(), 10, 3, 0, "root").unwrap();
+        assert!(tx.is_valid());
+        assert_eq!(tx.total_cost(), Some(13));
+        let original_id = tx.tx_id.clone();
+        tx.fee = 0;
+        assert!(!tx.verify_signature());
+        assert_ne!(tx.calculate_hash().unwrap(), original_id);
+    }
+
+    #[test]
+    fn test_tx_id_covers_signature() {
+        let alice = Keypair::from_seed(&[1; 32]);
+        let mut tx = Transaction::new_signed(&alice, "bob".to_string(), 10, 0, "root").unwrap();
+        let original_id = tx.tx_id.clone();
+        tx.signature = alice.sign(b"something else");
+        assert_ne!(tx.calculate_hash().unwrap(), original_id);
+    }
+
+    #[test]
+    fn test_signature_helpers_reject_malformed_input() {
+        assert!(wallet::verify_signature("zz", b"message", "00").is_err());
+        let alice = Keypair::from_seed(&[1; 32]);
+        let signature = alice.sign(b"message");
+        assert!(wallet::verify_signature(&alice.public_key_hex(), b"message", &signature).unwrap());
+        assert!(!wallet::verify_signature(&alice.public_key_hex(), b"other", &signature).unwrap());
+    }
+
+    #[test]
+    fn test_block_with_forged_transaction_is_rejected() {
+        let mut blocktree =
+            Blocktree::from_config(funded_config(), Box::new(MockNetwork::new())).unwrap();
+        let mut tx =
+            Transaction::new_signed(&account(1), "bob".to_string(), 10, 0, "root").unwrap();
+        tx.amount = 1_000;
+        tx.tx_id = tx.calculate_hash().unwrap();
+        match blocktree.add_block(vec![tx], "root") {
+            Err(BlocktreeError::Validation(ValidationError::BadTransaction {
+                position, ..
+            })) => {
+                assert_eq!(position, 1)
+            }
+            other => panic!("expected bad transaction, got {:?}", other),
+        }
+    }
+}
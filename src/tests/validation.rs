@@ -0,0 +1,314 @@
+#[cfg(test)]
+mod tests {
+    use crate::block::Block;
+    use crate::blocktree::Blocktree;
+    use crate::clock::{Clock, SystemClock};
+    use crate::coin::Coin;
+    use crate::consensus::{Consensus, ProofOfWork};
+    use crate::difficulty::{self, DEFAULT_POW_LIMIT_BITS};
+    use crate::error::{BlocktreeError, ValidationError};
+    use crate::miner::CancelToken;
+    use crate::network::MockNetwork;
+    use crate::storage::{InMemoryStorage, Storage};
+    use crate::tests::support::{account, funded_config, transfer};
+    use crate::transaction::Transaction;
+    use crate::tree::{BlocktreeCore, Tree};
+    use crate::validation;
+
+    fn blocktree_with_blocks(count: u64) -> Blocktree {
+        let mut blocktree =
+            Blocktree::from_config(funded_config(), Box::new(MockNetwork::new())).unwrap();
+        for i in 1..=count {
+            let tx = transfer(&blocktree, i as u8, &format!("receiver{}", i), 100, "root");
+            blocktree.add_block(vec![tx], "root").unwrap();
+        }
+        blocktree
+    }
+
+    /// Builds the next root block with `tamper` applied, then mines it so only
+    /// the tampered rule is violated.
+    fn next_block(blocktree: &Blocktree, tamper: impl FnOnce(&mut Block)) -> Block {
+        let chain = blocktree.storage.get_branch("root").unwrap();
+        let last = chain.last().unwrap();
+        let index = last.header.index + 1;
+        let coinbase = Transaction::coinbase(
+            "miner".to_string(),
+            Coin::new().reward_at(index),
+            index,
+            "root",
+            last.header.timestamp,
+        )
+        .unwrap();
+        let tx = transfer(blocktree, 0, "receiver", 100, "root");
+        let mut block = Block::new(
+            index,
+            vec![coinbase, tx],
+            last.hash.clone(),
+            "root".to_string(),
+        )
+        .unwrap();
+        block.header.bits = last.header.bits;
+        tamper(&mut block);
+        ProofOfWork::new(block.header.bits, 5.0)
+            .mine_block(block, &CancelToken::new())
+            .unwrap()
+            .block
+    }
+
+    fn validation_error(result: Result<(), BlocktreeError>) -> ValidationError {
+        match result {
+            Err(BlocktreeError::Validation(err)) => err,
+            other => panic!("expected validation error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_valid_block_is_accepted() {
+        let blocktree = blocktree_with_blocks(2);
+        let block = next_block(&blocktree, |_| {});
+        assert!(blocktree.validate_block(&block, "root").is_ok());
+        assert!(blocktree.is_branch_valid("root").unwrap());
+    }
+
+    #[test]
+    fn test_rejects_bad_link_and_index() {
+        let blocktree = blocktree_with_blocks(2);
+        let block = next_block(&blocktree, |b| b.header.previous_hash = "00".repeat(32));
+        assert!(matches!(
+            validation_error(blocktree.validate_block(&block, "root")),
+            ValidationError::BadLink { .. }
+        ));
+        let block = next_block(&blocktree, |b| b.header.index += 1);
+        assert!(matches!(
+            validation_error(blocktree.validate_block(&block, "root")),
+            ValidationError::BadIndex { expected: 3, .. }
+        ));
+    }
+
+    #[test]
+    fn test_rejects_unknown_version() {
+        let blocktree = blocktree_with_blocks(1);
+        let block = next_block(&blocktree, |b| b.header.version = 2);
+        assert!(matches!(
+            validation_error(blocktree.validate_block(&block, "root")),
+            ValidationError::UnsupportedVersion { version: 2, .. }
+        ));
+    }
+
+    #[test]
+    fn test_rejects_timestamp_out_of_range() {
+        let blocktree = blocktree_with_blocks(1);
+        let block = next_block(&blocktree, |b| b.header.timestamp = 0);
+        assert!(matches!(
+            validation_error(blocktree.validate_block(&block, "root")),
+            ValidationError::TimestampOutOfRange { timestamp: 0, .. }
+        ));
+        let block = next_block(&blocktree, |b| b.header.timestamp += 3_600_000);
+        assert!(matches!(
+            validation_error(blocktree.validate_block(&block, "root")),
+            ValidationError::TimestampOutOfRange { .. }
+        ));
+    }
+
+    #[test]
+    fn test_rejects_bad_merkle_root_and_transactions() {
+        let blocktree = blocktree_with_blocks(1);
+        let block = next_block(&blocktree, |b| b.header.merkle_root = "ab".repeat(32));
+        assert!(matches!(
+            validation_error(blocktree.validate_block(&block, "root")),
+            ValidationError::BadMerkleRoot { .. }
+        ));
+        // Changing a transaction without its id breaks the id, not the root
+        let block = next_block(&blocktree, |b| b.transactions[1].amount = 1);
+        assert!(matches!(
+            validation_error(blocktree.validate_block(&block, "root")),
+            ValidationError::BadTransaction { position: 1, .. }
+        ));
+        let block = next_block(&blocktree, |b| {
+            b.transactions[1].amount = 0;
+            b.transactions[1].tx_id = b.transactions[1].calculate_hash().unwrap();
+            b.header.merkle_root = Block::calculate_merkle_root(&b.transactions).unwrap();
+        });
+        assert!(matches!(
+            validation_error(blocktree.validate_block(&block, "root")),
+            ValidationError::BadTransaction { position: 1, .. }
+        ));
+    }
+
+    #[test]
+    fn test_rejects_missing_or_bad_coinbase() {
+        let blocktree = blocktree_with_blocks(1);
+        let block = next_block(&blocktree, |b| {
+            b.transactions.remove(0);
+            b.header.merkle_root = Block::calculate_merkle_root(&b.transactions).unwrap();
+        });
+        assert!(matches!(
+            validation_error(blocktree.validate_block(&block, "root")),
+            ValidationError::MissingCoinbase { index: 2, .. }
+        ));
+        let block = next_block(&blocktree, |b| {
+            b.transactions[0] =
+                Transaction::coinbase("miner".to_string(), 1_000, 2, "root", b.header.timestamp)
+                    .unwrap();
+            b.header.merkle_root = Block::calculate_merkle_root(&b.transactions).unwrap();
+        });
+        assert!(matches!(
+            validation_error(blocktree.validate_block(&block, "root")),
+            ValidationError::BadCoinbase {
+                found_reward: 1_000,
+                ..
+            }
+        ));
+        // A second coinbase would mint coins outside the schedule
+        let block = next_block(&blocktree, |b| {
+            let extra = b.transactions[0].clone();
+            b.transactions.push(extra);
+            b.header.merkle_root = Block::calculate_merkle_root(&b.transactions).unwrap();
+        });
+        assert!(matches!(
+            validation_error(blocktree.validate_block(&block, "root")),
+            ValidationError::BadTransaction { position: 2, .. }
+        ));
+    }
+
+    #[test]
+    fn test_coinbase_must_claim_fees_exactly() {
+        let blocktree = blocktree_with_blocks(1);
+        let reward = Coin::new().reward_at(2);
+        let with_fee = |coinbase_amount: u64| {
+            next_block(&blocktree, |b| {
+                let sender = account(0);
+                b.transactions[1] =
+                    Transaction::new_signed_with_fee(&sender, "bob".to_string(), 100, 5, 0, "root")
+                        .unwrap();
+                b.transactions[0] = Transaction::coinbase(
+                    "miner".to_string(),
+                    coinbase_amount,
+                    2,
+                    "root",
+                    b.header.timestamp,
+                )
+                .unwrap();
+                b.header.merkle_root = Block::calculate_merkle_root(&b.transactions).unwrap();
+            })
+        };
+        assert!(blocktree
+            .validate_block(&with_fee(reward + 5), "root")
+            .is_ok());
+        for amount in [reward, reward + 6] {
+            assert!(matches!(
+                validation_error(blocktree.validate_block(&with_fee(amount), "root")),
+                ValidationError::BadCoinbase {
+                    expected_reward,
+                    ..
+                } if expected_reward == reward + 5
+            ));
+        }
+    }
+
+    #[test]
+    fn test_rejects_wrong_difficulty_and_insufficient_work() {
+        let blocktree = blocktree_with_blocks(1);
+        let block = next_block(&blocktree, |b| b.header.bits = 0x1f7fffff);
+        assert!(matches!(
+            validation_error(blocktree.validate_block(&block, "root")),
+            ValidationError::BadDifficulty {
+                found_bits: 0x1f7fffff,
+                ..
+            }
+        ));
+        let mut block = next_block(&blocktree, |_| {});
+        while difficulty::hash_meets_target(&block.hash, block.header.bits).unwrap() {
+            block.header.nonce += 1;
+            block.update_hash().unwrap();
+        }
+        assert!(matches!(
+            validation_error(blocktree.validate_block(&block, "root")),
+            ValidationError::InsufficientWork { .. }
+        ));
+    }
+
+    #[test]
+    fn test_rejects_target_easier_than_pow_limit() {
+        let blocktree = blocktree_with_blocks(1);
+        let block = next_block(&blocktree, |b| b.header.bits = 0x207fffff);
+        assert!(matches!(
+            validation_error(blocktree.validate_block(&block, "root")),
+            ValidationError::TargetTooEasy {
+                bits: 0x207fffff,
+                limit_bits: DEFAULT_POW_LIMIT_BITS,
+                ..
+            }
+        ));
+        let pow_limit = blocktree.consensus().pow_limit();
+        assert!(matches!(
+            validation_error(
+                validation::validate_header(&block.header, "root", pow_limit).map(|_| ())
+            ),
+            ValidationError::TargetTooEasy { .. }
+        ));
+    }
+
+    #[test]
+    fn test_tampered_branch_is_invalid() {
+        let blocktree = blocktree_with_blocks(3);
+        assert!(blocktree.is_branch_valid("root").unwrap());
+        let mut tampered = InMemoryStorage::new();
+        for (i, block) in blocktree
+            .storage
+            .get_branch("root")
+            .unwrap()
+            .iter()
+            .enumerate()
+        {
+            let mut block = block.clone();
+            if i == 2 {
+                block.transactions[1].amount += 1;
+            }
+            tampered.save_block(block, "root").unwrap();
+        }
+        let pow = ProofOfWork::new(DEFAULT_POW_LIMIT_BITS, 0.2);
+        let coin = Coin::new();
+        let tree = BlocktreeCore::new();
+        let now = SystemClock.now_ms();
+        assert!(!tree
+            .is_branch_valid("root", &pow, &coin, &tampered, now)
+            .unwrap());
+        let report = tree
+            .validate_branch("root", &pow, &coin, &tampered, now)
+            .unwrap();
+        assert_eq!(report.blocks_checked, 3);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].block_index(), 2);
+        // The header hash covers the body only through the Merkle root
+        assert!(matches!(
+            report.errors[0],
+            ValidationError::BadTransaction { position: 1, .. }
+        ));
+    }
+
+    #[test]
+    fn test_report_collects_every_failure() {
+        let blocktree = blocktree_with_blocks(4);
+        let report = blocktree.validate_branch("root").unwrap();
+        assert!(report.is_valid());
+        assert_eq!(report.blocks_checked, 4);
+
+        let mut tampered = InMemoryStorage::new();
+        for block in blocktree.storage.get_branch("root").unwrap() {
+            let mut block = block.clone();
+            if block.header.index == 1 || block.header.index == 3 {
+                block.header.nonce += 1;
+            }
+            tampered.save_block(block, "root").unwrap();
+        }
+        let pow = ProofOfWork::new(DEFAULT_POW_LIMIT_BITS, 0.2);
+        let report = BlocktreeCore::new()
+            .validate_branch("root", &pow, &Coin::new(), &tampered, SystemClock.now_ms())
+            .unwrap();
+        let indices: Vec<u64> = report.errors.iter().map(|e| e.block_index()).collect();
+        assert_eq!(indices, vec![1, 3]);
+        let hash = &tampered.get_branch("root").unwrap()[3].hash;
+        assert_eq!(report.errors[1].block_hash(), hash);
+    }
+}
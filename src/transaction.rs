@@ -1,40 +1,202 @@
+use crate::crossbranch::{CrossBranch, CLAIM_SENDER};
+use crate::encoding;
 use crate::error::BlocktreeError;
+use crate::wallet::{self, Keypair};
 use serde::{Deserialize, Serialize};
 use sha3::{Digest, Sha3_256};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Transaction {
-    pub sender: String, // Simplified: public key or address
+    pub sender: String, // Address derived from `public_key`
     pub receiver: String,
     pub amount: u64, // BKT units
+    #[serde(default)]
+    pub fee: u64, // BKT units paid to the miner on top of `amount`
+    #[serde(default)]
+    pub nonce: u64, // Count of earlier transactions from the sender, for replay protection
+    #[serde(default)]
+    pub branch_id: String, // Branch the transaction is bound to; empty in genesis
     pub timestamp: i64,
-    pub tx_id: String, // Hash of transaction
+    #[serde(default)]
+    pub public_key: String, // Hex-encoded Ed25519 key; empty for unsigned system transactions
+    #[serde(default)]
+    pub signature: String, // Hex-encoded signature over the signing payload
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cross_branch: Option<CrossBranch>, // Lock or claim of a cross-branch transfer
+    pub tx_id: String, // Hash of transaction, including the signature
 }
 
+/// Sender of coinbase transactions, which mint the block reward.
+pub const COINBASE_SENDER: &str = "coinbase";
+
 impl Transaction {
+    /// Creates an unsigned transaction, as used for genesis allocations.
     pub fn new(sender: String, receiver: String, amount: u64) -> Result<Self, BlocktreeError> {
         let timestamp = chrono::Utc::now().timestamp_millis();
+        Self::with_timestamp(sender, receiver, amount, timestamp)
+    }
+
+    pub fn with_timestamp(
+        sender: String,
+        receiver: String,
+        amount: u64,
+        timestamp: i64,
+    ) -> Result<Self, BlocktreeError> {
         let mut tx = Transaction {
             sender,
             receiver,
             amount,
+            fee: 0,
+            nonce: 0,
+            branch_id: String::new(),
             timestamp,
+            public_key: String::new(),
+            signature: String::new(),
+            cross_branch: None,
             tx_id: String::new(),
         };
         tx.tx_id = tx.calculate_hash()?;
         Ok(tx)
     }
 
+    /// Creates the coinbase transaction of the block at `height` of
+    /// `branch_id`, paying `reward` to the miner and stamped with the block's
+    /// `timestamp`. Its nonce is the height, keeping tx ids unique.
+    pub fn coinbase(
+        miner: String,
+        reward: u64,
+        height: u64,
+        branch_id: &str,
+        timestamp: i64,
+    ) -> Result<Self, BlocktreeError> {
+        let mut tx = Self::with_timestamp(COINBASE_SENDER.to_string(), miner, reward, timestamp)?;
+        tx.nonce = height;
+        tx.branch_id = branch_id.to_string();
+        tx.tx_id = tx.calculate_hash()?;
+        Ok(tx)
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        self.sender == COINBASE_SENDER && self.public_key.is_empty()
+    }
+
+    /// True for the unsigned claim side of a cross-branch transfer.
+    pub fn is_claim(&self) -> bool {
+        matches!(self.cross_branch, Some(CrossBranch::Claim(_)))
+    }
+
+    /// Creates a transaction of `branch_id` from `keypair`'s address, signed
+    /// by it. `nonce` must equal the number of transactions the sender
+    /// already made on that branch.
+    pub fn new_signed(
+        keypair: &Keypair,
+        receiver: String,
+        amount: u64,
+        nonce: u64,
+        branch_id: &str,
+    ) -> Result<Self, BlocktreeError> {
+        Self::new_signed_with_fee(keypair, receiver, amount, 0, nonce, branch_id)
+    }
+
+    /// Like `new_signed`, additionally paying `fee` to the miner.
+    pub fn new_signed_with_fee(
+        keypair: &Keypair,
+        receiver: String,
+        amount: u64,
+        fee: u64,
+        nonce: u64,
+        branch_id: &str,
+    ) -> Result<Self, BlocktreeError> {
+        let mut tx = Self::new(keypair.address(), receiver, amount)?;
+        tx.fee = fee;
+        tx.nonce = nonce;
+        tx.branch_id = branch_id.to_string();
+        tx.sign(keypair)?;
+        Ok(tx)
+    }
+
+    /// Signs the transaction and recomputes `tx_id` to cover the signature.
+    pub fn sign(&mut self, keypair: &Keypair) -> Result<(), BlocktreeError> {
+        if keypair.address() != self.sender {
+            return Err(BlocktreeError::TransactionError(format!(
+                "Keypair address {} is not the sender {}",
+                keypair.address(),
+                self.sender
+            )));
+        }
+        self.public_key = keypair.public_key_hex();
+        self.signature = keypair.sign(&self.signing_payload()?);
+        self.tx_id = self.calculate_hash()?;
+        Ok(())
+    }
+
+    /// Canonical encoding of every field except the signature and tx id.
+    pub fn signing_payload(&self) -> Result<Vec<u8>, BlocktreeError> {
+        Ok(encoding::signing_bytes(self))
+    }
+
+    /// Hashes the canonical encoding of the transaction without its `tx_id`.
     pub fn calculate_hash(&self) -> Result<String, BlocktreeError> {
-        let tx_json = serde_json::to_string(&self)
-            .map_err(|e| BlocktreeError::SerializationError(e.to_string()))?;
         let mut hasher = Sha3_256::new();
-        hasher.update(tx_json);
+        hasher.update(encoding::unhashed_transaction_bytes(self));
         Ok(format!("{:x}", hasher.finalize()))
     }
 
+    /// Encoded size in bytes, which fee rates are measured against.
+    pub fn size(&self) -> Result<usize, BlocktreeError> {
+        Ok(encoding::to_bytes(self).len())
+    }
+
+    /// Fee per 1000 serialized bytes.
+    pub fn fee_rate(&self) -> Result<u64, BlocktreeError> {
+        Ok(self.fee.saturating_mul(1000) / self.size()?.max(1) as u64)
+    }
+
+    /// Amount plus fee, or `None` if the sum overflows.
+    pub fn total_cost(&self) -> Option<u64> {
+        self.amount.checked_add(self.fee)
+    }
+
+    /// True if the public key matches the sender address and signed the payload.
+    pub fn verify_signature(&self) -> bool {
+        let public_key: [u8; 32] = match wallet::decode_fixed(&self.public_key, "public key") {
+            Ok(public_key) => public_key,
+            Err(_) => return false,
+        };
+        if wallet::address_from_public_key(&public_key) != self.sender {
+            return false;
+        }
+        match self.signing_payload() {
+            Ok(payload) => wallet::verify_signature(&self.public_key, &payload, &self.signature)
+                .unwrap_or(false),
+            Err(_) => false,
+        }
+    }
+
+    /// Checks fields and signature. Coinbase and claim transactions are
+    /// unsigned; block validation checks them against the reward schedule and
+    /// the source branch instead.
     pub fn is_valid(&self) -> bool {
-        // Placeholder: Add real validation (e.g., signature, balance)
-        !self.sender.is_empty() && !self.receiver.is_empty() && self.amount > 0
+        if self.is_coinbase() {
+            return !self.receiver.is_empty() && self.fee == 0 && self.cross_branch.is_none();
+        }
+        match &self.cross_branch {
+            Some(CrossBranch::Claim(claim)) => {
+                return self.sender == CLAIM_SENDER
+                    && self.public_key.is_empty()
+                    && self.fee == 0
+                    && self.nonce == 0
+                    && self.receiver == claim.lock.receiver
+                    && self.amount == claim.lock.amount;
+            }
+            Some(CrossBranch::Lock { destination_branch }) if destination_branch.is_empty() => {
+                return false;
+            }
+            _ => {}
+        }
+        !self.sender.is_empty()
+            && !self.receiver.is_empty()
+            && self.amount > 0
+            && self.verify_signature()
     }
 }
@@ -1,7 +1,11 @@
 use crate::block::Block;
 use crate::clustering::Clustering;
+use crate::coin::Coin;
+use crate::consensus::Consensus;
+use crate::crossbranch::DEFAULT_CLAIM_DEPTH;
 use crate::error::BlocktreeError;
 use crate::storage::Storage;
+use crate::validation::{self, ValidationReport};
 
 pub trait Tree {
     fn add_block<S: Storage>(
@@ -16,21 +20,45 @@ pub trait Tree {
         clustering: &C,
         storage: &mut S,
     ) -> Result<(), BlocktreeError>;
-    fn is_branch_valid<S: Storage>(
+    fn is_branch_valid<C: Consensus, S: Storage>(
         &self,
         branch_id: &str,
+        consensus: &C,
+        coin: &Coin,
         storage: &S,
+        now: i64,
     ) -> Result<bool, BlocktreeError>;
+    fn validate_branch<C: Consensus, S: Storage>(
+        &self,
+        branch_id: &str,
+        consensus: &C,
+        coin: &Coin,
+        storage: &S,
+        now: i64,
+    ) -> Result<ValidationReport, BlocktreeError>;
     fn get_split_interval(&self) -> usize;
 }
 
 pub struct BlocktreeCore {
     pub split_interval: usize,
+    pub claim_depth: usize,
 }
 
 impl BlocktreeCore {
     pub fn new() -> Self {
-        BlocktreeCore { split_interval: 5 }
+        Self::with_split_interval(5)
+    }
+
+    pub fn with_split_interval(split_interval: usize) -> Self {
+        BlocktreeCore {
+            split_interval,
+            claim_depth: DEFAULT_CLAIM_DEPTH,
+        }
+    }
+
+    pub fn with_claim_depth(mut self, claim_depth: usize) -> Self {
+        self.claim_depth = claim_depth;
+        self
     }
 }
 
@@ -78,26 +106,39 @@ impl Tree for BlocktreeCore {
         Ok(())
     }
 
-    fn is_branch_valid<S: Storage>(
+    fn is_branch_valid<C: Consensus, S: Storage>(
         &self,
         branch_id: &str,
+        consensus: &C,
+        coin: &Coin,
         storage: &S,
+        now: i64,
     ) -> Result<bool, BlocktreeError> {
+        Ok(self
+            .validate_branch(branch_id, consensus, coin, storage, now)?
+            .is_valid())
+    }
+
+    fn validate_branch<C: Consensus, S: Storage>(
+        &self,
+        branch_id: &str,
+        consensus: &C,
+        coin: &Coin,
+        storage: &S,
+        now: i64,
+    ) -> Result<ValidationReport, BlocktreeError> {
         let chain = storage
             .get_branch(branch_id)
             .ok_or_else(|| BlocktreeError::BranchNotFound(branch_id.to_string()))?;
-        for i in 1..chain.len() {
-            let current = &chain[i];
-            let previous = &chain[i - 1];
-            let current_hash = current.calculate_hash()?;
-            if current.hash != current_hash {
-                return Ok(false);
-            }
-            if current.previous_hash != previous.hash {
-                return Ok(false);
-            }
-        }
-        Ok(true)
+        validation::validate_chain(
+            branch_id,
+            chain,
+            consensus,
+            coin,
+            storage,
+            self.claim_depth,
+            now,
+        )
     }
 
     fn get_split_interval(&self) -> usize {
@@ -0,0 +1,301 @@
+use crate::block::{Block, BlockHeader, BLOCK_VERSION};
+use crate::coin::Coin;
+use crate::consensus::Consensus;
+use crate::crossbranch;
+use crate::difficulty::{self, U256};
+use crate::error::{BlocktreeError, ValidationError};
+use crate::ledger::Ledger;
+use crate::storage::Storage;
+
+/// How far ahead of local time a block timestamp may be, in milliseconds.
+pub const MAX_FUTURE_DRIFT_MS: i64 = 60_000;
+
+/// Outcome of validating every block of a branch.
+#[derive(Debug, Clone)]
+pub struct ValidationReport {
+    pub branch_id: String,
+    pub blocks_checked: usize,
+    pub errors: Vec<ValidationError>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// Checks what a block of `branch_id` must satisfy regardless of its parent:
+/// a known version, the branch, its hash, and work meeting its own target,
+/// which is no easier than `pow_limit`.
+pub fn validate_standalone(
+    block: &Block,
+    branch_id: &str,
+    pow_limit: U256,
+) -> Result<(), BlocktreeError> {
+    let index = block.header.index;
+    let hash = block.hash.clone();
+    check_version_and_branch(&block.header, &hash, branch_id)?;
+    check_target(&block.header, &hash, pow_limit)?;
+    let computed = block.calculate_hash()?;
+    if block.hash != computed {
+        return Err(ValidationError::BadHash {
+            index,
+            hash,
+            computed,
+        }
+        .into());
+    }
+    if !difficulty::hash_meets_target(&block.hash, block.header.bits)? {
+        return Err(ValidationError::InsufficientWork {
+            index,
+            hash,
+            bits: block.header.bits,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Checks a header of `branch_id` received without its body, returning its
+/// hash: the version, the branch, and work meeting its own target, which is
+/// no easier than `pow_limit`.
+pub fn validate_header(
+    header: &BlockHeader,
+    branch_id: &str,
+    pow_limit: U256,
+) -> Result<String, BlocktreeError> {
+    let hash = header.calculate_hash()?;
+    check_version_and_branch(header, &hash, branch_id)?;
+    check_target(header, &hash, pow_limit)?;
+    if !difficulty::hash_meets_target(&hash, header.bits)? {
+        return Err(ValidationError::InsufficientWork {
+            index: header.index,
+            hash,
+            bits: header.bits,
+        }
+        .into());
+    }
+    Ok(hash)
+}
+
+/// Checks what `header` must satisfy given the blocks before it, `chain`, at
+/// local time `now`: a timestamp no earlier than its parent's and at most
+/// `MAX_FUTURE_DRIFT_MS` ahead, and the bits retargeting over `chain` sets.
+pub fn validate_header_context<C: Consensus>(
+    header: &BlockHeader,
+    hash: &str,
+    chain: &[Block],
+    consensus: &C,
+    now: i64,
+) -> Result<(), BlocktreeError> {
+    let previous = chain
+        .last()
+        .ok_or_else(|| BlocktreeError::BranchNotFound("Empty branch".to_string()))?;
+    let index = header.index;
+    let max_timestamp = now + MAX_FUTURE_DRIFT_MS;
+    if header.timestamp < previous.header.timestamp || header.timestamp > max_timestamp {
+        return Err(ValidationError::TimestampOutOfRange {
+            index,
+            hash: hash.to_string(),
+            timestamp: header.timestamp,
+            min: previous.header.timestamp,
+            max: max_timestamp,
+        }
+        .into());
+    }
+    let expected_bits = consensus.adjust_difficulty(chain);
+    if header.bits != expected_bits {
+        return Err(ValidationError::BadDifficulty {
+            index,
+            hash: hash.to_string(),
+            expected_bits,
+            found_bits: header.bits,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Checks that the target `header` declares is no easier than `limit`.
+pub fn check_target(header: &BlockHeader, hash: &str, limit: U256) -> Result<(), BlocktreeError> {
+    if difficulty::compact_to_target(header.bits)? > limit {
+        return Err(ValidationError::TargetTooEasy {
+            index: header.index,
+            hash: hash.to_string(),
+            bits: header.bits,
+            limit_bits: difficulty::target_to_compact(limit),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+fn check_version_and_branch(
+    header: &BlockHeader,
+    hash: &str,
+    branch_id: &str,
+) -> Result<(), BlocktreeError> {
+    if header.version != BLOCK_VERSION {
+        return Err(ValidationError::UnsupportedVersion {
+            index: header.index,
+            hash: hash.to_string(),
+            version: header.version,
+        }
+        .into());
+    }
+    if header.branch_id != branch_id {
+        return Err(ValidationError::WrongBranch {
+            index: header.index,
+            hash: hash.to_string(),
+            expected: branch_id.to_string(),
+            found: header.branch_id.clone(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Validates `block` as the next block of branch `branch_id`, whose blocks so
+/// far are `chain`, at local time `now`. Used both for locally mined blocks
+/// and blocks from peers.
+pub fn validate_block<C: Consensus>(
+    block: &Block,
+    branch_id: &str,
+    chain: &[Block],
+    consensus: &C,
+    coin: &Coin,
+    now: i64,
+) -> Result<(), BlocktreeError> {
+    let previous = chain
+        .last()
+        .ok_or_else(|| BlocktreeError::BranchNotFound("Empty branch".to_string()))?;
+    validate_standalone(block, branch_id, consensus.pow_limit())?;
+    let index = block.header.index;
+    let hash = block.hash.clone();
+    if block.header.index != previous.header.index + 1 {
+        return Err(ValidationError::BadIndex {
+            index,
+            hash,
+            expected: previous.header.index + 1,
+        }
+        .into());
+    }
+    if block.header.previous_hash != previous.hash {
+        return Err(ValidationError::BadLink {
+            index,
+            hash,
+            expected_previous: previous.hash.clone(),
+            found_previous: block.header.previous_hash.clone(),
+        }
+        .into());
+    }
+    validate_header_context(&block.header, &hash, chain, consensus, now)?;
+    let merkle_root = Block::calculate_merkle_root(&block.transactions)?;
+    if block.header.merkle_root != merkle_root {
+        return Err(ValidationError::BadMerkleRoot {
+            index,
+            hash,
+            expected: merkle_root,
+            found: block.header.merkle_root.clone(),
+        }
+        .into());
+    }
+    let coinbase = match block.transactions.first() {
+        Some(tx) if tx.is_coinbase() => tx,
+        _ => return Err(ValidationError::MissingCoinbase { index, hash }.into()),
+    };
+    let mut fees: u64 = 0;
+    for (position, tx) in block.transactions.iter().enumerate() {
+        // Only the first transaction may mint, and it must carry the height
+        let misplaced_coinbase =
+            tx.is_coinbase() && (position > 0 || tx.nonce != block.header.index);
+        let well_formed = tx.tx_id == tx.calculate_hash()?
+            && tx.is_valid()
+            && tx.branch_id == branch_id
+            && !misplaced_coinbase;
+        fees = match fees.checked_add(tx.fee) {
+            Some(total) if well_formed => total,
+            _ => {
+                return Err(ValidationError::BadTransaction {
+                    index,
+                    hash,
+                    position,
+                    tx_id: tx.tx_id.clone(),
+                }
+                .into())
+            }
+        };
+    }
+    // The coinbase may claim the scheduled reward plus every fee, no more
+    let expected_reward = coin.reward_at(block.header.index).saturating_add(fees);
+    if coinbase.amount != expected_reward {
+        return Err(ValidationError::BadCoinbase {
+            index,
+            hash,
+            expected_reward,
+            found_reward: coinbase.amount,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Checks every cross-branch claim in `block`, a block of `branch_id`,
+/// against the source branches in `storage`, requiring each lock to be
+/// buried under `claim_depth` blocks.
+pub fn validate_claims<S: Storage>(
+    block: &Block,
+    branch_id: &str,
+    storage: &S,
+    claim_depth: usize,
+) -> Result<(), BlocktreeError> {
+    for (position, tx) in block.transactions.iter().enumerate() {
+        if !tx.is_claim() {
+            continue;
+        }
+        if let Err(reason) = crossbranch::verify_claim(tx, branch_id, storage, claim_depth) {
+            return Err(ValidationError::BadClaim {
+                index: block.header.index,
+                hash: block.hash.clone(),
+                position,
+                tx_id: tx.tx_id.clone(),
+                reason,
+            }
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Validates every block of `chain` against the blocks stored before it at
+/// local time `now` and applies its transactions on top of the branch's
+/// state after the first block, collecting all failures instead of stopping
+/// at the first one. The first block of a split branch is a copy of its parent's tip and was
+/// validated on the parent branch, so checking starts at the second block.
+pub fn validate_chain<C: Consensus, S: Storage>(
+    branch_id: &str,
+    chain: &[Block],
+    consensus: &C,
+    coin: &Coin,
+    storage: &S,
+    claim_depth: usize,
+    now: i64,
+) -> Result<ValidationReport, BlocktreeError> {
+    let mut ledger = Ledger::branch_base(storage, branch_id)?;
+    let mut errors = Vec::new();
+    for i in 1..chain.len() {
+        let result = validate_block(&chain[i], branch_id, &chain[..i], consensus, coin, now)
+            .and_then(|()| validate_claims(&chain[i], branch_id, storage, claim_depth))
+            .and_then(|()| ledger.apply_block(&chain[i]));
+        match result {
+            Ok(()) => {}
+            Err(BlocktreeError::Validation(err)) => errors.push(err),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(ValidationReport {
+        branch_id: branch_id.to_string(),
+        blocks_checked: chain.len().saturating_sub(1),
+        errors,
+    })
+}
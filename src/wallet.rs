@@ -0,0 +1,68 @@
+use crate::error::BlocktreeError;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::rngs::OsRng;
+use sha3::{Digest, Sha3_256};
+
+/// Ed25519 keypair controlling a Blocktree address.
+#[derive(Clone)]
+pub struct Keypair {
+    signing_key: SigningKey,
+}
+
+impl Keypair {
+    pub fn generate() -> Self {
+        Keypair {
+            signing_key: SigningKey::generate(&mut OsRng),
+        }
+    }
+
+    /// Deterministic keypair, e.g. for tests and devnet genesis accounts.
+    pub fn from_seed(seed: &[u8; 32]) -> Self {
+        Keypair {
+            signing_key: SigningKey::from_bytes(seed),
+        }
+    }
+
+    pub fn public_key_hex(&self) -> String {
+        hex::encode(self.signing_key.verifying_key().to_bytes())
+    }
+
+    pub fn address(&self) -> String {
+        address_from_public_key(&self.signing_key.verifying_key().to_bytes())
+    }
+
+    pub fn sign(&self, message: &[u8]) -> String {
+        hex::encode(self.signing_key.sign(message).to_bytes())
+    }
+}
+
+/// Address of a public key: the last 20 bytes of its SHA3-256 hash, hex encoded.
+pub fn address_from_public_key(public_key: &[u8; 32]) -> String {
+    let digest = Sha3_256::digest(public_key);
+    hex::encode(&digest[12..])
+}
+
+/// Checks a hex-encoded signature over `message` by a hex-encoded public key.
+pub fn verify_signature(
+    public_key_hex: &str,
+    message: &[u8],
+    signature_hex: &str,
+) -> Result<bool, BlocktreeError> {
+    let public_key: [u8; 32] = decode_fixed(public_key_hex, "public key")?;
+    let signature: [u8; 64] = decode_fixed(signature_hex, "signature")?;
+    let verifying_key = VerifyingKey::from_bytes(&public_key)
+        .map_err(|e| BlocktreeError::TransactionError(e.to_string()))?;
+    Ok(verifying_key
+        .verify(message, &Signature::from_bytes(&signature))
+        .is_ok())
+}
+
+pub(crate) fn decode_fixed<const N: usize>(
+    hex_str: &str,
+    what: &str,
+) -> Result<[u8; N], BlocktreeError> {
+    hex::decode(hex_str)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| BlocktreeError::TransactionError(format!("Malformed {}", what)))
+}